@@ -1,6 +1,6 @@
 use crate::error::Result;
 use crate::utils::DT;
-use crate::{game_state, sprite, utils};
+use crate::{sprite, utils};
 use allegro::*;
 use na::{Point2, Point3, Vector2, Vector3};
 use nalgebra as na;
@@ -42,6 +42,8 @@ pub struct Velocity
 {
 	pub pos: Vector3<f32>,
 	pub ground_pos: Vector3<f32>,
+	// The platform being stood on, if any.
+	pub ground_id: Option<hecs::Entity>,
 }
 
 impl Velocity
@@ -51,6 +53,7 @@ impl Velocity
 		Self {
 			pos: pos,
 			ground_pos: Vector3::zeros(),
+			ground_id: None,
 		}
 	}
 }
@@ -170,6 +173,8 @@ pub enum CollisionKind
 	SmallPlayer,
 	World,
 	Platform,
+	// Only collides with the world and platforms.
+	Debris,
 }
 
 impl CollisionKind
@@ -184,6 +189,7 @@ impl CollisionKind
 			(CollisionKind::BigEnemy, CollisionKind::SmallPlayer) => true,
 			(CollisionKind::BigEnemy, CollisionKind::World) => true,
 			(CollisionKind::BigEnemy, CollisionKind::Platform) => true,
+			(CollisionKind::BigEnemy, CollisionKind::Debris) => false,
 
 			(CollisionKind::BigPlayer, CollisionKind::BigEnemy) => true,
 			(CollisionKind::BigPlayer, CollisionKind::BigPlayer) => true,
@@ -191,6 +197,7 @@ impl CollisionKind
 			(CollisionKind::BigPlayer, CollisionKind::SmallPlayer) => false,
 			(CollisionKind::BigPlayer, CollisionKind::World) => true,
 			(CollisionKind::BigPlayer, CollisionKind::Platform) => true,
+			(CollisionKind::BigPlayer, CollisionKind::Debris) => false,
 
 			(CollisionKind::SmallEnemy, CollisionKind::BigEnemy) => false,
 			(CollisionKind::SmallEnemy, CollisionKind::BigPlayer) => true,
@@ -198,6 +205,7 @@ impl CollisionKind
 			(CollisionKind::SmallEnemy, CollisionKind::SmallPlayer) => false,
 			(CollisionKind::SmallEnemy, CollisionKind::World) => true,
 			(CollisionKind::SmallEnemy, CollisionKind::Platform) => true,
+			(CollisionKind::SmallEnemy, CollisionKind::Debris) => false,
 
 			(CollisionKind::SmallPlayer, CollisionKind::BigEnemy) => true,
 			(CollisionKind::SmallPlayer, CollisionKind::BigPlayer) => false,
@@ -205,6 +213,7 @@ impl CollisionKind
 			(CollisionKind::SmallPlayer, CollisionKind::SmallPlayer) => false,
 			(CollisionKind::SmallPlayer, CollisionKind::World) => true,
 			(CollisionKind::SmallPlayer, CollisionKind::Platform) => true,
+			(CollisionKind::SmallPlayer, CollisionKind::Debris) => false,
 
 			(CollisionKind::World, CollisionKind::BigEnemy) => true,
 			(CollisionKind::World, CollisionKind::BigPlayer) => true,
@@ -212,6 +221,7 @@ impl CollisionKind
 			(CollisionKind::World, CollisionKind::SmallPlayer) => true,
 			(CollisionKind::World, CollisionKind::World) => false,
 			(CollisionKind::World, CollisionKind::Platform) => false,
+			(CollisionKind::World, CollisionKind::Debris) => true,
 
 			(CollisionKind::Platform, CollisionKind::BigEnemy) => true,
 			(CollisionKind::Platform, CollisionKind::BigPlayer) => true,
@@ -219,6 +229,11 @@ impl CollisionKind
 			(CollisionKind::Platform, CollisionKind::SmallPlayer) => true,
 			(CollisionKind::Platform, CollisionKind::World) => false,
 			(CollisionKind::Platform, CollisionKind::Platform) => false,
+			(CollisionKind::Platform, CollisionKind::Debris) => true,
+
+			(CollisionKind::Debris, CollisionKind::World) => true,
+			(CollisionKind::Debris, CollisionKind::Platform) => true,
+			(CollisionKind::Debris, _) => false,
 		}
 	}
 
@@ -235,6 +250,17 @@ impl CollisionKind
 			_ => false,
 		}
 	}
+
+	// SmallEnemy is only used by enemy projectiles, which are left to fly off the map and expire
+	// rather than slide along its edge.
+	pub fn stays_in_bounds(&self) -> bool
+	{
+		match self
+		{
+			CollisionKind::BigEnemy | CollisionKind::BigPlayer | CollisionKind::SmallPlayer => true,
+			_ => false,
+		}
+	}
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -252,6 +278,8 @@ pub enum AIState
 	Wander,
 	Chase(hecs::Entity),
 	Attack(hecs::Entity),
+	// Heading to where the target was last seen.
+	Search,
 }
 
 impl AIState
@@ -266,16 +294,100 @@ impl AIState
 	}
 }
 
-#[derive(Debug, Copy, Clone)]
+pub const LEVEL_SCALING_CAP: i32 = 50;
+
+// 10% compounding per level, continued linearly (with a matching slope) past LEVEL_SCALING_CAP so
+// endless runs don't overflow.
+pub fn level_scaling(level: i32) -> f32
+{
+	let level = utils::max(0, level);
+	let base = 1.1_f32;
+	if level <= LEVEL_SCALING_CAP
+	{
+		base.powf(level as f32)
+	}
+	else
+	{
+		let at_cap = base.powf(LEVEL_SCALING_CAP as f32);
+		at_cap * (1. + base.ln() * (level - LEVEL_SCALING_CAP) as f32)
+	}
+}
+
+pub fn enemy_item_level(level: i32) -> i32
+{
+	(-9. + 10. * level_scaling(level)) as i32
+}
+
+#[derive(Debug, Clone)]
 pub struct AI
 {
 	pub state: AIState,
 	pub next_state_time: f64,
 	pub target: Option<hecs::Entity>,
 	pub attack_range: f32,
+	// Targets are picked up inside this range, and dropped past 1.5 times it.
+	pub sense_range: f32,
+	// How far from the leash point the enemy wanders.
+	pub leash_range: f32,
 	pub leash: Point3<f32>,
+	pub pathing: bool,
+	// Tile coordinates, in reverse order.
+	pub path: Vec<Point2<i32>>,
+	pub path_goal: Point2<i32>,
+	pub next_path_time: f64,
+	pub combat_start: Option<f64>,
+	pub last_known_target_pos: Option<Point3<f32>>,
+	// Crystal whose garrison this enemy belongs to.
+	pub crystal: Option<hecs::Entity>,
+}
+
+pub const ENRAGE_DELAY: f64 = 10.;
+pub const ENRAGE_RATE: f32 = 0.1;
+pub const ENRAGE_MAX_MULTIPLIER: f32 = 3.;
+// The elemental aegis boosts one elemental resistance at a time, cycling through them.
+pub const AEGIS_PERIOD: f64 = 4.;
+// Time at the end of each period spent blending into the next element.
+pub const AEGIS_BLEND: f64 = 0.5;
+pub const AEGIS_RESISTANCE: f32 = 0.4;
+pub const AEGIS_ELEMENTS: [&str; 3] = ["Cold", "Fire", "Lightning"];
+// Overheal shield cap and decay per second, both as fractions of max life.
+pub const OVERHEAL_MAX: f32 = 0.5;
+pub const OVERHEAL_DECAY: f32 = 0.1;
+
+// Weights of the cold, fire and lightning aegis bonus at `time`, summing to 1.
+pub fn aegis_weights(time: f64) -> [f32; 3]
+{
+	let phase = utils::max(0., time) / AEGIS_PERIOD;
+	let cur = phase.floor() as usize % 3;
+	let next = (cur + 1) % 3;
+	let left = (1. - phase.fract()) * AEGIS_PERIOD;
+	let blend = if left < AEGIS_BLEND
+	{
+		1. - left / AEGIS_BLEND
+	}
+	else
+	{
+		0.
+	};
+	let blend = blend * blend * (3. - 2. * blend);
+	let mut weights = [0.; 3];
+	weights[cur] = (1. - blend) as f32;
+	weights[next] = blend as f32;
+	weights
+}
+
+// Index into AEGIS_ELEMENTS of the element currently getting most of the bonus.
+pub fn aegis_element(time: f64) -> usize
+{
+	let weights = aegis_weights(time);
+	(0..3)
+		.max_by(|&a, &b| weights[a].partial_cmp(&weights[b]).unwrap())
+		.unwrap()
 }
 
+// Base fraction of max life below which frozen enemies shatter when hit by the player.
+pub const FREEZE_SHATTER_THRESHOLD: f32 = 0.15;
+
 impl AI
 {
 	pub fn new_ranged(leash: Point3<f32>) -> Self
@@ -284,8 +396,17 @@ impl AI
 			state: AIState::Idle,
 			next_state_time: 0.,
 			attack_range: 96.,
+			sense_range: 160.,
+			leash_range: 64.,
 			target: None,
 			leash: leash,
+			pathing: false,
+			path: vec![],
+			path_goal: Point2::new(-1, -1),
+			next_path_time: 0.,
+			combat_start: None,
+			last_known_target_pos: None,
+			crystal: None,
 		}
 	}
 
@@ -295,8 +416,32 @@ impl AI
 			state: AIState::Idle,
 			next_state_time: 0.,
 			attack_range: 24.,
+			sense_range: 128.,
+			leash_range: 64.,
 			target: None,
 			leash: leash,
+			pathing: true,
+			path: vec![],
+			path_goal: Point2::new(-1, -1),
+			next_path_time: 0.,
+			combat_start: None,
+			last_known_target_pos: None,
+			crystal: None,
+		}
+	}
+
+	// Damage multiplier that ramps up the longer this enemy has been fighting, so an unkillable
+	// player can't stall forever.
+	pub fn enrage_multiplier(&self, time: f64) -> f32
+	{
+		match self.combat_start
+		{
+			Some(combat_start) =>
+			{
+				let t = utils::max(0., time - combat_start - ENRAGE_DELAY) as f32;
+				utils::min(1. + ENRAGE_RATE * t, ENRAGE_MAX_MULTIPLIER)
+			}
+			None => 1.,
 		}
 	}
 }
@@ -322,6 +467,47 @@ impl Team
 	}
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct StatusImmunities
+{
+	bits: u8,
+}
+
+impl StatusImmunities
+{
+	pub const FREEZE: StatusImmunities = StatusImmunities { bits: 1 << 0 };
+	pub const IGNITE: StatusImmunities = StatusImmunities { bits: 1 << 1 };
+	pub const SHOCK: StatusImmunities = StatusImmunities { bits: 1 << 2 };
+
+	pub fn empty() -> Self
+	{
+		Self { bits: 0 }
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.bits == 0
+	}
+
+	pub fn contains(&self, other: StatusImmunities) -> bool
+	{
+		self.bits & other.bits == other.bits
+	}
+
+	pub fn insert(&mut self, other: StatusImmunities)
+	{
+		self.bits |= other.bits;
+	}
+}
+
+impl std::ops::BitOrAssign for StatusImmunities
+{
+	fn bitor_assign(&mut self, other: StatusImmunities)
+	{
+		self.insert(other);
+	}
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct StatValues
 {
@@ -355,9 +541,17 @@ pub struct StatValues
 
 	pub life_leech: f32,
 	pub mana_leech: f32,
+	pub crit_leech: f32,
+	pub blade_speed: f32,
+	pub mana_cost_reduction: f32,
+	pub magic_find: f32,
 	pub chance_to_ignite: f32,
 	pub chance_to_freeze: f32,
 	pub chance_to_shock: f32,
+	pub increased_ailment_effect: f32,
+	pub increased_ailment_duration: f32,
+	// Frozen targets hit below this fraction of their max life are shattered.
+	pub shatter_threshold: f32,
 
 	pub freeze_propagate_value: f32,
 	pub ignite_propagate_value: EffectAndDuration,
@@ -365,6 +559,8 @@ pub struct StatValues
 
 	pub increased_physical_damage: f32,
 	pub multishot: bool,
+	pub projectile_pierce: i32,
+	pub projectile_bounce: i32,
 	pub explode_on_death: bool,
 	pub freeze_propagate: bool,
 	pub ignite_propagate: bool,
@@ -380,6 +576,16 @@ pub struct StatValues
 	pub half_mana: bool,
 	pub armor_to_phys: bool,
 	pub no_elemental_damage: bool,
+	pub stored_blades: bool,
+	pub elemental_aegis: bool,
+	pub second_chance: bool,
+	pub physical_to_cold: bool,
+	pub physical_to_fire: bool,
+	pub physical_to_lightning: bool,
+	pub overheal_shield: bool,
+	// Set by the no healing challenge, blocks leech and the overheal shield.
+	pub no_healing: bool,
+	pub status_immunities: StatusImmunities,
 
 	pub is_invincible: bool,
 }
@@ -419,9 +625,16 @@ impl Default for StatValues
 
 			life_leech: 0.,
 			mana_leech: 0.,
+			crit_leech: 0.,
+			blade_speed: 0.,
+			mana_cost_reduction: 0.,
+			magic_find: 0.,
 			chance_to_ignite: 0.,
 			chance_to_freeze: 0.,
 			chance_to_shock: 0.,
+			increased_ailment_effect: 0.,
+			increased_ailment_duration: 0.,
+			shatter_threshold: 0.,
 
 			freeze_propagate_value: 0.,
 			ignite_propagate_value: EffectAndDuration::new(),
@@ -429,6 +642,8 @@ impl Default for StatValues
 
 			increased_physical_damage: 0.,
 			multishot: false,
+			projectile_pierce: 0,
+			projectile_bounce: 0,
 			explode_on_death: false,
 			freeze_propagate: false,
 			ignite_propagate: false,
@@ -444,6 +659,15 @@ impl Default for StatValues
 			half_mana: false,
 			armor_to_phys: false,
 			no_elemental_damage: false,
+			stored_blades: false,
+			elemental_aegis: false,
+			second_chance: false,
+			physical_to_cold: false,
+			physical_to_fire: false,
+			physical_to_lightning: false,
+			overheal_shield: false,
+			no_healing: false,
+			status_immunities: StatusImmunities::empty(),
 
 			is_invincible: false,
 		}
@@ -452,15 +676,57 @@ impl Default for StatValues
 
 impl StatValues
 {
-	pub fn new_player() -> Self
+	pub fn aoe_radius_factor(&self) -> f32
+	{
+		if !self.area_of_effect.is_finite()
+		{
+			return 1.;
+		}
+		utils::clamp(
+			utils::max(0., self.area_of_effect).sqrt(),
+			0.,
+			MAX_AOE_RADIUS_FACTOR,
+		)
+	}
+
+	pub fn scale_damage(&mut self, f: f32)
+	{
+		self.physical_damage *= f;
+		self.cold_damage *= f;
+		self.fire_damage *= f;
+		self.lightning_damage *= f;
+	}
+
+	// Rough DPS of the blades against a target with 0% resistances. Assumes the player keeps a
+	// single blade up, recast at cast speed, and ignores armor, ailments and leech.
+	pub fn estimate_dps(&self) -> f32
+	{
+		let mut blade_blade = BladeBlade::new();
+		blade_blade.num_blades = 1;
+		let (interval, damage) = blade_blade.hit_interval_and_damage(self.blade_speed);
+		let hit_damage = damage
+			* (self.physical_damage + self.cold_damage + self.fire_damage + self.lightning_damage);
+		let crit_factor = 1.
+			+ utils::clamp(self.critical_chance, 0., 1.)
+				* utils::max(0., self.critical_multiplier - 1.);
+		hit_damage * crit_factor * self.cast_speed / interval
+	}
+
+	pub fn new_player(archetype: Archetype) -> Self
 	{
+		let (life_f, damage_f) = match archetype
+		{
+			Archetype::Wanderer => (1., 1.),
+			Archetype::Pyromancer => (0.8, 1.25),
+			Archetype::Guardian => (1.3, 0.8),
+		};
 		Self {
 			speed: 136.,
 			acceleration: 512.,
 			jump_strength: 128.,
 			team: Team::Player,
 
-			max_life: 100.,
+			max_life: 100. * life_f,
 			life_regen: 5.,
 			max_mana: 100.,
 			mana_regen: 5.,
@@ -471,7 +737,8 @@ impl StatValues
 
 			critical_chance: 0.05,
 			critical_multiplier: 2.,
-			physical_damage: 10.,
+			physical_damage: 10. * damage_f,
+			shatter_threshold: FREEZE_SHATTER_THRESHOLD,
 
 			//freeze_propagate: true,
 			//ignite_propagate: true,
@@ -490,7 +757,9 @@ impl StatValues
 		}
 	}
 
-	pub fn new_enemy(level: i32, rarity: Rarity, ranged: bool) -> Self
+	pub fn new_enemy(
+		level: i32, rarity: Rarity, ranged: bool, challenges: &[Challenge], map_mods: &[MapMod],
+	) -> Self
 	{
 		let mut f = match rarity
 		{
@@ -505,24 +774,45 @@ impl StatValues
 			f *= 1.5;
 		}
 
-		Self {
+		let mut values = Self {
 			speed: if ranged { 64. } else { 96. },
 			acceleration: if ranged { 512. } else { 1400. },
 			skill_duration: 1.,
-			max_life: (42. + 10. * level as f32) * 1.1_f32.powf(level as f32) * f,
+			max_life: (42. + 10. * level as f32) * level_scaling(level) * f,
 			mana_regen: 100.,
 			max_mana: 100.,
 			cast_speed: 1.,
 			critical_chance: 0.05,
 			critical_multiplier: 1.5,
 
-			physical_damage: (-7. + 0. * level as f32 + 10. * 1.1_f32.powf(level as f32)) * f,
+			physical_damage: (-7. + 0. * level as f32 + 10. * level_scaling(level)) * f,
 
 			//cold_damage: 5.,
 			//chance_to_freeze: 1.,
 			area_of_effect: 1.,
 			..Self::default()
+		};
+		if challenges.contains(&Challenge::FastEnemies)
+		{
+			values.speed *= 2.;
+			values.cast_speed *= 2.;
+		}
+		for map_mod in map_mods
+		{
+			match map_mod
+			{
+				MapMod::BlazingEnemies =>
+				{
+					values.fire_damage += 0.5 * utils::max(0., values.physical_damage);
+				}
+				MapMod::ToughEnemies =>
+				{
+					values.max_life *= 1.5;
+				}
+				MapMod::PoorRegen | MapMod::ExtraCrystal | MapMod::Bountiful => (),
+			}
 		}
+		values
 	}
 
 	pub fn new_fireball() -> Self
@@ -606,6 +896,8 @@ pub struct DamageReport
 	pub hit: bool,
 	pub life_leech: f32,
 	pub mana_leech: f32,
+	pub crit_life_leech: f32,
+	pub crit_mana_leech: f32,
 	pub explode_on_death: bool,
 	pub freeze_propagation: f32,
 	pub ignite_propagation: EffectAndDuration,
@@ -620,6 +912,8 @@ impl DamageReport
 			hit: false,
 			life_leech: 0.,
 			mana_leech: 0.,
+			crit_life_leech: 0.,
+			crit_mana_leech: 0.,
 			explode_on_death: false,
 			freeze_propagation: 0.,
 			ignite_propagation: EffectAndDuration::new(),
@@ -648,6 +942,10 @@ pub struct Stats
 	pub ignite_instances: Vec<RateInstance>,
 	pub shock_instances: Vec<RateInstance>,
 	pub freeze_time: f64,
+	pub last_damaged_time: f64,
+	pub invincible_until: f64,
+	// Banked from healing past max life, absorbs damage before life does.
+	pub shield: f32,
 }
 
 impl Stats
@@ -668,15 +966,46 @@ impl Stats
 			ignite_instances: vec![],
 			shock_instances: vec![],
 			freeze_time: 0.,
+			last_damaged_time: std::f64::NEG_INFINITY,
+			invincible_until: 0.,
 			exploded: false,
+			shield: 0.,
+		}
+	}
+
+	// Clamps life to max, banking the excess into the shield with the overheal shield.
+	pub fn clamp_life(&mut self)
+	{
+		if self.values.overheal_shield
+			&& !self.values.no_healing
+			&& self.life > self.values.max_life
+		{
+			self.shield = utils::min(
+				OVERHEAL_MAX * self.values.max_life,
+				self.shield + self.life - self.values.max_life,
+			);
 		}
+		self.life = utils::clamp(self.life, 0., self.values.max_life);
 	}
 
-	pub fn reset(&mut self, time: f64, penalty_level: i32, inventory: Option<&Inventory>)
+	// Life regen pauses for `delay` seconds after taking a hit. Leech is unaffected.
+	pub fn regen_suppressed(&self, time: f64, delay: f32) -> bool
+	{
+		delay > 0. && time - self.last_damaged_time < delay as f64
+	}
+
+	pub fn reset(
+		&mut self, time: f64, penalty_level: i32, inventory: Option<&Inventory>,
+		challenges: &[Challenge], map_mods: &[MapMod],
+	)
 	{
 		let penalty = (penalty_level / 5) as f32;
 
 		self.values = self.base_values;
+		if time < self.invincible_until
+		{
+			self.values.is_invincible = true;
+		}
 		if let Some(inventory) = inventory
 		{
 			let mut adds = StatValues::default();
@@ -696,6 +1025,8 @@ impl Stats
 				}
 			}
 			self.values.multishot |= adds.multishot;
+			self.values.projectile_pierce += adds.projectile_pierce;
+			self.values.projectile_bounce += adds.projectile_bounce;
 			self.values.explode_on_death |= adds.explode_on_death;
 			self.values.shock_propagate |= adds.shock_propagate;
 			self.values.ignite_propagate |= adds.ignite_propagate;
@@ -710,6 +1041,14 @@ impl Stats
 			self.values.half_life |= adds.half_life;
 			self.values.armor_to_phys |= adds.armor_to_phys;
 			self.values.no_elemental_damage |= adds.no_elemental_damage;
+			self.values.stored_blades |= adds.stored_blades;
+			self.values.elemental_aegis |= adds.elemental_aegis;
+			self.values.second_chance |= adds.second_chance;
+			self.values.physical_to_cold |= adds.physical_to_cold;
+			self.values.physical_to_fire |= adds.physical_to_fire;
+			self.values.physical_to_lightning |= adds.physical_to_lightning;
+			self.values.overheal_shield |= adds.overheal_shield;
+			self.values.status_immunities |= adds.status_immunities;
 
 			self.values.speed = (self.base_values.speed + adds.speed) * (1. + increases.speed);
 			self.values.acceleration =
@@ -761,6 +1100,28 @@ impl Stats
 				+ adds.lightning_damage)
 				* (1. + increases.lightning_damage);
 
+			// Physical damage is split evenly between all the converted elements.
+			let num_conversions = self.values.physical_to_cold as i32
+				+ self.values.physical_to_fire as i32
+				+ self.values.physical_to_lightning as i32;
+			if num_conversions > 0
+			{
+				let converted = self.values.physical_damage / num_conversions as f32;
+				if self.values.physical_to_cold
+				{
+					self.values.cold_damage += converted;
+				}
+				if self.values.physical_to_fire
+				{
+					self.values.fire_damage += converted;
+				}
+				if self.values.physical_to_lightning
+				{
+					self.values.lightning_damage += converted;
+				}
+				self.values.physical_damage = 0.;
+			}
+
 			self.values.physical_resistance = (self.base_values.physical_resistance
 				+ adds.physical_resistance)
 				* (1. + increases.physical_resistance);
@@ -776,6 +1137,15 @@ impl Stats
 				(self.base_values.life_leech + adds.life_leech) * (1. + increases.life_leech);
 			self.values.mana_leech =
 				(self.base_values.mana_leech + adds.mana_leech) * (1. + increases.mana_leech);
+			self.values.crit_leech =
+				(self.base_values.crit_leech + adds.crit_leech) * (1. + increases.crit_leech);
+			self.values.blade_speed =
+				(self.base_values.blade_speed + adds.blade_speed) * (1. + increases.blade_speed);
+			self.values.mana_cost_reduction = (self.base_values.mana_cost_reduction
+				+ adds.mana_cost_reduction)
+				* (1. + increases.mana_cost_reduction);
+			self.values.magic_find =
+				(self.base_values.magic_find + adds.magic_find) * (1. + increases.magic_find);
 			self.values.chance_to_ignite = (self.base_values.chance_to_ignite
 				+ adds.chance_to_ignite)
 				* (1. + increases.chance_to_ignite);
@@ -784,6 +1154,11 @@ impl Stats
 				* (1. + increases.chance_to_freeze);
 			self.values.chance_to_shock = (self.base_values.chance_to_shock + adds.chance_to_shock)
 				* (1. + increases.chance_to_shock);
+			self.values.increased_ailment_effect = increases.increased_ailment_effect;
+			self.values.increased_ailment_duration = increases.increased_ailment_duration;
+			self.values.shatter_threshold = (self.base_values.shatter_threshold
+				+ adds.shatter_threshold)
+				* (1. + increases.shatter_threshold);
 
 			if self.values.no_cold_damage
 			{
@@ -804,6 +1179,14 @@ impl Stats
 
 			self.values.critical_chance = utils::min(1., self.values.critical_chance);
 
+			if self.values.elemental_aegis
+			{
+				let weights = aegis_weights(time);
+				self.values.cold_resistance += AEGIS_RESISTANCE * weights[0];
+				self.values.fire_resistance += AEGIS_RESISTANCE * weights[1];
+				self.values.lightning_resistance += AEGIS_RESISTANCE * weights[2];
+			}
+
 			self.values.physical_resistance = utils::min(0.9, self.values.physical_resistance);
 			self.values.cold_resistance =
 				utils::clamp(self.values.cold_resistance - penalty * 0.3, -1., 0.75);
@@ -828,9 +1211,37 @@ impl Stats
 				self.values.max_mana *= 0.5;
 			}
 
+			for challenge in challenges
+			{
+				match challenge
+				{
+					Challenge::NoHealing =>
+					{
+						self.values.life_regen = utils::min(0., self.values.life_regen);
+						self.values.life_leech = 0.;
+						self.values.no_healing = true;
+					}
+					Challenge::HalfLife =>
+					{
+						self.values.max_life *= 0.5;
+					}
+					Challenge::FastEnemies => (),
+					Challenge::Enrage => (),
+				}
+			}
+
+			if map_mods.contains(&MapMod::PoorRegen)
+			{
+				self.values.life_regen *= 0.5;
+				self.values.mana_regen *= 0.5;
+			}
+
 			self.values.chance_to_shock = utils::min(1., self.values.chance_to_shock);
 			self.values.chance_to_ignite = utils::min(1., self.values.chance_to_ignite);
 			self.values.chance_to_freeze = utils::min(1., self.values.chance_to_freeze);
+			self.values.shatter_threshold = utils::min(0.5, self.values.shatter_threshold);
+			// Skills should always cost something.
+			self.values.mana_cost_reduction = utils::min(0.75, self.values.mana_cost_reduction);
 
 			self.life *= self.values.max_life / self.old_max_life;
 			self.life = utils::min(self.values.max_life, self.life);
@@ -860,7 +1271,7 @@ impl Stats
 	}
 
 	pub fn apply_damage(
-		&mut self, values: &StatValues, state: &mut game_state::GameState, rng: &mut impl Rng,
+		&mut self, values: &StatValues, time: f64, rng: &mut impl Rng,
 	) -> DamageReport
 	{
 		let can_dodge = self.values.armor == 0. && self.values.naked_dodge;
@@ -895,7 +1306,7 @@ impl Stats
 			damage_mult
 		};
 
-		let old_frozen = state.time() < self.freeze_time;
+		let old_frozen = time < self.freeze_time;
 		let mut freeze_duration = values.freeze_propagate_value;
 		if values.cold_damage > 0. && (crit || rng.gen_bool(values.chance_to_freeze as f64))
 		{
@@ -904,11 +1315,17 @@ impl Stats
 				* values.skill_duration
 				* values.cold_damage
 				* (1. - self.values.cold_resistance)
-				/ self.values.max_life;
+				/ self.values.max_life
+				* (1. + values.increased_ailment_duration);
 		}
-		if freeze_duration > 0.1 && !self.values.cannot_be_frozen
+		if freeze_duration > 0.1
+			&& !self.values.cannot_be_frozen
+			&& !self
+				.values
+				.status_immunities
+				.contains(StatusImmunities::FREEZE)
 		{
-			self.freeze_time = state.time() + freeze_duration as f64;
+			self.freeze_time = time + freeze_duration as f64;
 		}
 		else
 		{
@@ -920,15 +1337,24 @@ impl Stats
 		if values.fire_damage > 0. && (crit || rng.gen_bool(values.chance_to_ignite as f64))
 		{
 			ignite = EffectAndDuration {
-				effect: damage_mult * values.fire_damage * DT,
-				duration: values.skill_duration * 2.,
+				effect: damage_mult
+					* values.fire_damage
+					* DT * (1. + values.increased_ailment_effect),
+				duration: values.skill_duration * 2. * (1. + values.increased_ailment_duration),
 			};
 		}
+		if self
+			.values
+			.status_immunities
+			.contains(StatusImmunities::IGNITE)
+		{
+			ignite = EffectAndDuration::new();
+		}
 		if ignite.active()
 		{
 			self.ignite_instances.push(RateInstance {
 				rate: ignite.effect,
-				time_to_remove: state.time() + ignite.duration as f64,
+				time_to_remove: time + ignite.duration as f64,
 			});
 		}
 
@@ -937,15 +1363,24 @@ impl Stats
 		if values.lightning_damage > 0. && (crit || rng.gen_bool(values.chance_to_shock as f64))
 		{
 			shock = EffectAndDuration {
-				effect: damage_mult * values.lightning_damage,
-				duration: values.skill_duration * 2.,
+				effect: damage_mult
+					* values.lightning_damage
+					* (1. + values.increased_ailment_effect),
+				duration: values.skill_duration * 2. * (1. + values.increased_ailment_duration),
 			};
 		}
+		if self
+			.values
+			.status_immunities
+			.contains(StatusImmunities::SHOCK)
+		{
+			shock = EffectAndDuration::new();
+		}
 		if shock.active()
 		{
 			self.shock_instances.push(RateInstance {
 				rate: shock.effect,
-				time_to_remove: state.time() + shock.duration as f64,
+				time_to_remove: time + shock.duration as f64,
 			})
 		}
 
@@ -966,7 +1401,36 @@ impl Stats
 		let final_damage = damage_mult * damage;
 		let life_leech = final_damage * values.life_leech;
 		let mana_leech = final_damage * values.mana_leech;
-		self.life = utils::max(0., self.life - final_damage);
+		let crit_leech = if crit
+		{
+			final_damage * values.crit_leech
+		}
+		else
+		{
+			0.
+		};
+		// With crit leech, a crit's mana leech is granted at once instead of over time.
+		let (mana_leech, crit_mana_leech) = if crit && values.crit_leech > 0.
+		{
+			(0., mana_leech)
+		}
+		else
+		{
+			(mana_leech, 0.)
+		};
+		let absorbed = utils::min(self.shield, final_damage);
+		self.shield -= absorbed;
+		self.life = utils::max(0., self.life - (final_damage - absorbed));
+		if final_damage > 0.
+		{
+			self.last_damaged_time = time;
+		}
+		if self.life > 0.
+			&& time < self.freeze_time
+			&& self.life < values.shatter_threshold * self.values.max_life
+		{
+			self.life = 0.;
+		}
 		let explode_on_death = if self.life == 0. && values.explode_on_death
 		{
 			self.exploded = true;
@@ -1005,6 +1469,8 @@ impl Stats
 			hit: true,
 			life_leech: life_leech,
 			mana_leech: mana_leech,
+			crit_life_leech: if values.no_healing { 0. } else { crit_leech },
+			crit_mana_leech: crit_mana_leech,
 			explode_on_death: explode_on_death,
 			freeze_propagation: freeze_propagation,
 			ignite_propagation: ignite_propagation,
@@ -1012,16 +1478,16 @@ impl Stats
 		}
 	}
 
-	pub fn logic(&mut self, state: &mut game_state::GameState)
+	pub fn logic(&mut self, time: f64, regen_delay: f32)
 	{
 		self.life_leech_instances
-			.retain_mut(|li| li.time_to_remove > state.time());
+			.retain_mut(|li| li.time_to_remove > time);
 		self.mana_leech_instances
-			.retain_mut(|li| li.time_to_remove > state.time());
+			.retain_mut(|li| li.time_to_remove > time);
 		self.ignite_instances
-			.retain_mut(|li| li.time_to_remove > state.time());
+			.retain_mut(|li| li.time_to_remove > time);
 		self.shock_instances
-			.retain_mut(|li| li.time_to_remove > state.time());
+			.retain_mut(|li| li.time_to_remove > time);
 
 		let life_leech = self
 			.life_leech_instances
@@ -1045,7 +1511,8 @@ impl Stats
 		self.life += life_leech - ignite_damage * (1. - self.values.fire_resistance);
 		self.mana += mana_leech;
 
-		if self.life >= self.values.max_life
+		// With the overheal shield, leech keeps flowing into the shield.
+		if self.life >= self.values.max_life && !self.values.overheal_shield
 		{
 			self.life_leech_instances.clear();
 		}
@@ -1053,9 +1520,20 @@ impl Stats
 		{
 			self.mana_leech_instances.clear();
 		}
-		self.life += self.values.life_regen * DT;
+		if !self.regen_suppressed(time, regen_delay)
+		{
+			self.life += self.values.life_regen * DT;
+		}
 		self.mana += self.values.mana_regen * DT;
-		self.life = utils::clamp(self.life, 0., self.values.max_life);
+		if self.values.overheal_shield
+		{
+			self.shield = utils::max(0., self.shield - OVERHEAL_DECAY * self.values.max_life * DT);
+		}
+		else
+		{
+			self.shield = 0.;
+		}
+		self.clamp_life();
 		self.mana = utils::clamp(self.mana, 0., self.values.max_mana);
 	}
 }
@@ -1066,15 +1544,24 @@ pub enum AttackKind
 	BladeBlade,
 	Slam,
 	Fireball(Rarity),
+	// Winds up before firing a bigger, faster fireball.
+	ChargedFireball(Rarity),
 	Explode,
 }
 
+// Seconds of windup at normal cast speed. Being frozen stops and resets the windup.
+pub const CHARGE_TIME: f32 = 1.5;
+// Charge past CHARGE_TIME keeps scaling the fireball up to this factor.
+pub const CHARGE_MAX_FACTOR: f32 = 1.5;
+pub const CHARGE_DAMAGE_MULTIPLIER: f32 = 2.;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Attack
 {
 	pub want_attack: bool,
 	pub target_position: Point3<f32>,
 	pub kind: AttackKind,
+	pub charge: f32,
 }
 
 impl Attack
@@ -1085,6 +1572,7 @@ impl Attack
 			want_attack: false,
 			target_position: Point3::new(0., 0., 0.),
 			kind: kind,
+			charge: 0.,
 		}
 	}
 }
@@ -1103,11 +1591,45 @@ impl TimeToDie
 	}
 }
 
+#[derive(Debug, Clone)]
+pub struct Pierce
+{
+	pub remaining: i32,
+	pub hit_ids: Vec<hecs::Entity>,
+}
+
+impl Pierce
+{
+	pub fn new(remaining: i32) -> Self
+	{
+		Self {
+			remaining: remaining,
+			hit_ids: vec![],
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Bounce
+{
+	pub remaining: i32,
+	// A bounce can leave the projectile overlapping its target for a few ticks.
+	pub last_hit: Option<hecs::Entity>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LastSafePosition
+{
+	pub pos: Point2<f32>,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct PlaceToDie
 {
 	pub target: Point3<f32>,
 	pub old_dist: f32,
+	// If set, the target is updated to this entity's position.
+	pub follow: Option<hecs::Entity>,
 }
 
 impl PlaceToDie
@@ -1117,6 +1639,16 @@ impl PlaceToDie
 		Self {
 			target: target,
 			old_dist: std::f32::INFINITY,
+			follow: None,
+		}
+	}
+
+	pub fn new_follow(target: Point3<f32>, follow: hecs::Entity) -> Self
+	{
+		Self {
+			target: target,
+			old_dist: std::f32::INFINITY,
+			follow: Some(follow),
 		}
 	}
 }
@@ -1128,11 +1660,14 @@ pub enum Effect
 	SpawnExplosion(String, Color, &'static str),
 	DoDamage(StatValues, Team),
 	SpawnCorpse(&'static str),
-	SpawnSoul(hecs::Entity),
+	// Crystal the soul goes to, if any.
+	SpawnSoul(Option<hecs::Entity>),
 	UnlockCrystal(hecs::Entity),
+	CollectSoul,
 	SpawnPowerSphere(ItemKind),
 	ElevateCrystal(hecs::Entity),
 	SpawnItems(ItemKind),
+	BossDeath,
 }
 
 #[derive(Debug, Clone)]
@@ -1177,14 +1712,54 @@ impl Jump
 
 pub struct DieOnActivation;
 
+pub struct CurseGround;
+
+#[derive(Debug, Copy, Clone)]
+pub struct SpawnDelay
+{
+	pub active_at: f64,
+}
+
+pub const GROUND_AOE_DURATION: f32 = 3.;
+pub const GROUND_AOE_INTERVAL: f32 = 0.5;
+pub const GROUND_AOE_RADIUS: f32 = 24.;
+pub const GROUND_AOE_DAMAGE: f32 = 0.2;
+
+#[derive(Debug, Copy, Clone)]
+pub struct GroundAoe
+{
+	pub radius: f32,
+	pub interval: f32,
+	pub time_to_tick: f64,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct BladeBlade
 {
 	pub num_blades: i32,
 	pub time_to_remove: f64,
 	pub time_to_hit: f64,
+	pub last_sound_time: f64,
 }
 
+pub const BLADE_BLADE_CADENCE: f32 = 0.5;
+pub const BLADE_BLADE_RADIUS: f32 = 32.;
+pub const BLADE_BLADE_DAMAGE_PER_BLADE: f32 = 0.05;
+pub const BLADE_BLADE_MIN_HIT_INTERVAL: f32 = 0.1;
+// A single blade hits 1 / BLADE_BLADE_CADENCE = 2 times per second, and takes about a second to
+// cast, so a stored blade is worth roughly two seconds of sustained hits.
+pub const BLADE_BLADE_RELEASE_DAMAGE_PER_BLADE: f32 = 4.;
+pub const BLADE_BLADE_RELEASE_RADIUS: f32 = 2. * BLADE_BLADE_RADIUS;
+// Corpses within this range of the player get detonated.
+pub const CORPSE_EXPLOSION_RANGE: f32 = 96.;
+pub const CORPSE_EXPLOSION_RADIUS: f32 = 32.;
+// Fraction of the corpse's max life dealt as physical damage.
+pub const CORPSE_EXPLOSION_DAMAGE: f32 = 0.2;
+// Caps how far area of effect can grow blade and slam radii, so a stacked build can't turn every
+// hit into a query over the whole map.
+pub const MAX_AOE_RADIUS_FACTOR: f32 = 3.;
+pub const MAX_SLAM_RADIUS: f32 = 32. * MAX_AOE_RADIUS_FACTOR;
+
 impl BladeBlade
 {
 	pub fn new() -> Self
@@ -1193,6 +1768,26 @@ impl BladeBlade
 			num_blades: 0,
 			time_to_remove: 0.,
 			time_to_hit: 0.,
+			last_sound_time: std::f64::NEG_INFINITY,
+		}
+	}
+
+	pub fn hit_interval_and_damage(&self, blade_speed: f32) -> (f32, f32)
+	{
+		let num_blades = utils::max(1, self.num_blades) as f32;
+		let damage = 1. + BLADE_BLADE_DAMAGE_PER_BLADE * (num_blades - 1.);
+		let interval = BLADE_BLADE_CADENCE / (num_blades * utils::max(0.1, 1. + blade_speed));
+		// Fold fast cadences into fewer, stronger hits so the hit count doesn't depend on DT.
+		if interval < BLADE_BLADE_MIN_HIT_INTERVAL
+		{
+			(
+				BLADE_BLADE_MIN_HIT_INTERVAL,
+				damage * BLADE_BLADE_MIN_HIT_INTERVAL / interval,
+			)
+		}
+		else
+		{
+			(interval, damage)
 		}
 	}
 }
@@ -1200,10 +1795,17 @@ impl BladeBlade
 #[derive(Debug, Copy, Clone)]
 pub struct CastsShadow;
 
+#[derive(Debug, Copy, Clone)]
+pub struct EnemyProjectile;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Controller
 {
 	pub want_attack: bool,
+	// Set for one tick when want_attack goes from true to false.
+	pub attack_released: bool,
+	// When the attack was last pressed, consumed once it starts an attack.
+	pub attack_press_time: Option<f64>,
 	pub want_jump: bool,
 	pub want_move: Vector2<f32>,
 	pub target_position: Point3<f32>,
@@ -1215,6 +1817,8 @@ impl Controller
 	{
 		Self {
 			want_attack: false,
+			attack_released: false,
+			attack_press_time: None,
 			want_jump: false,
 			want_move: Vector2::zeros(),
 			target_position: Point3::new(0., 0., 0.),
@@ -1225,7 +1829,7 @@ impl Controller
 #[derive(Debug, Copy, Clone)]
 pub struct Corpse;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(i32)]
 pub enum ItemKind
 {
@@ -1245,6 +1849,111 @@ impl ItemKind
 			ItemKind::Blue => "Sapphire Ring",
 		}
 	}
+
+	pub fn gem_str(&self) -> &'static str
+	{
+		match self
+		{
+			ItemKind::Red => "Ruby",
+			ItemKind::Green => "Emerald",
+			ItemKind::Blue => "Sapphire",
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ItemCategory
+{
+	Ring,
+	Amulet,
+	Belt,
+}
+
+impl Default for ItemCategory
+{
+	fn default() -> Self
+	{
+		ItemCategory::Ring
+	}
+}
+
+impl ItemCategory
+{
+	pub fn to_str(&self) -> &'static str
+	{
+		match self
+		{
+			ItemCategory::Ring => "Ring",
+			ItemCategory::Amulet => "Amulet",
+			ItemCategory::Belt => "Belt",
+		}
+	}
+
+	// The category an inventory slot requires. Storage slots take anything.
+	pub fn for_slot(slot: usize) -> Option<ItemCategory>
+	{
+		match slot
+		{
+			0 => Some(ItemCategory::Amulet),
+			3 => Some(ItemCategory::Belt),
+			1 | 2 | 4 | 5 => Some(ItemCategory::Ring),
+			_ => None,
+		}
+	}
+
+	pub fn appearance(&self, kind: ItemKind) -> &'static str
+	{
+		match (self, kind)
+		{
+			(ItemCategory::Ring, ItemKind::Red) => "data/ring_red.cfg",
+			(ItemCategory::Ring, ItemKind::Green) => "data/ring_yellow.cfg",
+			(ItemCategory::Ring, ItemKind::Blue) => "data/ring_blue.cfg",
+			(ItemCategory::Amulet, ItemKind::Red) => "data/amulet_red.cfg",
+			(ItemCategory::Amulet, ItemKind::Green) => "data/amulet_yellow.cfg",
+			(ItemCategory::Amulet, ItemKind::Blue) => "data/amulet_blue.cfg",
+			(ItemCategory::Belt, ItemKind::Red) => "data/belt_red.cfg",
+			(ItemCategory::Belt, ItemKind::Green) => "data/belt_yellow.cfg",
+			(ItemCategory::Belt, ItemKind::Blue) => "data/belt_blue.cfg",
+		}
+	}
+
+	// Amulets lean towards casting and crits, belts towards life and resistances.
+	fn prefix_weight_multiplier(&self, prefix: ItemPrefix) -> i32
+	{
+		match (self, prefix)
+		{
+			(
+				ItemCategory::Amulet,
+				ItemPrefix::Mana
+				| ItemPrefix::ManaRegen
+				| ItemPrefix::CastSpeed
+				| ItemPrefix::CriticalChance
+				| ItemPrefix::ReducedManaCost,
+			) => 3,
+			(ItemCategory::Belt, ItemPrefix::Life | ItemPrefix::LifeRegen) => 3,
+			_ => 1,
+		}
+	}
+
+	fn suffix_weight_multiplier(&self, suffix: ItemSuffix) -> i32
+	{
+		match (self, suffix)
+		{
+			(
+				ItemCategory::Amulet,
+				ItemSuffix::CriticalMultiplier | ItemSuffix::Duration | ItemSuffix::MagicFind,
+			) => 3,
+			(
+				ItemCategory::Belt,
+				ItemSuffix::Armour
+				| ItemSuffix::PhysicalResistance
+				| ItemSuffix::ColdResistance
+				| ItemSuffix::FireResistance
+				| ItemSuffix::LightningResistance,
+			) => 3,
+			_ => 1,
+		}
+	}
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -1253,6 +1962,9 @@ pub struct Crystal
 	pub kind: ItemKind,
 	pub level: i32,
 	pub enemies: i32,
+	pub first_spawn_time: Option<f64>,
+	// When the garrison was first aggroed or damaged, for the quick clear bonus.
+	pub engage_time: Option<f64>,
 }
 
 impl Crystal
@@ -1263,6 +1975,8 @@ impl Crystal
 			kind: kind,
 			level: 0,
 			enemies: 0,
+			first_spawn_time: None,
+			engage_time: None,
 		}
 	}
 }
@@ -1284,8 +1998,11 @@ pub enum ItemPrefix
 	ManaRegen,
 	AreaOfEffect,
 	CastSpeed,
+	ReducedManaCost,
 	MoveSpeed,
 	MultiShot,
+	ProjectilePierce,
+	ProjectileBounce,
 	ExplodeOnDeath,
 	FreezePropagate,
 	IgnitePropagate,
@@ -1300,6 +2017,13 @@ pub enum ItemPrefix
 	HalfMana,
 	ArmourToPhys,
 	NoElementalDamage,
+	StoredBlades,
+	ElementalAegis,
+	SecondChance,
+	PhysicalToCold,
+	PhysicalToFire,
+	PhysicalToLightning,
+	OverhealShield,
 }
 
 impl ItemPrefix
@@ -1322,8 +2046,11 @@ impl ItemPrefix
 			ItemPrefix::ManaRegen => "Meditating",
 			ItemPrefix::AreaOfEffect => "Engorged",
 			ItemPrefix::CastSpeed => "Animated",
+			ItemPrefix::ReducedManaCost => "Frugal",
 			ItemPrefix::MoveSpeed => "Fast",
 			ItemPrefix::MultiShot => "MultiShot",
+			ItemPrefix::ProjectilePierce => "Piercing",
+			ItemPrefix::ProjectileBounce => "Ricocheting",
 			ItemPrefix::ExplodeOnDeath => "ExplodeOnDeath",
 			ItemPrefix::FreezePropagate => "FreezePropagate",
 			ItemPrefix::IgnitePropagate => "IgnitePropagate",
@@ -1338,6 +2065,13 @@ impl ItemPrefix
 			ItemPrefix::HalfMana => "HalfMana",
 			ItemPrefix::ArmourToPhys => "ArmourToPhys",
 			ItemPrefix::NoElementalDamage => "NoElementalDamage",
+			ItemPrefix::StoredBlades => "StoredBlades",
+			ItemPrefix::ElementalAegis => "ElementalAegis",
+			ItemPrefix::SecondChance => "SecondChance",
+			ItemPrefix::PhysicalToCold => "PhysicalToCold",
+			ItemPrefix::PhysicalToFire => "PhysicalToFire",
+			ItemPrefix::PhysicalToLightning => "PhysicalToLightning",
+			ItemPrefix::OverhealShield => "OverhealShield",
 		}
 	}
 
@@ -1360,8 +2094,11 @@ impl ItemPrefix
 			ItemPrefix::ManaRegen => (2., 1.),
 			ItemPrefix::AreaOfEffect => (0.05, 0.01),
 			ItemPrefix::CastSpeed => (0.05, 0.01),
+			ItemPrefix::ReducedManaCost => (0.01, 0.01),
 			ItemPrefix::MoveSpeed => (0.01, 0.01),
 			ItemPrefix::MultiShot => (0.1, 0.01),
+			ItemPrefix::ProjectilePierce => (1., 1.),
+			ItemPrefix::ProjectileBounce => (1., 1.),
 			ItemPrefix::ExplodeOnDeath => (0.1, 0.01),
 			ItemPrefix::FreezePropagate => (0.1, 0.01),
 			ItemPrefix::IgnitePropagate => (0.1, 0.01),
@@ -1376,6 +2113,13 @@ impl ItemPrefix
 			ItemPrefix::HalfMana => (0.1, 0.01),
 			ItemPrefix::ArmourToPhys => (0.1, 0.01),
 			ItemPrefix::NoElementalDamage => (0.1, 0.01),
+			ItemPrefix::StoredBlades => (0.1, 0.01),
+			ItemPrefix::ElementalAegis => (0.1, 0.01),
+			ItemPrefix::SecondChance => (0.1, 0.01),
+			ItemPrefix::PhysicalToCold => (0.1, 0.01),
+			ItemPrefix::PhysicalToFire => (0.1, 0.01),
+			ItemPrefix::PhysicalToLightning => (0.1, 0.01),
+			ItemPrefix::OverhealShield => (0.1, 0.01),
 		};
 		let start = delta * tier;
 		let end = delta * (tier + 1.);
@@ -1417,8 +2161,11 @@ impl ItemPrefix
 			ItemPrefix::ManaRegen => "Mana Regen",
 			ItemPrefix::AreaOfEffect => "Area of Effect",
 			ItemPrefix::CastSpeed => "Cast Speed",
+			ItemPrefix::ReducedManaCost => "Reduced Mana Cost",
 			ItemPrefix::MoveSpeed => "Move Speed",
 			ItemPrefix::MultiShot => "Multiple Shots",
+			ItemPrefix::ProjectilePierce => "Projectiles Pierce",
+			ItemPrefix::ProjectileBounce => "Projectiles Bounce",
 			ItemPrefix::ExplodeOnDeath => "Enemies Explode",
 			ItemPrefix::FreezePropagate => "Freezes Spread",
 			ItemPrefix::IgnitePropagate => "Ignites Spread",
@@ -1433,6 +2180,13 @@ impl ItemPrefix
 			ItemPrefix::HalfMana => "50% Less Mana",
 			ItemPrefix::ArmourToPhys => "Armoured Blades",
 			ItemPrefix::NoElementalDamage => "Elemental Damage is 0",
+			ItemPrefix::StoredBlades => "Blades Release on Key Up",
+			ItemPrefix::ElementalAegis => "Rotating Elemental Resist",
+			ItemPrefix::SecondChance => "Survive a Lethal Hit Once",
+			ItemPrefix::PhysicalToCold => "Physical Damage Becomes Cold",
+			ItemPrefix::PhysicalToFire => "Physical Damage Becomes Fire",
+			ItemPrefix::PhysicalToLightning => "Physical Damage Becomes Lightning",
+			ItemPrefix::OverhealShield => "Excess Healing Becomes a Shield",
 		};
 		let unique = match self
 		{
@@ -1446,6 +2200,13 @@ impl ItemPrefix
 			| ItemPrefix::HalfMana
 			| ItemPrefix::ArmourToPhys
 			| ItemPrefix::NoElementalDamage
+			| ItemPrefix::StoredBlades
+			| ItemPrefix::ElementalAegis
+			| ItemPrefix::SecondChance
+			| ItemPrefix::PhysicalToCold
+			| ItemPrefix::PhysicalToFire
+			| ItemPrefix::PhysicalToLightning
+			| ItemPrefix::OverhealShield
 			| ItemPrefix::ExplodeOnDeath
 			| ItemPrefix::FreezePropagate
 			| ItemPrefix::ShockPropagate
@@ -1525,6 +2286,10 @@ impl ItemPrefix
 			{
 				increases.cast_speed += value;
 			}
+			ItemPrefix::ReducedManaCost =>
+			{
+				adds.mana_cost_reduction += value;
+			}
 			ItemPrefix::MoveSpeed =>
 			{
 				increases.speed += value;
@@ -1533,6 +2298,14 @@ impl ItemPrefix
 			{
 				adds.multishot = true;
 			}
+			ItemPrefix::ProjectilePierce =>
+			{
+				adds.projectile_pierce += value as i32;
+			}
+			ItemPrefix::ProjectileBounce =>
+			{
+				adds.projectile_bounce += value as i32;
+			}
 			ItemPrefix::ExplodeOnDeath =>
 			{
 				adds.explode_on_death = true;
@@ -1589,7 +2362,35 @@ impl ItemPrefix
 			{
 				adds.no_elemental_damage = true;
 			}
-		}
+			ItemPrefix::StoredBlades =>
+			{
+				adds.stored_blades = true;
+			}
+			ItemPrefix::ElementalAegis =>
+			{
+				adds.elemental_aegis = true;
+			}
+			ItemPrefix::SecondChance =>
+			{
+				adds.second_chance = true;
+			}
+			ItemPrefix::PhysicalToCold =>
+			{
+				adds.physical_to_cold = true;
+			}
+			ItemPrefix::PhysicalToFire =>
+			{
+				adds.physical_to_fire = true;
+			}
+			ItemPrefix::PhysicalToLightning =>
+			{
+				adds.physical_to_lightning = true;
+			}
+			ItemPrefix::OverhealShield =>
+			{
+				adds.overheal_shield = true;
+			}
+		}
 	}
 }
 
@@ -1610,6 +2411,12 @@ pub enum ItemSuffix
 	LifeLeech = 10,
 	ManaLeech = 11,
 	Duration = 12,
+	CritLeech = 13,
+	BladeSpeed = 14,
+	MagicFind = 15,
+	AilmentEffect = 16,
+	AilmentDuration = 17,
+	ShatterThreshold = 18,
 }
 
 impl ItemSuffix
@@ -1631,6 +2438,12 @@ impl ItemSuffix
 			ItemSuffix::LifeLeech => "of the Vampire",
 			ItemSuffix::ManaLeech => "of the Wight",
 			ItemSuffix::Duration => "of Time",
+			ItemSuffix::CritLeech => "of the Leech",
+			ItemSuffix::BladeSpeed => "of Whirling",
+			ItemSuffix::MagicFind => "of Fortune",
+			ItemSuffix::AilmentEffect => "of Affliction",
+			ItemSuffix::AilmentDuration => "of Lingering",
+			ItemSuffix::ShatterThreshold => "of Shattering",
 		}
 	}
 
@@ -1652,6 +2465,12 @@ impl ItemSuffix
 			ItemSuffix::LifeLeech => (0.01, 0.01),
 			ItemSuffix::ManaLeech => (0.01, 0.01),
 			ItemSuffix::Duration => (0.02, 0.01),
+			ItemSuffix::CritLeech => (0.02, 0.01),
+			ItemSuffix::BladeSpeed => (0.05, 0.01),
+			ItemSuffix::MagicFind => (0.02, 0.01),
+			ItemSuffix::AilmentEffect => (0.05, 0.01),
+			ItemSuffix::AilmentDuration => (0.05, 0.01),
+			ItemSuffix::ShatterThreshold => (0.01, 0.01),
 		};
 		let start = delta * tier;
 		let end = delta * (tier + 1.);
@@ -1685,6 +2504,12 @@ impl ItemSuffix
 			ItemSuffix::LifeLeech => "Life Leech",
 			ItemSuffix::ManaLeech => "Mana Leech",
 			ItemSuffix::Duration => "Skill Duration",
+			ItemSuffix::CritLeech => "Instant Leech On Crit",
+			ItemSuffix::BladeSpeed => "Blade Speed",
+			ItemSuffix::MagicFind => "Magic Find",
+			ItemSuffix::AilmentEffect => "Ailment Effect",
+			ItemSuffix::AilmentDuration => "Ailment Duration",
+			ItemSuffix::ShatterThreshold => "Shatter Threshold",
 		};
 		format!(
 			"{sign}{value}{percent} {suffix}",
@@ -1749,10 +2574,285 @@ impl ItemSuffix
 			{
 				increases.skill_duration += value;
 			}
+			ItemSuffix::CritLeech =>
+			{
+				adds.crit_leech += value;
+			}
+			ItemSuffix::BladeSpeed =>
+			{
+				adds.blade_speed += value;
+			}
+			ItemSuffix::MagicFind =>
+			{
+				adds.magic_find += value;
+			}
+			ItemSuffix::AilmentEffect =>
+			{
+				increases.increased_ailment_effect += value;
+			}
+			ItemSuffix::AilmentDuration =>
+			{
+				increases.increased_ailment_duration += value;
+			}
+			ItemSuffix::ShatterThreshold =>
+			{
+				adds.shatter_threshold += value;
+			}
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Challenge
+{
+	NoHealing,
+	FastEnemies,
+	HalfLife,
+	Enrage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Archetype
+{
+	Wanderer,
+	Pyromancer,
+	Guardian,
+}
+
+impl Default for Archetype
+{
+	fn default() -> Self
+	{
+		Archetype::Wanderer
+	}
+}
+
+pub const ARCHETYPES: [Archetype; 3] = [
+	Archetype::Wanderer,
+	Archetype::Pyromancer,
+	Archetype::Guardian,
+];
+
+impl Archetype
+{
+	pub fn to_str(&self) -> &'static str
+	{
+		match self
+		{
+			Archetype::Wanderer => "Wanderer",
+			Archetype::Pyromancer => "Pyromancer",
+			Archetype::Guardian => "Guardian",
+		}
+	}
+
+	pub fn description(&self) -> &'static str
+	{
+		match self
+		{
+			Archetype::Wanderer => "No frills. Starts empty-handed.",
+			Archetype::Pyromancer =>
+			{
+				"More damage, less life. Starts with a fire ring and a mana amulet."
+			}
+			Archetype::Guardian =>
+			{
+				"More life, less damage. Starts with an armour ring and a life belt."
+			}
+		}
+	}
+
+	pub fn starting_inventory(&self) -> Inventory
+	{
+		let mut inventory = Inventory::new();
+		inventory.slots[1] = match self
+		{
+			Archetype::Wanderer => None,
+			Archetype::Pyromancer => Some(Item {
+				level_requirement: 0,
+				category: ItemCategory::Ring,
+				name: vec!["Kindling".to_string(), "Ruby Ring".to_string()],
+				appearance: Appearance::new("data/ring_fire.cfg"),
+				rarity: Rarity::Magic,
+				prefixes: vec![(ItemPrefix::AddedFireDamage, 1, 0.5)],
+				suffixes: vec![(ItemSuffix::IncreasedFireDamage, 1, 0.5)],
+			}),
+			Archetype::Guardian => Some(Item {
+				level_requirement: 0,
+				category: ItemCategory::Ring,
+				name: vec!["Sturdy".to_string(), "Sapphire Ring".to_string()],
+				appearance: Appearance::new("data/ring_armor.cfg"),
+				rarity: Rarity::Magic,
+				prefixes: vec![(ItemPrefix::Life, 1, 0.5)],
+				suffixes: vec![(ItemSuffix::Armour, 1, 0.5)],
+			}),
+		};
+		match self
+		{
+			Archetype::Wanderer => (),
+			Archetype::Pyromancer =>
+			{
+				inventory.slots[0] = Some(Item {
+					level_requirement: 0,
+					category: ItemCategory::Amulet,
+					name: vec!["Glowing".to_string(), "Ember Amulet".to_string()],
+					appearance: Appearance::new("data/amulet_red.cfg"),
+					rarity: Rarity::Magic,
+					prefixes: vec![(ItemPrefix::Mana, 1, 0.5)],
+					suffixes: vec![],
+				});
+			}
+			Archetype::Guardian =>
+			{
+				inventory.slots[3] = Some(Item {
+					level_requirement: 0,
+					category: ItemCategory::Belt,
+					name: vec!["Healthy".to_string(), "Leather Belt".to_string()],
+					appearance: Appearance::new("data/belt_blue.cfg"),
+					rarity: Rarity::Magic,
+					prefixes: vec![(ItemPrefix::Life, 1, 0.5)],
+					suffixes: vec![],
+				});
+			}
 		}
+		inventory
 	}
 }
 
+pub const CHALLENGES: [Challenge; 4] = [
+	Challenge::NoHealing,
+	Challenge::FastEnemies,
+	Challenge::HalfLife,
+	Challenge::Enrage,
+];
+
+impl Challenge
+{
+	pub fn to_str(&self) -> &'static str
+	{
+		match self
+		{
+			Challenge::NoHealing => "No Healing",
+			Challenge::FastEnemies => "Fast Enemies",
+			Challenge::HalfLife => "Half Life",
+			Challenge::Enrage => "Enraging Enemies",
+		}
+	}
+}
+
+// Rolled once per map from the map seed.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapMod
+{
+	BlazingEnemies,
+	ToughEnemies,
+	PoorRegen,
+	ExtraCrystal,
+	Bountiful,
+}
+
+pub const MAP_MODS: [(MapMod, i32); 5] = [
+	(MapMod::BlazingEnemies, 3),
+	(MapMod::ToughEnemies, 3),
+	(MapMod::PoorRegen, 3),
+	(MapMod::ExtraCrystal, 2),
+	(MapMod::Bountiful, 1),
+];
+
+impl MapMod
+{
+	pub fn to_str(&self) -> &'static str
+	{
+		match self
+		{
+			MapMod::BlazingEnemies => "Enemies deal extra Fire Damage",
+			MapMod::ToughEnemies => "Enemies have more Life",
+			MapMod::PoorRegen => "Reduced Life and Mana Regen",
+			MapMod::ExtraCrystal => "Extra Crystal",
+			MapMod::Bountiful => "More Loot",
+		}
+	}
+
+	// Riskier mods grant more magic find.
+	pub fn magic_find(&self) -> f32
+	{
+		match self
+		{
+			MapMod::BlazingEnemies => 0.3,
+			MapMod::ToughEnemies => 0.4,
+			MapMod::PoorRegen => 0.2,
+			MapMod::ExtraCrystal => 0.,
+			MapMod::Bountiful => 0.25,
+		}
+	}
+}
+
+pub fn roll_map_mods(seed: u64) -> Vec<MapMod>
+{
+	let mut rng = StdRng::seed_from_u64(seed);
+	let count = *[(0, 2), (1, 3), (2, 1)]
+		.choose_weighted(&mut rng, |&(_, w)| w)
+		.map(|(c, _)| c)
+		.unwrap();
+	MAP_MODS
+		.choose_multiple_weighted(&mut rng, count, |&(_, w)| w)
+		.unwrap()
+		.map(|&(m, _)| m)
+		.collect()
+}
+
+pub fn map_mods_magic_find(map_mods: &[MapMod]) -> f32
+{
+	map_mods.iter().map(|m| m.magic_find()).sum()
+}
+
+// What opens the exit, rolled once per map from the map seed.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Objective
+{
+	BreakCrystals,
+	Survive,
+	// Reach the exit without breaking any crystals, breaking one falls back to breaking all.
+	Sneak,
+	KillElite,
+}
+
+pub const OBJECTIVES: [(Objective, i32); 4] = [
+	(Objective::BreakCrystals, 6),
+	(Objective::Survive, 2),
+	(Objective::Sneak, 1),
+	(Objective::KillElite, 2),
+];
+
+pub const SURVIVE_TIME: f64 = 60.;
+
+impl Objective
+{
+	pub fn to_str(&self) -> &'static str
+	{
+		match self
+		{
+			Objective::BreakCrystals => "Break All Crystals",
+			Objective::Survive => "Survive",
+			Objective::Sneak => "Reach the Exit Without Breaking Crystals",
+			Objective::KillElite => "Slay the Exit Guardian",
+		}
+	}
+}
+
+// The first map always teaches the crystals.
+pub fn roll_objective(seed: u64, level: i32) -> Objective
+{
+	if level <= 1
+	{
+		return Objective::BreakCrystals;
+	}
+	let mut rng = StdRng::seed_from_u64(seed);
+	OBJECTIVES
+		.choose_weighted(&mut rng, |&(_, w)| w)
+		.map(|&(o, _)| o)
+		.unwrap()
+}
+
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Rarity
 {
@@ -1766,10 +2866,87 @@ pub enum Rarity
 pub struct Item
 {
 	pub name: Vec<String>,
+	#[serde(default)]
+	pub category: ItemCategory,
 	pub appearance: Appearance,
 	pub rarity: Rarity,
 	pub prefixes: Vec<(ItemPrefix, i32, f32)>,
 	pub suffixes: Vec<(ItemSuffix, i32, f32)>,
+	#[serde(default)]
+	pub level_requirement: i32,
+}
+
+impl Item
+{
+	pub fn can_equip(&self, level: i32) -> bool
+	{
+		level >= self.level_requirement
+	}
+
+	// Rerolls the values of the affixes, keeping their tiers.
+	pub fn reroll(&mut self, rng: &mut impl Rng)
+	{
+		for prefix in &mut self.prefixes
+		{
+			prefix.2 = rng.gen_range(0.0..1.0);
+		}
+		for suffix in &mut self.suffixes
+		{
+			suffix.2 = rng.gen_range(0.0..1.0);
+		}
+	}
+
+	pub fn fits_slot(&self, slot: usize) -> bool
+	{
+		ItemCategory::for_slot(slot)
+			.map(|category| category == self.category)
+			.unwrap_or(true)
+	}
+
+	// The query is expected to be lowercase already. An empty query matches everything.
+	pub fn matches_query(&self, query: &str) -> bool
+	{
+		if query.is_empty()
+		{
+			return true;
+		}
+		let matches = |text: &str| text.to_lowercase().contains(query);
+		self.name.iter().any(|name| matches(name))
+			|| matches(self.category.to_str())
+			|| matches(&format!("{:?}", self.rarity))
+			|| self
+				.prefixes
+				.iter()
+				.any(|(prefix, _, _)| matches(prefix.to_str()))
+			|| self
+				.suffixes
+				.iter()
+				.any(|(suffix, _, _)| matches(suffix.to_str()))
+	}
+
+	// Items have no identity, so two rolls that match exactly are treated as the same item.
+	pub fn same_item(&self, other: &Item) -> bool
+	{
+		self.name == other.name
+			&& self.category == other.category
+			&& self.rarity == other.rarity
+			&& self.level_requirement == other.level_requirement
+			&& self.prefixes == other.prefixes
+			&& self.suffixes == other.suffixes
+	}
+}
+
+pub fn level_requirement_for_tiers(
+	prefixes: &[(ItemPrefix, i32, f32)], suffixes: &[(ItemSuffix, i32, f32)],
+) -> i32
+{
+	let max_tier = prefixes
+		.iter()
+		.map(|p| p.1)
+		.chain(suffixes.iter().map(|s| s.1))
+		.max()
+		.unwrap_or(0);
+	max_tier * 2 / 3
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -1786,13 +2963,122 @@ impl Inventory
 			slots: [None, None, None, None, None, None, None, None, None],
 		}
 	}
+
+	// Builds the inventory that results from swapping `new_item` into `slot`, returning it along
+	// with the item that got displaced. `self` is left untouched so the caller can persist the
+	// result before mutating the world.
+	pub fn with_swap(&self, slot: usize, new_item: Option<Item>) -> (Inventory, Option<Item>)
+	{
+		let mut inventory = self.clone();
+		let old_item = std::mem::replace(&mut inventory.slots[slot], new_item);
+		(inventory, old_item)
+	}
+
+	// Moves the items of a saved loadout back into the equipped slots they were saved from.
+	// Displaced items take the vacated slots. Returns the new inventory and how many of the
+	// loadout's items are no longer in the inventory.
+	pub fn with_loadout(&self, loadout: &[Option<Item>]) -> (Inventory, i32)
+	{
+		let mut inventory = self.clone();
+		let mut placed = [false; 9];
+		let mut missing = 0;
+		for (slot, item) in loadout.iter().enumerate().take(6)
+		{
+			if let Some(item) = item
+			{
+				let found = (0..inventory.slots.len()).find(|&i| {
+					!placed[i]
+						&& inventory.slots[i]
+							.as_ref()
+							.map(|other| other.same_item(item))
+							.unwrap_or(false)
+				});
+				if let Some(i) = found
+				{
+					inventory.slots.swap(slot, i);
+					placed[slot] = true;
+				}
+				else
+				{
+					missing += 1;
+				}
+			}
+		}
+		(inventory, missing)
+	}
+
+	// Equips the items of an imported build. Items that don't fit their slot or are above `level`
+	// are skipped, and the displaced items move to storage. Returns the new inventory, the
+	// displaced items that didn't fit in storage and how many of the build's items were skipped.
+	pub fn with_build(&self, items: &[Option<Item>], level: i32) -> (Inventory, Vec<Item>, i32)
+	{
+		let mut inventory = self.clone();
+		let mut displaced = vec![];
+		let mut skipped = 0;
+		for (slot, item) in items.iter().enumerate().take(6)
+		{
+			match item
+			{
+				Some(item) if !item.fits_slot(slot) || !item.can_equip(level) => skipped += 1,
+				_ => displaced.extend(std::mem::replace(&mut inventory.slots[slot], item.clone())),
+			}
+		}
+		let (inventory, overflow) = inventory.with_stored(displaced);
+		(inventory, overflow, skipped)
+	}
+
+	// Moves equipped items that don't fit their slot into storage, e.g. rings equipped in the
+	// amulet or belt slots by older versions. Returns the items that didn't fit in storage.
+	pub fn with_misplaced_stored(&self) -> (Inventory, Vec<Item>)
+	{
+		let mut inventory = self.clone();
+		let mut misplaced = vec![];
+		for slot in 0..6
+		{
+			if inventory.slots[slot]
+				.as_ref()
+				.map(|item| !item.fits_slot(slot))
+				.unwrap_or(false)
+			{
+				misplaced.extend(inventory.slots[slot].take());
+			}
+		}
+		inventory.with_stored(misplaced)
+	}
+
+	fn with_stored(mut self, items: Vec<Item>) -> (Inventory, Vec<Item>)
+	{
+		let mut overflow = vec![];
+		for item in items
+		{
+			if let Some(slot) = self.slots[6..].iter_mut().find(|slot| slot.is_none())
+			{
+				*slot = Some(item);
+			}
+			else
+			{
+				overflow.push(item);
+			}
+		}
+		(self, overflow)
+	}
+}
+
+pub struct UniqueDesc
+{
+	pub weight: i32,
+	pub make: fn() -> Item,
 }
 
-pub fn generate_unique(rng: &mut impl Rng) -> Item
+// New uniques go here. Their level requirement doubles as the depth they start dropping at.
+pub fn unique_pool() -> Vec<UniqueDesc>
 {
-	let item_weight: [(Box<dyn Fn() -> Item>, i32); 9] = [
-		(
-			Box::new(|| Item {
+	vec![
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 0,
+				category: ItemCategory::Ring,
 				name: vec!["Polaris".to_string()],
 				appearance: Appearance::new("data/ring_cold.cfg"),
 				rarity: Rarity::Unique,
@@ -1801,11 +3087,13 @@ pub fn generate_unique(rng: &mut impl Rng) -> Item
 					(ItemPrefix::ChanceToFreeze, 25, 0.),
 				],
 				suffixes: vec![],
-			}),
-			10,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 20,
+			make: || Item {
+				level_requirement: 0,
+				category: ItemCategory::Ring,
 				name: vec!["Rageheart".to_string()],
 				appearance: Appearance::new("data/ring_fire.cfg"),
 				rarity: Rarity::Unique,
@@ -1814,11 +3102,13 @@ pub fn generate_unique(rng: &mut impl Rng) -> Item
 					(ItemPrefix::ChanceToIgnite, 25, 0.),
 				],
 				suffixes: vec![],
-			}),
-			20,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 20,
+			make: || Item {
+				level_requirement: 0,
+				category: ItemCategory::Ring,
 				name: vec!["Tesla Coil".to_string()],
 				appearance: Appearance::new("data/ring_lightning.cfg"),
 				rarity: Rarity::Unique,
@@ -1827,21 +3117,25 @@ pub fn generate_unique(rng: &mut impl Rng) -> Item
 					(ItemPrefix::ChanceToShock, 25, 0.),
 				],
 				suffixes: vec![],
-			}),
-			20,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 15,
+			make: || Item {
+				level_requirement: 0,
+				category: ItemCategory::Ring,
 				name: vec!["Uncontrollable".to_string(), "Hate".to_string()],
 				appearance: Appearance::new("data/ring_explode.cfg"),
 				rarity: Rarity::Unique,
 				prefixes: vec![(ItemPrefix::ExplodeOnDeath, 1, 0.)],
 				suffixes: vec![],
-			}),
-			15,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 20,
+			make: || Item {
+				level_requirement: 0,
+				category: ItemCategory::Ring,
 				name: vec!["Exile's".to_string(), "Warm Memories".to_string()],
 				appearance: Appearance::new("data/ring_warmth.cfg"),
 				rarity: Rarity::Unique,
@@ -1850,21 +3144,25 @@ pub fn generate_unique(rng: &mut impl Rng) -> Item
 					(ItemPrefix::NoColdDamage, 1, 0.),
 				],
 				suffixes: vec![],
-			}),
-			20,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 20,
+			make: || Item {
+				level_requirement: 3,
+				category: ItemCategory::Ring,
 				name: vec!["Vulnerable Grace".to_string()],
 				appearance: Appearance::new("data/ring_dodge.cfg"),
 				rarity: Rarity::Unique,
 				prefixes: vec![(ItemPrefix::NakedDodge, 1, 0.)],
 				suffixes: vec![],
-			}),
-			20,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 20,
+			make: || Item {
+				level_requirement: 5,
+				category: ItemCategory::Ring,
 				name: vec!["Ethereal Tear".to_string()],
 				appearance: Appearance::new("data/ring_constrict.cfg"),
 				rarity: Rarity::Unique,
@@ -1873,11 +3171,13 @@ pub fn generate_unique(rng: &mut impl Rng) -> Item
 					(ItemPrefix::Decaying, 1, 0.),
 				],
 				suffixes: vec![],
-			}),
-			20,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 8,
+				category: ItemCategory::Ring,
 				name: vec!["Whale Pact".to_string()],
 				appearance: Appearance::new("data/ring_leech.cfg"),
 				rarity: Rarity::Unique,
@@ -1887,11 +3187,13 @@ pub fn generate_unique(rng: &mut impl Rng) -> Item
 					(ItemPrefix::HalfMana, 1, 0.),
 				],
 				suffixes: vec![],
-			}),
-			10,
-		),
-		(
-			Box::new(|| Item {
+			},
+		},
+		UniqueDesc {
+			weight: 20,
+			make: || Item {
+				level_requirement: 8,
+				category: ItemCategory::Ring,
 				name: vec!["Final".to_string(), "Blasphemy".to_string()],
 				appearance: Appearance::new("data/ring_armor.cfg"),
 				rarity: Rarity::Unique,
@@ -1900,14 +3202,155 @@ pub fn generate_unique(rng: &mut impl Rng) -> Item
 					(ItemPrefix::NoElementalDamage, 1, 0.),
 				],
 				suffixes: vec![],
-			}),
-			20,
-		),
-	];
-	item_weight.choose_weighted(rng, |&(_, w)| w).unwrap().0()
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 5,
+				category: ItemCategory::Ring,
+				name: vec!["Held".to_string(), "Breath".to_string()],
+				appearance: Appearance::new("data/ring_explode.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![
+					(ItemPrefix::StoredBlades, 1, 0.),
+					(ItemPrefix::AreaOfEffect, 10, 0.),
+				],
+				suffixes: vec![],
+			},
+		},
+		UniqueDesc {
+			weight: 15,
+			make: || Item {
+				level_requirement: 4,
+				category: ItemCategory::Ring,
+				name: vec!["Turning".to_string(), "Ward".to_string()],
+				appearance: Appearance::new("data/ring_warmth.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![
+					(ItemPrefix::ElementalAegis, 1, 0.),
+					(ItemPrefix::Life, 5, 0.),
+				],
+				suffixes: vec![],
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 6,
+				category: ItemCategory::Ring,
+				name: vec!["Second".to_string(), "Wind".to_string()],
+				appearance: Appearance::new("data/ring_leech.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![
+					(ItemPrefix::SecondChance, 1, 0.),
+					(ItemPrefix::LifeRegen, 5, 0.),
+				],
+				suffixes: vec![],
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 3,
+				category: ItemCategory::Ring,
+				name: vec!["Rimed".to_string(), "Edge".to_string()],
+				appearance: Appearance::new("data/ring_cold.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![
+					(ItemPrefix::PhysicalToCold, 1, 0.),
+					(ItemPrefix::AddedPhysicalDamage, 3, 0.),
+				],
+				suffixes: vec![],
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 3,
+				category: ItemCategory::Ring,
+				name: vec!["Cinder".to_string(), "Edge".to_string()],
+				appearance: Appearance::new("data/ring_fire.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![
+					(ItemPrefix::PhysicalToFire, 1, 0.),
+					(ItemPrefix::AddedPhysicalDamage, 3, 0.),
+				],
+				suffixes: vec![],
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 3,
+				category: ItemCategory::Ring,
+				name: vec!["Storm".to_string(), "Edge".to_string()],
+				appearance: Appearance::new("data/ring_lightning.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![
+					(ItemPrefix::PhysicalToLightning, 1, 0.),
+					(ItemPrefix::AddedPhysicalDamage, 3, 0.),
+				],
+				suffixes: vec![],
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 5,
+				category: ItemCategory::Amulet,
+				name: vec!["Brimming".to_string(), "Heart".to_string()],
+				appearance: Appearance::new("data/amulet_red.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![(ItemPrefix::OverhealShield, 1, 0.)],
+				suffixes: vec![(ItemSuffix::LifeLeech, 5, 0.)],
+			},
+		},
+		UniqueDesc {
+			weight: 10,
+			make: || Item {
+				level_requirement: 2,
+				category: ItemCategory::Belt,
+				name: vec!["Warden's".to_string(), "Cinch".to_string()],
+				appearance: Appearance::new("data/belt_yellow.cfg"),
+				rarity: Rarity::Unique,
+				prefixes: vec![(ItemPrefix::Life, 3, 0.), (ItemPrefix::LifeRegen, 3, 0.)],
+				suffixes: vec![
+					(ItemSuffix::Armour, 3, 0.),
+					(ItemSuffix::PhysicalResistance, 2, 0.),
+				],
+			},
+		},
+	]
 }
 
-pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut impl Rng) -> Item
+pub fn generate_unique(level: i32, rng: &mut impl Rng) -> Item
+{
+	let pool: Vec<_> = unique_pool()
+		.into_iter()
+		.filter(|desc| (desc.make)().level_requirement <= level)
+		.collect();
+	(pool.choose_weighted(rng, |desc| desc.weight).unwrap().make)()
+}
+
+pub const MIN_RARE_AFFIXES: i32 = 3;
+pub const MAX_RARE_AFFIXES: i32 = 5;
+// Gives up on finding a new affix after this many rolls, in case the pool runs dry.
+const MAX_AFFIX_ROLLS: i32 = 100;
+
+// Max prefixes (and suffixes) on a rare, growing with crystal level and depth.
+pub fn rare_affix_count(crystal_level: i32, level: i32) -> i32
+{
+	utils::clamp(
+		MIN_RARE_AFFIXES + crystal_level / 4 + level / 20,
+		MIN_RARE_AFFIXES,
+		MAX_RARE_AFFIXES,
+	)
+}
+
+pub fn generate_item(
+	kind: ItemKind, crystal_level: i32, level: i32, magic_find: f32, rng: &mut impl Rng,
+) -> Item
 {
 	let rarity_weights = match crystal_level
 	{
@@ -1922,10 +3365,11 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 		_ => unreachable!(),
 	};
 
+	let magic_find = utils::clamp(magic_find, 0., 1.);
 	let rarity = [
-		(Rarity::Magic, rarity_weights.0),
-		(Rarity::Rare, rarity_weights.1),
-		(Rarity::Unique, rarity_weights.2),
+		(Rarity::Magic, rarity_weights.0 as f32),
+		(Rarity::Rare, rarity_weights.1 as f32 * (1. + magic_find)),
+		(Rarity::Unique, rarity_weights.2 as f32 * (1. + magic_find)),
 	]
 	.choose_weighted(rng, |&(_, w)| w)
 	.unwrap()
@@ -1933,7 +3377,7 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 
 	if rarity == Rarity::Unique
 	{
-		return generate_unique(rng);
+		return generate_unique(level, rng);
 	}
 
 	let red_prefix_weights = [
@@ -1951,6 +3395,7 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 		(ItemPrefix::ManaRegen, 50),
 		(ItemPrefix::AreaOfEffect, 500),
 		(ItemPrefix::CastSpeed, 50),
+		(ItemPrefix::ReducedManaCost, 10),
 	];
 
 	let green_prefix_weights = [
@@ -1968,6 +3413,7 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 		(ItemPrefix::ManaRegen, 50),
 		(ItemPrefix::AreaOfEffect, 50),
 		(ItemPrefix::CastSpeed, 500),
+		(ItemPrefix::ReducedManaCost, 10),
 	];
 
 	let blue_prefix_weights = [
@@ -1985,6 +3431,7 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 		(ItemPrefix::ManaRegen, 1000),
 		(ItemPrefix::AreaOfEffect, 50),
 		(ItemPrefix::CastSpeed, 50),
+		(ItemPrefix::ReducedManaCost, 200),
 	];
 
 	let red_suffix_weights = [
@@ -2001,6 +3448,12 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 		(ItemSuffix::LifeLeech, 200),
 		(ItemSuffix::ManaLeech, 50),
 		(ItemSuffix::Duration, 50),
+		(ItemSuffix::CritLeech, 50),
+		(ItemSuffix::BladeSpeed, 100),
+		(ItemSuffix::MagicFind, 50),
+		(ItemSuffix::AilmentEffect, 50),
+		(ItemSuffix::AilmentDuration, 50),
+		(ItemSuffix::ShatterThreshold, 10),
 	];
 
 	let green_suffix_weights = [
@@ -2017,6 +3470,12 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 		(ItemSuffix::LifeLeech, 50),
 		(ItemSuffix::ManaLeech, 50),
 		(ItemSuffix::Duration, 500),
+		(ItemSuffix::CritLeech, 200),
+		(ItemSuffix::BladeSpeed, 200),
+		(ItemSuffix::MagicFind, 50),
+		(ItemSuffix::AilmentEffect, 50),
+		(ItemSuffix::AilmentDuration, 200),
+		(ItemSuffix::ShatterThreshold, 10),
 	];
 
 	let blue_suffix_weights = [
@@ -2033,23 +3492,40 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 		(ItemSuffix::LifeLeech, 50),
 		(ItemSuffix::ManaLeech, 200),
 		(ItemSuffix::Duration, 50),
+		(ItemSuffix::CritLeech, 50),
+		(ItemSuffix::BladeSpeed, 100),
+		(ItemSuffix::MagicFind, 50),
+		(ItemSuffix::AilmentEffect, 50),
+		(ItemSuffix::AilmentDuration, 50),
+		(ItemSuffix::ShatterThreshold, 200),
 	];
 
+	let category = [
+		(ItemCategory::Ring, 6),
+		(ItemCategory::Amulet, 2),
+		(ItemCategory::Belt, 2),
+	]
+	.choose_weighted(rng, |&(_, w)| w)
+	.unwrap()
+	.0;
+
 	let prefix_weights = [
 		red_prefix_weights,
 		green_prefix_weights,
 		blue_prefix_weights,
-	][kind as usize];
+	][kind as usize]
+		.map(|(prefix, w)| (prefix, w * category.prefix_weight_multiplier(prefix)));
 	let suffix_weights = [
 		red_suffix_weights,
 		green_suffix_weights,
 		blue_suffix_weights,
-	][kind as usize];
+	][kind as usize]
+		.map(|(suffix, w)| (suffix, w * category.suffix_weight_multiplier(suffix)));
 
 	let (num_affixes, min_affixes) = match rarity
 	{
 		Rarity::Magic => (1, 1),
-		Rarity::Rare => (3, 3),
+		Rarity::Rare => (rare_affix_count(crystal_level, level), MIN_RARE_AFFIXES),
 		_ => unreachable!(),
 	};
 
@@ -2064,6 +3540,15 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 			break;
 		}
 	}
+	// Affixes don't repeat, so there can't be more than the pool has.
+	num_prefixes = utils::min(
+		num_prefixes,
+		prefix_weights.iter().filter(|&&(_, w)| w > 0).count() as i32,
+	);
+	num_suffixes = utils::min(
+		num_suffixes,
+		suffix_weights.iter().filter(|&&(_, w)| w > 0).count() as i32,
+	);
 
 	let affix_level = if rarity == Rarity::Magic
 	{
@@ -2076,7 +3561,7 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 	let mut prefixes: Vec<(_, i32, f32)> = vec![];
 	for _ in 0..num_prefixes
 	{
-		loop
+		for _ in 0..MAX_AFFIX_ROLLS
 		{
 			let prefix = prefix_weights.choose_weighted(rng, |&(_, w)| w).unwrap().0;
 			if prefixes.iter().find(|p| p.0 == prefix).is_none()
@@ -2094,7 +3579,7 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 	let mut suffixes: Vec<(_, i32, f32)> = vec![];
 	for _ in 0..num_suffixes
 	{
-		loop
+		for _ in 0..MAX_AFFIX_ROLLS
 		{
 			let suffix = suffix_weights.choose_weighted(rng, |&(_, w)| w).unwrap().0;
 			if suffixes.iter().find(|p| p.0 == suffix).is_none()
@@ -2112,10 +3597,12 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 	let name = match rarity
 	{
 		Rarity::Normal => unreachable!(),
-		Rarity::Magic =>
-		{
-			make_magic_name(kind, prefixes.first().copied(), suffixes.first().copied())
-		}
+		Rarity::Magic => make_magic_name(
+			kind,
+			category,
+			prefixes.first().copied(),
+			suffixes.first().copied(),
+		),
 		Rarity::Rare => make_rare_name(rng),
 		Rarity::Unique => unreachable!(),
 	};
@@ -2123,14 +3610,11 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 	prefixes.sort_by_key(|a| a.0);
 	suffixes.sort_by_key(|a| a.0);
 
-	let appearance = match kind
-	{
-		ItemKind::Red => "data/ring_red.cfg",
-		ItemKind::Green => "data/ring_yellow.cfg",
-		ItemKind::Blue => "data/ring_blue.cfg",
-	};
-	let appearance = Appearance::new(appearance);
+	let appearance = Appearance::new(category.appearance(kind));
+	let level_requirement = level_requirement_for_tiers(&prefixes, &suffixes);
 	let item = Item {
+		level_requirement: level_requirement,
+		category: category,
 		name: name,
 		rarity: rarity,
 		appearance: appearance,
@@ -2141,15 +3625,57 @@ pub fn generate_item(kind: ItemKind, crystal_level: i32, level: i32, rng: &mut i
 	item
 }
 
+// Builds an item with exactly the given affixes, used to test specific affix combinations.
+pub fn make_item(
+	kind: ItemKind, category: ItemCategory, rarity: Rarity,
+	mut prefixes: Vec<(ItemPrefix, i32, f32)>, mut suffixes: Vec<(ItemSuffix, i32, f32)>,
+) -> Item
+{
+	prefixes.sort_by_key(|a| a.0);
+	suffixes.sort_by_key(|a| a.0);
+	let name = match rarity
+	{
+		Rarity::Magic => make_magic_name(
+			kind,
+			category,
+			prefixes.first().copied(),
+			suffixes.first().copied(),
+		),
+		_ => vec![
+			"Debug".to_string(),
+			format!("{} {}", kind.gem_str(), category.to_str()),
+		],
+	};
+	Item {
+		level_requirement: level_requirement_for_tiers(&prefixes, &suffixes),
+		category: category,
+		name: name,
+		rarity: rarity,
+		appearance: Appearance::new(category.appearance(kind)),
+		prefixes: prefixes,
+		suffixes: suffixes,
+	}
+}
+
+// Same inputs always produce the same item, as all the randomness comes from the seeded RNG.
+pub fn generate_item_from_seed(
+	seed: u64, kind: ItemKind, crystal_level: i32, level: i32, magic_find: f32,
+) -> Item
+{
+	let mut rng = StdRng::seed_from_u64(seed);
+	generate_item(kind, crystal_level, level, magic_find, &mut rng)
+}
+
 fn make_magic_name(
-	kind: ItemKind, prefix: Option<(ItemPrefix, i32, f32)>, suffix: Option<(ItemSuffix, i32, f32)>,
+	kind: ItemKind, category: ItemCategory, prefix: Option<(ItemPrefix, i32, f32)>,
+	suffix: Option<(ItemSuffix, i32, f32)>,
 ) -> Vec<String>
 {
 	let prefix = prefix.map(|(a, _, _)| a.to_str()).unwrap_or("");
 	let suffix = suffix.map(|(a, _, _)| a.to_str()).unwrap_or("");
 	vec![
 		prefix.to_string(),
-		kind.to_str().to_string(),
+		format!("{} {}", kind.gem_str(), category.to_str()),
 		suffix.to_string(),
 	]
 }
@@ -2278,44 +3804,60 @@ pub struct DamageSprites
 	pub sound: &'static str,
 }
 
-pub fn damage_sprites(values: &StatValues, rarity: Rarity) -> DamageSprites
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DamageElement
+{
+	Fire,
+	Cold,
+	Lightning,
+	Physical,
+}
+
+impl DamageElement
 {
+	pub fn color(&self, f: f32) -> Color
+	{
+		match self
+		{
+			DamageElement::Fire => Color::from_rgb_f(f, f, 0.),
+			DamageElement::Cold => Color::from_rgb_f(0., 0., f),
+			DamageElement::Lightning => Color::from_rgb_f(0.5 * f, 0.5 * f, f),
+			DamageElement::Physical => Color::from_rgb_f(f, f, f),
+		}
+	}
+}
+
+// The element with the most added damage, falling back to the strongest propagation.
+pub fn dominant_element(values: &StatValues) -> DamageElement
+{
+	let elements = [
+		DamageElement::Fire,
+		DamageElement::Cold,
+		DamageElement::Lightning,
+	];
 	let damage_vals = [
 		values.fire_damage as i32,
 		values.cold_damage as i32,
 		values.lightning_damage as i32,
 	];
-	let damage_idx = if damage_vals.iter().sum::<i32>() == 0
+	let propagate_vals = [
+		(values.ignite_propagate_value.effect * 100.) as i32,
+		(values.freeze_propagate_value * 100.) as i32,
+		(values.shock_propagate_value.effect * 100.) as i32,
+	];
+	for vals in [damage_vals, propagate_vals]
 	{
-		let propagate_vals = [
-			(values.ignite_propagate_value.effect * 100.) as i32,
-			(values.freeze_propagate_value * 100.) as i32,
-			(values.shock_propagate_value.effect * 100.) as i32,
-		];
-		if propagate_vals.iter().sum::<i32>() == 0
+		if vals.iter().sum::<i32>() != 0
 		{
-			3
-		}
-		else
-		{
-			propagate_vals
-				.iter()
-				.enumerate()
-				.max_by_key(|(_, &v)| v)
-				.unwrap()
-				.0
+			let idx = vals.iter().enumerate().max_by_key(|(_, &v)| v).unwrap().0;
+			return elements[idx];
 		}
 	}
-	else
-	{
-		damage_vals
-			.iter()
-			.enumerate()
-			.max_by_key(|(_, &v)| v)
-			.unwrap()
-			.0
-	};
+	DamageElement::Physical
+}
 
+pub fn damage_sprites(values: &StatValues, rarity: Rarity) -> DamageSprites
+{
 	let f = match rarity
 	{
 		Rarity::Normal => 0.5,
@@ -2324,37 +3866,509 @@ pub fn damage_sprites(values: &StatValues, rarity: Rarity) -> DamageSprites
 		Rarity::Unique => 1.,
 	};
 
-	let (arrow, hit, color, sound) = [
-		(
+	let element = dominant_element(values);
+	let (arrow, hit, sound) = match element
+	{
+		DamageElement::Fire => (
 			"data/fireball.cfg",
 			"data/fire_hit.cfg",
-			Color::from_rgb_f(f, f, 0.),
 			"data/hit_fire.ogg",
 		),
-		(
+		DamageElement::Cold => (
 			"data/arrow_cold.cfg",
 			"data/cold_hit.cfg",
-			Color::from_rgb_f(0., 0., f),
 			"data/hit_cold.ogg",
 		),
-		(
+		DamageElement::Lightning => (
 			"data/arrow_lightning.cfg",
 			"data/lightning_hit.cfg",
-			Color::from_rgb_f(0.5 * f, 0.5 * f, f),
 			"data/hit_lightning.ogg",
 		),
-		(
+		DamageElement::Physical => (
 			"data/arrow_normal.cfg",
 			"data/normal_hit.cfg",
-			Color::from_rgb_f(f, f, f),
 			"data/hit_normal.ogg",
 		),
-	][damage_idx];
+	};
 
 	DamageSprites {
 		arrow: arrow,
 		hit: hit,
-		color: color,
+		color: element.color(f),
 		sound: sound,
 	}
 }
+
+// Blades take on the color of the dominant element, staying red for pure physical.
+pub fn blade_blade_color(values: &StatValues) -> Color
+{
+	match dominant_element(values)
+	{
+		DamageElement::Physical => Color::from_rgb_f(1., 0.2, 0.2),
+		element => element.color(1.),
+	}
+}
+
+#[test]
+fn inventory_swap_test()
+{
+	let item = |name: &str| Item {
+		level_requirement: 0,
+		category: ItemCategory::Ring,
+		name: vec![name.to_string()],
+		appearance: Appearance::new("data/ring_red.cfg"),
+		rarity: Rarity::Normal,
+		prefixes: vec![],
+		suffixes: vec![],
+	};
+	let mut inventory = Inventory::new();
+	inventory.slots[2] = Some(item("Equipped"));
+	let ground_item = Some(item("Ground"));
+
+	let (new_inventory, dropped_item) = inventory.with_swap(2, ground_item);
+
+	// If we crash before the save, the original inventory still holds its item.
+	assert_eq!(inventory.slots[2].as_ref().unwrap().name[0], "Equipped");
+	// The committed inventory and the dropped item account for both items.
+	assert_eq!(new_inventory.slots[2].as_ref().unwrap().name[0], "Ground");
+	assert_eq!(dropped_item.unwrap().name[0], "Equipped");
+
+	let (new_inventory, dropped_item) = new_inventory.with_swap(5, None);
+	assert!(dropped_item.is_none());
+	assert_eq!(
+		new_inventory.slots.iter().filter(|s| s.is_some()).count(),
+		1
+	);
+}
+
+#[test]
+fn inventory_loadout_test()
+{
+	let item = |kind| make_item(kind, ItemCategory::Ring, Rarity::Normal, vec![], vec![]);
+	let mut inventory = Inventory::new();
+	inventory.slots[1] = Some(item(ItemKind::Red));
+	inventory.slots[2] = Some(item(ItemKind::Blue));
+	let loadout = inventory.slots[..6].to_vec();
+
+	// Swap the rings around and stash one.
+	inventory.slots[7] = inventory.slots[1].take();
+	inventory.slots.swap(1, 2);
+	let (restored, missing) = inventory.with_loadout(&loadout);
+	assert_eq!(missing, 0);
+	assert_eq!(restored.slots[1].as_ref().unwrap().name[1], "Ruby Ring");
+	assert_eq!(restored.slots[2].as_ref().unwrap().name[1], "Sapphire Ring");
+	assert!(restored.slots[7].is_none());
+
+	// A dropped item is reported and its slot is left alone.
+	inventory.slots[7] = None;
+	let (restored, missing) = inventory.with_loadout(&loadout);
+	assert_eq!(missing, 1);
+	assert!(restored.slots[1].is_none());
+	assert_eq!(restored.slots[2].as_ref().unwrap().name[1], "Sapphire Ring");
+}
+
+#[test]
+fn item_category_slot_test()
+{
+	let amulet = make_item(
+		ItemKind::Red,
+		ItemCategory::Amulet,
+		Rarity::Magic,
+		vec![],
+		vec![],
+	);
+	assert!(amulet.fits_slot(0));
+	assert!(!amulet.fits_slot(1));
+	assert!(!amulet.fits_slot(3));
+	// Storage takes anything.
+	assert!(amulet.fits_slot(6));
+}
+
+#[test]
+fn item_query_test()
+{
+	let item = make_item(
+		ItemKind::Red,
+		ItemCategory::Belt,
+		Rarity::Rare,
+		vec![],
+		vec![],
+	);
+	assert!(item.matches_query(""));
+	assert!(item.matches_query("belt"));
+	assert!(item.matches_query("rare"));
+	assert!(!item.matches_query("unique"));
+}
+
+#[test]
+fn level_scaling_test()
+{
+	let mut prev_life = 0.;
+	let mut prev_damage = 0.;
+	let mut prev_item_level = i32::MIN;
+	for level in 1..=200
+	{
+		let values = StatValues::new_enemy(level, Rarity::Rare, false, &[], &[]);
+		assert!(values.max_life.is_finite());
+		assert!(values.physical_damage.is_finite());
+		assert!(values.max_life > prev_life);
+		assert!(values.physical_damage > prev_damage);
+		assert!(enemy_item_level(level) >= prev_item_level);
+		prev_life = values.max_life;
+		prev_damage = values.physical_damage;
+		prev_item_level = enemy_item_level(level);
+	}
+
+	assert!((level_scaling(1) - 1.1).abs() < 1e-4);
+	assert!(level_scaling(50) > 100. && level_scaling(50) < 200.);
+	assert!(level_scaling(200) < 1e4);
+	assert!(enemy_item_level(1) >= 0);
+	assert!(enemy_item_level(200) < 100_000);
+}
+
+#[test]
+fn deterministic_item_test()
+{
+	for seed in 0..100
+	{
+		for kind in [ItemKind::Red, ItemKind::Green, ItemKind::Blue]
+		{
+			let item1 = generate_item_from_seed(seed, kind, 5, 10, 0.);
+			let item2 = generate_item_from_seed(seed, kind, 5, 10, 0.);
+			assert_eq!(format!("{:?}", item1), format!("{:?}", item2));
+		}
+	}
+
+	let mut num_magic = 0;
+	let mut num_rare = 0;
+	let mut num_unique = 0;
+	let mut names = std::collections::HashSet::new();
+	for seed in 0..1000
+	{
+		let item = generate_item_from_seed(seed, ItemKind::Red, 4, 10, 0.);
+		match item.rarity
+		{
+			Rarity::Magic => num_magic += 1,
+			Rarity::Rare => num_rare += 1,
+			Rarity::Unique => num_unique += 1,
+			Rarity::Normal => panic!("Normal items aren't generated"),
+		}
+		if item.rarity != Rarity::Unique
+		{
+			for (_, tier, frac) in &item.prefixes
+			{
+				assert!(*tier >= 0 && *tier <= 15);
+				assert!(*frac >= 0. && *frac < 1.);
+			}
+		}
+		names.insert(item.name.join(" "));
+	}
+	assert!(num_magic > 400);
+	assert!(num_rare > 200);
+	assert!(num_unique > 0 && num_unique < 100);
+	assert!(names.len() > 20);
+}
+
+#[test]
+fn aoe_radius_factor_test()
+{
+	let mut values = StatValues::default();
+	values.area_of_effect = 1.;
+	assert!((values.aoe_radius_factor() - 1.).abs() < 1e-4);
+	values.area_of_effect = 1e6;
+	assert_eq!(values.aoe_radius_factor(), MAX_AOE_RADIUS_FACTOR);
+	values.area_of_effect = -1.;
+	assert_eq!(values.aoe_radius_factor(), 0.);
+	values.area_of_effect = f32::INFINITY;
+	assert_eq!(values.aoe_radius_factor(), 1.);
+}
+
+#[test]
+fn map_mods_test()
+{
+	let mut num_empty = 0;
+	for seed in 0..200
+	{
+		let mods = roll_map_mods(seed);
+		assert_eq!(mods, roll_map_mods(seed));
+		assert!(mods.len() <= 2);
+		if mods.len() == 2
+		{
+			assert_ne!(mods[0], mods[1]);
+		}
+		if mods.is_empty()
+		{
+			num_empty += 1;
+		}
+	}
+	assert!(num_empty > 0 && num_empty < 200);
+}
+
+#[test]
+fn objective_test()
+{
+	let mut num_crystals = 0;
+	for seed in 0..200
+	{
+		assert_eq!(roll_objective(seed, 1), Objective::BreakCrystals);
+		let objective = roll_objective(seed, 5);
+		assert_eq!(objective, roll_objective(seed, 5));
+		if objective == Objective::BreakCrystals
+		{
+			num_crystals += 1;
+		}
+	}
+	assert!(num_crystals > 0 && num_crystals < 200);
+}
+
+#[test]
+fn aegis_weights_test()
+{
+	let mut time = 0.;
+	while time < 3. * AEGIS_PERIOD
+	{
+		let weights = aegis_weights(time);
+		assert!((weights.iter().sum::<f32>() - 1.).abs() < 1e-5);
+		assert_eq!(weights, aegis_weights(time));
+		let next = aegis_weights(time + 0.01);
+		for i in 0..3
+		{
+			assert!((weights[i] - next[i]).abs() < 0.1);
+		}
+		time += 0.01;
+	}
+	assert_eq!(aegis_element(0.), 0);
+	assert_eq!(aegis_element(AEGIS_PERIOD + 0.1), 1);
+	assert_eq!(aegis_element(2. * AEGIS_PERIOD + 0.1), 2);
+}
+
+#[test]
+fn rare_affix_count_test()
+{
+	assert_eq!(rare_affix_count(0, 1), MIN_RARE_AFFIXES);
+	assert_eq!(rare_affix_count(7, 1000), MAX_RARE_AFFIXES);
+	assert!(rare_affix_count(4, 1) > MIN_RARE_AFFIXES);
+
+	for seed in 0..200
+	{
+		let item = generate_item_from_seed(seed, ItemKind::Blue, 7, 100, 0.);
+		if item.rarity == Rarity::Rare
+		{
+			assert!(item.prefixes.len() as i32 <= MAX_RARE_AFFIXES);
+			assert!(item.suffixes.len() as i32 <= MAX_RARE_AFFIXES);
+			assert!(item.prefixes.len() + item.suffixes.len() >= MIN_RARE_AFFIXES as usize);
+		}
+	}
+}
+
+#[test]
+fn overheal_shield_test()
+{
+	let mut values = StatValues::default();
+	values.max_life = 100.;
+	let mut stats = Stats::new(values);
+	stats.life = 130.;
+	stats.clamp_life();
+	assert_eq!(stats.life, 100.);
+	assert_eq!(stats.shield, 0.);
+
+	stats.values.overheal_shield = true;
+	stats.life = 130.;
+	stats.clamp_life();
+	assert_eq!(stats.life, 100.);
+	assert_eq!(stats.shield, 30.);
+
+	stats.life = 200.;
+	stats.clamp_life();
+	assert_eq!(stats.shield, OVERHEAL_MAX * 100.);
+}
+
+#[test]
+fn ailment_scaling_test()
+{
+	let mut rng = StdRng::seed_from_u64(0);
+	let mut target_values = StatValues::default();
+	target_values.max_life = 1000.;
+
+	let mut values = StatValues::default();
+	values.skill_duration = 1.;
+	values.cold_damage = 100.;
+	values.fire_damage = 10.;
+	values.chance_to_freeze = 1.;
+	values.chance_to_ignite = 1.;
+	values.ignite_propagate = true;
+
+	let mut freeze_time = |values: &StatValues| {
+		let mut target = Stats::new(target_values);
+		target.apply_damage(values, 0., &mut rng);
+		target.freeze_time
+	};
+	let base = freeze_time(&values);
+	assert!(base > 0.);
+	// Freeze only has a duration, so only the duration increase scales it.
+	values.increased_ailment_effect = 1.;
+	assert_eq!(freeze_time(&values), base);
+	values.increased_ailment_duration = 1.;
+	assert!((freeze_time(&values) - 2. * base).abs() < 1e-6);
+
+	let mut target = Stats::new(target_values);
+	let ignite = target
+		.apply_damage(&values, 0., &mut rng)
+		.ignite_propagation;
+	assert!((ignite.effect - 2. * 10. * DT).abs() < 1e-6);
+	assert!((ignite.duration - 4.).abs() < 1e-6);
+
+	// Propagated ailments were already scaled by the hit that caused them.
+	let mut propagated = values;
+	propagated.cold_damage = 0.;
+	propagated.fire_damage = 0.;
+	propagated.ignite_propagate_value = ignite;
+	let mut other = Stats::new(target_values);
+	other.apply_damage(&propagated, 0., &mut rng);
+	assert_eq!(other.ignite_instances[0].rate, ignite.effect);
+	assert_eq!(
+		other.ignite_instances[0].time_to_remove,
+		ignite.duration as f64
+	);
+}
+
+#[test]
+fn regen_delay_test()
+{
+	let mut values = StatValues::default();
+	values.max_life = 100.;
+	values.life_regen = 10.;
+	let mut stats = Stats::new(values);
+	stats.life = 50.;
+	stats.last_damaged_time = 1.;
+
+	// Regen is paused inside the delay, but leech still heals.
+	stats.logic(2., 2.);
+	assert_eq!(stats.life, 50.);
+	stats.life_leech_instances.push(RateInstance {
+		rate: 5.,
+		time_to_remove: 10.,
+	});
+	stats.logic(2., 2.);
+	assert_eq!(stats.life, 55.);
+
+	// Regen resumes once the delay runs out.
+	stats.life_leech_instances.clear();
+	stats.logic(3.5, 2.);
+	assert!((stats.life - (55. + 10. * DT)).abs() < 1e-4);
+
+	// A delay of 0 turns the suppression off.
+	stats.logic(2., 0.);
+	assert!((stats.life - (55. + 20. * DT)).abs() < 1e-4);
+}
+
+#[test]
+fn blade_blade_damage_test()
+{
+	let mut blade_blade = BladeBlade::new();
+	blade_blade.num_blades = 1;
+	let (interval, damage) = blade_blade.hit_interval_and_damage(0.);
+	assert_eq!(interval, BLADE_BLADE_CADENCE);
+	assert_eq!(damage, 1.);
+
+	let mut last_damage = damage;
+	let mut last_dps = damage / interval;
+	for num_blades in 2..10
+	{
+		blade_blade.num_blades = num_blades;
+		let (interval, damage) = blade_blade.hit_interval_and_damage(0.);
+		assert!(damage > last_damage);
+		assert!(damage / interval > last_dps);
+		last_damage = damage;
+		last_dps = damage / interval;
+	}
+
+	// Capped hit rate is compensated by extra damage per hit.
+	blade_blade.num_blades = 1;
+	let (interval, damage) = blade_blade.hit_interval_and_damage(9.);
+	assert_eq!(interval, BLADE_BLADE_MIN_HIT_INTERVAL);
+	assert!((damage / interval - 1. / (BLADE_BLADE_CADENCE / 10.)).abs() < 1e-3);
+}
+
+#[test]
+fn unique_pool_categories_test()
+{
+	let uniques: Vec<_> = unique_pool().iter().map(|desc| (desc.make)()).collect();
+	for category in [ItemCategory::Ring, ItemCategory::Amulet, ItemCategory::Belt]
+	{
+		assert!(uniques.iter().any(|item| item.category == category));
+	}
+	for archetype in [Archetype::Pyromancer, Archetype::Guardian]
+	{
+		let inventory = archetype.starting_inventory();
+		for (slot, item) in inventory.slots.iter().enumerate()
+		{
+			if let Some(item) = item
+			{
+				assert!(item.fits_slot(slot));
+			}
+		}
+	}
+}
+
+#[test]
+fn misplaced_items_test()
+{
+	let ring = Archetype::Pyromancer.starting_inventory().slots[1].clone();
+	let amulet = Archetype::Pyromancer.starting_inventory().slots[0].clone();
+
+	// An inventory from before amulets and belts, with rings everywhere.
+	let mut inventory = Inventory::new();
+	for slot in 0..7
+	{
+		inventory.slots[slot] = ring.clone();
+	}
+	inventory.slots[8] = amulet.clone();
+
+	let (new_inventory, overflow) = inventory.with_misplaced_stored();
+	assert!(new_inventory.slots[0].is_none());
+	assert!(new_inventory.slots[3].is_none());
+	for slot in [1, 2, 4, 5, 6, 7]
+	{
+		assert!(new_inventory.slots[slot].is_some());
+	}
+	assert_eq!(new_inventory.slots[8].as_ref().unwrap().name[0], "Glowing");
+	assert_eq!(overflow.len(), 1);
+
+	let (_, overflow) = Archetype::Guardian
+		.starting_inventory()
+		.with_misplaced_stored();
+	assert!(overflow.is_empty());
+}
+
+#[test]
+fn with_build_test()
+{
+	let ring = Archetype::Pyromancer.starting_inventory().slots[1].clone();
+	let mut inventory = Archetype::Guardian.starting_inventory();
+	inventory.slots[6] = ring.clone();
+	inventory.slots[7] = ring.clone();
+
+	// The ring doesn't fit the amulet slot, and the displaced ring fills the last storage slot.
+	let (new_inventory, overflow, skipped) = inventory.with_build(&[ring.clone(), ring.clone()], 1);
+	assert_eq!(skipped, 1);
+	assert!(overflow.is_empty());
+	assert!(new_inventory.slots[1]
+		.as_ref()
+		.unwrap()
+		.same_item(ring.as_ref().unwrap()));
+	assert_eq!(new_inventory.slots[8].as_ref().unwrap().name[0], "Sturdy");
+
+	let (_, overflow, _) = new_inventory.with_build(&[None, None], 1);
+	assert_eq!(overflow.len(), 1);
+
+	// Items above the level are skipped too.
+	let mut high_ring = ring.clone().unwrap();
+	high_ring.level_requirement = 5;
+	let (high_inventory, _, skipped) = inventory.with_build(&[None, Some(high_ring)], 4);
+	assert_eq!(skipped, 1);
+	assert!(high_inventory.slots[1]
+		.as_ref()
+		.unwrap()
+		.same_item(inventory.slots[1].as_ref().unwrap()));
+}
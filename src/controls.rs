@@ -14,13 +14,24 @@ pub enum Action
 	MoveDown,
 	Jump,
 	BladeBlade,
+	CorpseExplosion,
 	Inventory,
+	CycleTarget,
 	UILeft,
 	UIRight,
 	UIUp,
 	UIDown,
 	UIAccept,
 	UICancel,
+	UIDrop,
+	UILoadout1,
+	UILoadout2,
+	UILoadout3,
+	UIPreview,
+	UIExport,
+	UIImport,
+	UIFilter,
+	UIReroll,
 }
 
 impl Action
@@ -35,13 +46,24 @@ impl Action
 			Action::MoveDown => "Move Down",
 			Action::Jump => "Jump",
 			Action::BladeBlade => "BladeBlade",
+			Action::CorpseExplosion => "Corpse Explosion",
 			Action::Inventory => "Show Inventory",
+			Action::CycleTarget => "Cycle Target",
 			Action::UILeft => "UI Left",
 			Action::UIRight => "UI Right",
 			Action::UIUp => "UI Up",
 			Action::UIDown => "UI Down",
 			Action::UIAccept => "UI Accept",
 			Action::UICancel => "UI Cancel",
+			Action::UIDrop => "Drop Item",
+			Action::UILoadout1 => "Loadout 1",
+			Action::UILoadout2 => "Loadout 2",
+			Action::UILoadout3 => "Loadout 3",
+			Action::UIPreview => "Preview Item",
+			Action::UIExport => "Export Build",
+			Action::UIImport => "Import Build",
+			Action::UIFilter => "Filter Items",
+			Action::UIReroll => "Reroll Item",
 		}
 	}
 }
@@ -618,6 +640,24 @@ pub struct Controls
 {
 	action_to_inputs: BTreeMap<Action, [Option<Input>; 2]>,
 	mouse_sensitivity: f32,
+	#[serde(default = "default_stick_deadzone")]
+	stick_deadzone: f32,
+	// Exponent of the stick response curve, values above 1 give finer control near the center.
+	#[serde(default = "default_stick_response")]
+	stick_response: f32,
+	// Seconds the aim takes to catch up with the stick, 0 turns the smoothing off.
+	#[serde(default)]
+	stick_smoothing: f32,
+}
+
+fn default_stick_deadzone() -> f32
+{
+	0.25
+}
+
+fn default_stick_response() -> f32
+{
+	1.
 }
 
 impl Controls
@@ -670,6 +710,13 @@ impl Controls
 				)),
 			],
 		);
+		action_to_inputs.insert(
+			Action::CorpseExplosion,
+			[
+				Some(Input::Keyboard(allegro::KeyCode::E)),
+				Some(Input::JoystickButton(allegro::JoystickButton::X)),
+			],
+		);
 		action_to_inputs.insert(
 			Action::Inventory,
 			[
@@ -677,10 +724,22 @@ impl Controls
 				Some(Input::JoystickButton(allegro::JoystickButton::B)),
 			],
 		);
+		action_to_inputs.insert(
+			Action::CycleTarget,
+			[
+				Some(Input::Keyboard(allegro::KeyCode::Q)),
+				Some(Input::JoystickButton(
+					allegro::JoystickButton::RightShoulder,
+				)),
+			],
+		);
 
 		Self {
 			action_to_inputs: action_to_inputs,
 			mouse_sensitivity: 0.1,
+			stick_deadzone: default_stick_deadzone(),
+			stick_response: default_stick_response(),
+			stick_smoothing: 0.,
 		}
 	}
 
@@ -733,6 +792,9 @@ impl Controls
 		Self {
 			action_to_inputs: action_to_inputs,
 			mouse_sensitivity: 0.1,
+			stick_deadzone: default_stick_deadzone(),
+			stick_response: default_stick_response(),
+			stick_smoothing: 0.,
 		}
 	}
 
@@ -781,10 +843,94 @@ impl Controls
 				Some(Input::JoystickButton(allegro::JoystickButton::Start)),
 			],
 		);
+		action_to_inputs.insert(
+			Action::UIDrop,
+			[
+				Some(Input::Keyboard(allegro::KeyCode::Delete)),
+				Some(Input::JoystickButton(allegro::JoystickButton::Y)),
+			],
+		);
+		action_to_inputs.insert(
+			Action::UILoadout1,
+			[Some(Input::Keyboard(allegro::KeyCode::_1)), None],
+		);
+		action_to_inputs.insert(
+			Action::UILoadout2,
+			[Some(Input::Keyboard(allegro::KeyCode::_2)), None],
+		);
+		action_to_inputs.insert(
+			Action::UILoadout3,
+			[Some(Input::Keyboard(allegro::KeyCode::_3)), None],
+		);
+		action_to_inputs.insert(
+			Action::UIPreview,
+			[
+				Some(Input::Keyboard(allegro::KeyCode::LShift)),
+				Some(Input::Keyboard(allegro::KeyCode::RShift)),
+			],
+		);
+		action_to_inputs.insert(
+			Action::UIExport,
+			[Some(Input::Keyboard(allegro::KeyCode::X)), None],
+		);
+		action_to_inputs.insert(
+			Action::UIImport,
+			[Some(Input::Keyboard(allegro::KeyCode::I)), None],
+		);
+		action_to_inputs.insert(
+			Action::UIFilter,
+			[Some(Input::Keyboard(allegro::KeyCode::Slash)), None],
+		);
+		action_to_inputs.insert(
+			Action::UIReroll,
+			[Some(Input::Keyboard(allegro::KeyCode::R)), None],
+		);
 
 		Self {
 			action_to_inputs: action_to_inputs,
 			mouse_sensitivity: 0.1,
+			stick_deadzone: default_stick_deadzone(),
+			stick_response: default_stick_response(),
+			stick_smoothing: 0.,
+		}
+	}
+
+	pub fn sanitize(&mut self)
+	{
+		if !self.mouse_sensitivity.is_finite()
+		{
+			self.mouse_sensitivity = 0.1;
+		}
+		if !self.stick_deadzone.is_finite()
+		{
+			self.stick_deadzone = default_stick_deadzone();
+		}
+		if !self.stick_response.is_finite()
+		{
+			self.stick_response = default_stick_response();
+		}
+		if !self.stick_smoothing.is_finite()
+		{
+			self.stick_smoothing = 0.;
+		}
+		self.mouse_sensitivity = utils::clamp(self.mouse_sensitivity, 0., 2.);
+		self.stick_deadzone = utils::clamp(self.stick_deadzone, 0., 0.9);
+		self.stick_response = utils::clamp(self.stick_response, 0.5, 3.);
+		self.stick_smoothing = utils::clamp(self.stick_smoothing, 0., 0.5);
+	}
+
+	// Rescales the part of the axis outside the deadzone to 0..1 and applies the response curve.
+	fn stick_value(&self, pos: f32) -> f32
+	{
+		let f = (pos.abs() - self.stick_deadzone) / (1. - self.stick_deadzone);
+		utils::clamp(f, 0., 1.).powf(self.stick_response)
+	}
+
+	pub fn add_missing_actions(&mut self, defaults: &Controls)
+	{
+		for (&action, &inputs) in &defaults.action_to_inputs
+		{
+			self.action_to_inputs.entry(action).or_insert(inputs);
 		}
 	}
 
@@ -865,6 +1011,36 @@ impl ControlsHandler
 		self.controls.mouse_sensitivity = mouse_sensitivity;
 	}
 
+	pub fn get_stick_deadzone(&self) -> f32
+	{
+		self.controls.stick_deadzone
+	}
+
+	pub fn set_stick_deadzone(&mut self, stick_deadzone: f32)
+	{
+		self.controls.stick_deadzone = stick_deadzone;
+	}
+
+	pub fn get_stick_response(&self) -> f32
+	{
+		self.controls.stick_response
+	}
+
+	pub fn set_stick_response(&mut self, stick_response: f32)
+	{
+		self.controls.stick_response = stick_response;
+	}
+
+	pub fn get_stick_smoothing(&self) -> f32
+	{
+		self.controls.stick_smoothing
+	}
+
+	pub fn set_stick_smoothing(&mut self, stick_smoothing: f32)
+	{
+		self.controls.stick_smoothing = stick_smoothing;
+	}
+
 	pub fn get_actions_to_inputs(&self) -> impl Iterator<Item = (&Action, &[Option<Input>; 2])>
 	{
 		self.controls.action_to_inputs.iter()
@@ -875,6 +1051,14 @@ impl ControlsHandler
 		self.controls.action_to_inputs.get(&action)
 	}
 
+	// Action states are cleared after every event, so held actions check the key directly.
+	pub fn is_bound_key(&self, action: Action, keycode: allegro::KeyCode) -> bool
+	{
+		self.get_inputs(action)
+			.map(|inputs| inputs.contains(&Some(Input::Keyboard(keycode))))
+			.unwrap_or(false)
+	}
+
 	pub fn decode_event(&mut self, event: &allegro::Event) -> Vec<(f32, Action)>
 	{
 		match event
@@ -911,14 +1095,15 @@ impl ControlsHandler
 				axis, stick, pos, ..
 			} =>
 			{
-				let dead = 0.25;
+				let dead = self.controls.stick_deadzone;
+				let value = self.controls.stick_value(*pos);
 				if *pos > dead
 				{
 					if let Some(state) = self
 						.input_state
 						.get_mut(&Input::JoystickPosAxis(*stick, *axis))
 					{
-						state.push(*pos);
+						state.push(value);
 					}
 					if let Some(state) = self
 						.input_state
@@ -939,7 +1124,7 @@ impl ControlsHandler
 						.input_state
 						.get_mut(&Input::JoystickNegAxis(*stick, *axis))
 					{
-						state.push(-*pos);
+						state.push(value);
 					}
 				}
 				else
@@ -1076,6 +1261,20 @@ impl ControlsHandler
 		}
 	}
 
+	pub fn clear_joystick_states(&mut self)
+	{
+		for (input, state) in &mut self.input_state
+		{
+			match input
+			{
+				Input::JoystickButton(_)
+				| Input::JoystickPosAxis(_, _)
+				| Input::JoystickNegAxis(_, _) => state.clear(),
+				_ => (),
+			}
+		}
+	}
+
 	pub fn clear_action(&mut self, action: Action, index: usize)
 	{
 		self.controls.action_to_inputs.get_mut(&action).unwrap()[index] = None;
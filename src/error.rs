@@ -3,8 +3,39 @@ use std::{error, fmt};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind
+{
+	Other,
+	Io,
+	MapLoad,
+	Config,
+	Asset,
+	Audio,
+	Entity,
+}
+
+impl fmt::Display for ErrorKind
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		let name = match self
+		{
+			ErrorKind::Other => "Other",
+			ErrorKind::Io => "IO",
+			ErrorKind::MapLoad => "Map load",
+			ErrorKind::Config => "Config",
+			ErrorKind::Asset => "Asset",
+			ErrorKind::Audio => "Audio",
+			ErrorKind::Entity => "Entity",
+		};
+		write!(f, "{}", name)
+	}
+}
+
 pub struct Error
 {
+	kind: ErrorKind,
 	message: String,
 	inner: Option<Box<dyn error::Error + 'static>>,
 	backtrace: Backtrace,
@@ -13,17 +44,31 @@ pub struct Error
 impl Error
 {
 	pub fn new(message: String, inner: Option<Box<dyn error::Error + 'static>>) -> Self
+	{
+		Self::with_kind(ErrorKind::Other, message, inner)
+	}
+
+	pub fn with_kind(
+		kind: ErrorKind, message: String, inner: Option<Box<dyn error::Error + 'static>>,
+	) -> Self
 	{
 		Self {
+			kind: kind,
 			message: message,
 			inner: inner,
 			backtrace: Backtrace::capture(),
 		}
 	}
 
+	pub fn kind(&self) -> ErrorKind
+	{
+		self.kind
+	}
+
 	pub fn context(self, message: String) -> Self
 	{
-		Error::new(message, Some(Box::new(self)))
+		let kind = self.kind;
+		Error::with_kind(kind, message, Some(Box::new(self)))
 	}
 }
 
@@ -32,6 +77,7 @@ impl From<String> for Error
 	fn from(error: String) -> Self
 	{
 		Self {
+			kind: ErrorKind::Other,
 			message: error,
 			inner: None,
 			backtrace: Backtrace::capture(),
@@ -44,6 +90,7 @@ impl From<hecs::NoSuchEntity> for Error
 	fn from(error: hecs::NoSuchEntity) -> Self
 	{
 		Self {
+			kind: ErrorKind::Entity,
 			message: format!("{}", error),
 			inner: Some(Box::new(error)),
 			backtrace: Backtrace::capture(),
@@ -56,6 +103,7 @@ impl From<hecs::ComponentError> for Error
 	fn from(error: hecs::ComponentError) -> Self
 	{
 		Self {
+			kind: ErrorKind::Entity,
 			message: format!("{}", error),
 			inner: Some(Box::new(error)),
 			backtrace: Backtrace::capture(),
@@ -68,6 +116,20 @@ impl From<tiled::Error> for Error
 	fn from(error: tiled::Error) -> Self
 	{
 		Self {
+			kind: ErrorKind::MapLoad,
+			message: format!("{}", error),
+			inner: Some(Box::new(error)),
+			backtrace: Backtrace::capture(),
+		}
+	}
+}
+
+impl From<std::io::Error> for Error
+{
+	fn from(error: std::io::Error) -> Self
+	{
+		Self {
+			kind: ErrorKind::Io,
 			message: format!("{}", error),
 			inner: Some(Box::new(error)),
 			backtrace: Backtrace::capture(),
@@ -79,6 +141,10 @@ impl fmt::Display for Error
 {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
 	{
+		if self.kind != ErrorKind::Other
+		{
+			write!(f, "{} error: ", self.kind)?;
+		}
 		write!(f, "{}", self.message,)?;
 		if let Some(ref inner) = self.inner
 		{
@@ -1,5 +1,5 @@
 use crate::components::{StatValues, Velocity};
-use crate::error::Result;
+use crate::error::{Error, ErrorKind, Result};
 use crate::utils::{XYExt, DT};
 use crate::{
 	astar, atlas, components as comps, controls, game_state, spatial_grid, sprite, ui, utils,
@@ -16,12 +16,138 @@ use rand::prelude::*;
 use serde_derive::{Deserialize, Serialize};
 use tiled;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
 use std::path::Path;
 use std::thread;
 
 const TILE_SIZE: f32 = 32.;
 const PI: f32 = std::f32::consts::PI;
+const TARGET_RANGE: f32 = 256.;
+const SOFT_FALL_LIFE_PENALTY: f32 = 0.1;
+const SPAWN_DELAY: f64 = 0.75;
+const ITEM_DESPAWN_TIME: f64 = 60.;
+const ITEM_DESPAWN_WARNING: f64 = 5.;
+const HINT_DURATION: f64 = 6.;
+const HINT_CRYSTAL_RANGE: f32 = 128.;
+const EVENT_LOG_SIZE: usize = 5;
+const EVENT_LOG_DURATION: f64 = 10.;
+const EVENT_LOG_FADE: f64 = 2.;
+// Resistances at or above this get an icon next to the enemy life bar.
+const HIGH_RESISTANCE: f32 = 0.5;
+// The blade blade orbit doesn't shrink below this fraction near walls.
+const BLADE_WALL_MIN_FACTOR: f32 = 0.4;
+// Chance for a kill to also send a soul to the player.
+const PLAYER_SOUL_CHANCE: f64 = 0.25;
+// Wind-up cue played on the Windup animation event.
+const ENEMY_TELL_SOUND: &str = "data/enemy_tell.ogg";
+// Minimum time between wind-up cues, so packs of archers don't pile up.
+const ENEMY_TELL_INTERVAL: f64 = 0.2;
+const NUM_LOADOUTS: usize = 3;
+// Souls granted for reaching the exit after killing only the elves the crystals started with.
+const GHOST_CLEAR_SOULS: i32 = 25;
+// Souls spent to reroll the affix values of an item in the inventory.
+const SOUL_REROLL_COST: i32 = 10;
+// Slow motion after a rare enemy dies. The duration is in game time, so it lasts longer in real time.
+const SLOWMO_DURATION: f64 = 0.35;
+const SLOWMO_SPEED: f32 = 0.3;
+const SLOWMO_ZOOM: f32 = 1.15;
+// How far ahead enemy projectile paths are predicted, and in how many segments.
+const PROJECTILE_PREVIEW_TIME: f64 = 0.4;
+const PROJECTILE_PREVIEW_SEGMENTS: i32 = 4;
+// Enemies further than this are ignored when auto-facing the nearest one.
+const AUTO_FACE_RANGE: f32 = 256.;
+const PATH_REPLAN_INTERVAL: f64 = 0.5;
+// Fraction of the distance to the player the camera covers per tick, per camera speed step.
+const CAMERA_SPEED_SCALE: f32 = 0.0625;
+// Second chance revive: life restored, invincibility and the push on nearby enemies.
+const SECOND_CHANCE_LIFE: f32 = 0.5;
+const SECOND_CHANCE_INVINCIBILITY: f64 = 2.;
+const SECOND_CHANCE_RADIUS: f32 = 96.;
+const SECOND_CHANCE_KNOCKBACK: f32 = 512.;
+// Holding a direction in the inventory starts repeating after the delay, then steps at the rate.
+const INVENTORY_REPEAT_DELAY: f64 = 0.4;
+const INVENTORY_REPEAT_RATE: f64 = 0.08;
+// Minimum time between repeated selection sounds.
+const INVENTORY_REPEAT_SOUND_INTERVAL: f64 = 0.16;
+const BENCHMARK_DURATION: f64 = 20.;
+const BENCHMARK_ENEMIES: i32 = 200;
+const BENCHMARK_ITEMS: i32 = 100;
+const BENCHMARK_LIGHTS: i32 = 100;
+// The sprite test shows each animation for one loop, but at least this long.
+const SPRITE_TEST_MIN_TIME: f64 = 0.5;
+
+struct Benchmark
+{
+	end_time: f64,
+	logic_times: Vec<f64>,
+	collision_times: Vec<f64>,
+	draw_times: Vec<f64>,
+}
+
+impl Benchmark
+{
+	fn new(state: &game_state::GameState) -> Self
+	{
+		Self {
+			end_time: state.core.get_time() + BENCHMARK_DURATION,
+			logic_times: vec![],
+			collision_times: vec![],
+			draw_times: vec![],
+		}
+	}
+
+	fn summarize(name: &str, times: &[f64]) -> String
+	{
+		let mut sorted = times.to_vec();
+		sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		let avg = sorted.iter().sum::<f64>() / utils::max(1, sorted.len()) as f64;
+		format!(
+			"{name}_avg_ms={:.3} {name}_p50_ms={:.3} {name}_p95_ms={:.3} {name}_p99_ms={:.3}",
+			1000. * avg,
+			1000. * utils::percentile(&sorted, 50.),
+			1000. * utils::percentile(&sorted, 95.),
+			1000. * utils::percentile(&sorted, 99.),
+		)
+	}
+
+	// One line of key=value pairs, printed and appended to benchmark.txt.
+	fn report(&self, num_entities: u32, state: &game_state::GameState) -> Result<()>
+	{
+		let line = format!(
+			"benchmark entities={} logic_ticks={} frames={} {} {} {}",
+			num_entities,
+			self.logic_times.len(),
+			self.draw_times.len(),
+			Benchmark::summarize("logic", &self.logic_times),
+			Benchmark::summarize("collision", &self.collision_times),
+			Benchmark::summarize("draw", &self.draw_times),
+		);
+		println!("{}", line);
+
+		let mut path_buf = utils::user_data_path(&state.core)?;
+		path_buf.push("benchmark.txt");
+		let mut file = std::fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&path_buf)
+			.map_err(|e| {
+				Error::with_kind(
+					ErrorKind::Io,
+					"Couldn't open benchmark.txt".to_string(),
+					Some(Box::new(e)),
+				)
+			})?;
+		writeln!(file, "{}", line).map_err(|e| {
+			Error::with_kind(
+				ErrorKind::Io,
+				"Couldn't write benchmark.txt".to_string(),
+				Some(Box::new(e)),
+			)
+		})?;
+		Ok(())
+	}
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Save
@@ -31,22 +157,62 @@ pub struct Save
 	map_seed: u64,
 	map_file: String,
 	level: i32,
+	#[serde(default)]
+	challenges: Vec<comps::Challenge>,
+	#[serde(default)]
+	archetype: comps::Archetype,
+	// Day of the daily run, if this is one.
+	#[serde(default)]
+	daily: Option<i64>,
+	// Time already survived towards the Survive objective.
+	#[serde(default)]
+	objective_time: f64,
+	#[serde(default)]
+	loadouts: Loadouts,
 }
 
 impl Save
 {
 	pub fn new(seed: u64, state: &game_state::GameState) -> Self
 	{
+		let daily = if state.daily_run
+		{
+			Some(utils::current_day())
+		}
+		else
+		{
+			None
+		};
 		Self {
 			stats: GameStats::new(),
-			inventory: comps::Inventory::new(),
-			map_seed: seed,
+			inventory: state.archetype.starting_inventory(),
+			map_seed: daily.map(|day| daily_seed(day, 1)).unwrap_or(seed),
 			map_file: state.game_spec.start_map.clone(),
 			level: 1,
+			// Everyone faces the same daily run.
+			challenges: if daily.is_some()
+			{
+				vec![]
+			}
+			else
+			{
+				state.challenges.clone()
+			},
+			archetype: state.archetype,
+			daily: daily,
+			objective_time: 0.,
+			loadouts: Loadouts::default(),
 		}
 	}
 }
 
+fn daily_seed(day: i64, level: i32) -> u64
+{
+	(day as u64)
+		.wrapping_mul(0x9e3779b97f4a7c15)
+		.wrapping_add(level as u64)
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone)]
 struct GameStats
 {
@@ -54,6 +220,13 @@ struct GameStats
 	num_blades_cast: i32,
 	num_resets: i32,
 	num_crystals_done: i32,
+	#[serde(default)]
+	souls: i32,
+	#[serde(default)]
+	second_chance_used: bool,
+	// Lethal hits survived with the instant respawn debug option. Debug only, so not saved.
+	#[serde(skip)]
+	near_deaths: i32,
 }
 
 impl GameStats
@@ -65,7 +238,491 @@ impl GameStats
 			num_blades_cast: 0,
 			num_resets: 0,
 			num_crystals_done: 0,
+			souls: 0,
+			second_chance_used: false,
+			near_deaths: 0,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct BestTime
+{
+	map_file: String,
+	time: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct BestTimes
+{
+	times: Vec<BestTime>,
+}
+
+impl BestTimes
+{
+	fn load(state: &game_state::GameState) -> Result<Self>
+	{
+		Ok(utils::load_user_data(&state.core, "best_times.cfg")?.unwrap_or_default())
+	}
+
+	fn save(&self, state: &game_state::GameState) -> Result<()>
+	{
+		utils::save_user_data(&state.core, "best_times.cfg", self)
+	}
+
+	fn get(&self, map_file: &str) -> Option<f64>
+	{
+		self.times
+			.iter()
+			.find(|best_time| best_time.map_file == map_file)
+			.map(|best_time| best_time.time)
+	}
+
+	fn set(&mut self, map_file: &str, time: f64)
+	{
+		if let Some(best_time) = self
+			.times
+			.iter_mut()
+			.find(|best_time| best_time.map_file == map_file)
+		{
+			best_time.time = time;
+		}
+		else
+		{
+			self.times.push(BestTime {
+				map_file: map_file.to_string(),
+				time: time,
+			});
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ChallengeBadge
+{
+	challenge: comps::Challenge,
+	level: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Loadout
+{
+	name: String,
+	slots: Vec<Option<comps::Item>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Loadouts
+{
+	loadouts: Vec<Loadout>,
+}
+
+impl Loadouts
+{
+	fn get(&self, name: &str) -> Option<&Loadout>
+	{
+		self.loadouts.iter().find(|loadout| loadout.name == name)
+	}
+
+	fn set(&mut self, name: &str, slots: Vec<Option<comps::Item>>)
+	{
+		if let Some(loadout) = self
+			.loadouts
+			.iter_mut()
+			.find(|loadout| loadout.name == name)
+		{
+			loadout.slots = slots;
 		}
+		else
+		{
+			self.loadouts.push(Loadout {
+				name: name.to_string(),
+				slots: slots,
+			});
+		}
+	}
+}
+
+// Equipped items plus a few headline stats, written to `build.cfg` so builds can be shared.
+#[derive(Serialize, Deserialize, Clone)]
+struct BuildSummary
+{
+	version: String,
+	archetype: comps::Archetype,
+	level: i32,
+	items: Vec<Option<comps::Item>>,
+	// Informational only, recomputed from the items on import.
+	stats: Vec<(String, f32)>,
+}
+
+impl BuildSummary
+{
+	fn new(
+		inventory: &comps::Inventory, values: &comps::StatValues, archetype: comps::Archetype,
+		level: i32,
+	) -> Self
+	{
+		Self {
+			version: game_state::VERSION.to_string(),
+			archetype: archetype,
+			level: level,
+			items: inventory.slots[..6].to_vec(),
+			stats: vec![
+				("Life".to_string(), values.max_life),
+				("Mana".to_string(), values.max_mana),
+				("Physical Damage".to_string(), values.physical_damage),
+				("Fire Damage".to_string(), values.fire_damage),
+				("Cold Damage".to_string(), values.cold_damage),
+				("Lightning Damage".to_string(), values.lightning_damage),
+				("Area of Effect".to_string(), values.area_of_effect),
+				("Cast Speed".to_string(), values.cast_speed),
+				("Fire Resistance".to_string(), values.fire_resistance),
+				("Cold Resistance".to_string(), values.cold_resistance),
+				(
+					"Lightning Resistance".to_string(),
+					values.lightning_resistance,
+				),
+			],
+		}
+	}
+
+	fn load(state: &game_state::GameState) -> Result<Option<Self>>
+	{
+		utils::load_user_data(&state.core, "build.cfg")
+	}
+
+	fn save(&self, state: &game_state::GameState) -> Result<()>
+	{
+		utils::save_user_data(&state.core, "build.cfg", self)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct DailyRun
+{
+	date: String,
+	level: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct RunHistory
+{
+	badges: Vec<ChallengeBadge>,
+	#[serde(default)]
+	daily_runs: Vec<DailyRun>,
+	// Map files cleared on the ghost route.
+	#[serde(default)]
+	ghost_clears: Vec<String>,
+}
+
+impl RunHistory
+{
+	fn load(state: &game_state::GameState) -> Result<Self>
+	{
+		Ok(utils::load_user_data(&state.core, "run_history.cfg")?.unwrap_or_default())
+	}
+
+	fn save(&self, state: &game_state::GameState) -> Result<()>
+	{
+		utils::save_user_data(&state.core, "run_history.cfg", self)
+	}
+
+	fn record(&mut self, challenge: comps::Challenge, level: i32)
+	{
+		if let Some(badge) = self
+			.badges
+			.iter_mut()
+			.find(|badge| badge.challenge == challenge)
+		{
+			badge.level = badge.level.max(level);
+		}
+		else
+		{
+			self.badges.push(ChallengeBadge {
+				challenge: challenge,
+				level: level,
+			});
+		}
+	}
+
+	fn record_ghost_clear(&mut self, map_file: &str)
+	{
+		if !self.ghost_clears.iter().any(|f| f == map_file)
+		{
+			self.ghost_clears.push(map_file.to_string());
+		}
+	}
+
+	fn record_daily(&mut self, date: String, level: i32)
+	{
+		if let Some(daily_run) = self.daily_runs.iter_mut().find(|run| run.date == date)
+		{
+			daily_run.level = daily_run.level.max(level);
+		}
+		else
+		{
+			self.daily_runs.push(DailyRun {
+				date: date,
+				level: level,
+			});
+		}
+	}
+}
+
+// Highest level reached on a run with the challenge, for showing the badge.
+pub fn challenge_badge(challenge: comps::Challenge, state: &game_state::GameState) -> Option<i32>
+{
+	let run_history = RunHistory::load(state).ok()?;
+	run_history
+		.badges
+		.iter()
+		.find(|badge| badge.challenge == challenge)
+		.map(|badge| badge.level)
+}
+
+pub const GAME_ASSETS: &[game_state::Asset] = &[
+	game_state::Asset::Bitmap("data/circle.png"),
+	game_state::Asset::Sprite("data/damager.cfg"),
+	game_state::Asset::Sprite("data/spawn.cfg"),
+	game_state::Asset::Sprite("data/shatter.cfg"),
+	game_state::Asset::Sprite("data/explosion.cfg"),
+	game_state::Asset::Sprite("data/doodad.cfg"),
+	game_state::Asset::Sprite("data/exit.cfg"),
+	game_state::Asset::Sprite("data/slam.cfg"),
+	game_state::Asset::Sprite("data/archer.cfg"),
+	game_state::Asset::Sprite("data/melee.cfg"),
+	game_state::Asset::Sprite("data/player.cfg"),
+	game_state::Asset::Sprite("data/fireball.cfg"),
+	game_state::Asset::Sprite("data/fire_hit.cfg"),
+	game_state::Asset::Sprite("data/normal_hit.cfg"),
+	game_state::Asset::Sprite("data/cold_hit.cfg"),
+	game_state::Asset::Sprite("data/lightning_hit.cfg"),
+	game_state::Asset::Sprite("data/shadow.cfg"),
+	game_state::Asset::Sprite("data/tree.cfg"),
+	game_state::Asset::Sprite("data/terrain.cfg"),
+	game_state::Asset::Sprite("data/crystal_red.cfg"),
+	game_state::Asset::Sprite("data/crystal_blue.cfg"),
+	game_state::Asset::Sprite("data/crystal_green.cfg"),
+	game_state::Asset::Sprite("data/crystal_pips.cfg"),
+	game_state::Asset::Sprite("data/soul.cfg"),
+	game_state::Asset::Sprite("data/power_sphere.cfg"),
+	game_state::Asset::Sprite("data/inventory_center_bkg.cfg"),
+	game_state::Asset::Sprite("data/inventory_panel_tl.cfg"),
+	game_state::Asset::Sprite("data/inventory_panel_bl.cfg"),
+	game_state::Asset::Sprite("data/inventory_panel_r.cfg"),
+	game_state::Asset::Sprite("data/inventory_cell.cfg"),
+	game_state::Asset::Sprite("data/ring_red.cfg"),
+	game_state::Asset::Sprite("data/ring_yellow.cfg"),
+	game_state::Asset::Sprite("data/ring_blue.cfg"),
+	game_state::Asset::Sprite("data/amulet_red.cfg"),
+	game_state::Asset::Sprite("data/amulet_yellow.cfg"),
+	game_state::Asset::Sprite("data/amulet_blue.cfg"),
+	game_state::Asset::Sprite("data/belt_red.cfg"),
+	game_state::Asset::Sprite("data/belt_yellow.cfg"),
+	game_state::Asset::Sprite("data/belt_blue.cfg"),
+	game_state::Asset::Sprite("data/ring_explode.cfg"),
+	game_state::Asset::Sprite("data/ring_fire.cfg"),
+	game_state::Asset::Sprite("data/ring_cold.cfg"),
+	game_state::Asset::Sprite("data/ring_lightning.cfg"),
+	game_state::Asset::Sprite("data/ring_warmth.cfg"),
+	game_state::Asset::Sprite("data/ring_dodge.cfg"),
+	game_state::Asset::Sprite("data/ring_constrict.cfg"),
+	game_state::Asset::Sprite("data/ring_leech.cfg"),
+	game_state::Asset::Sprite("data/ring_armor.cfg"),
+	game_state::Asset::Sprite("data/item.cfg"),
+	game_state::Asset::Sprite("data/shocked.cfg"),
+	game_state::Asset::Sprite("data/ignited.cfg"),
+	game_state::Asset::Sprite("data/arrow_normal.cfg"),
+	game_state::Asset::Sprite("data/arrow_cold.cfg"),
+	game_state::Asset::Sprite("data/arrow_lightning.cfg"),
+	game_state::Asset::Sprite("data/cold_enchanted.cfg"),
+	game_state::Asset::Sprite("data/lightning_enchanted.cfg"),
+	game_state::Asset::Sprite("data/fire_enchanted.cfg"),
+	game_state::Asset::Sprite("data/platform.cfg"),
+	game_state::Asset::Sprite("data/orb_small.cfg"),
+	game_state::Asset::Sprite("data/orb_big.cfg"),
+];
+
+// Loaded from `debug_item.cfg` in the user data directory, a default one is written out the first
+// time so there's something to edit.
+#[derive(Serialize, Deserialize, Clone)]
+struct DebugItemSpec
+{
+	kind: comps::ItemKind,
+	#[serde(default)]
+	category: comps::ItemCategory,
+	rarity: comps::Rarity,
+	prefixes: Vec<(comps::ItemPrefix, i32, f32)>,
+	suffixes: Vec<(comps::ItemSuffix, i32, f32)>,
+}
+
+impl Default for DebugItemSpec
+{
+	fn default() -> Self
+	{
+		Self {
+			kind: comps::ItemKind::Red,
+			category: comps::ItemCategory::Ring,
+			rarity: comps::Rarity::Rare,
+			prefixes: vec![(comps::ItemPrefix::AddedFireDamage, 10, 1.)],
+			suffixes: vec![(comps::ItemSuffix::IncreasedFireDamage, 10, 1.)],
+		}
+	}
+}
+
+impl DebugItemSpec
+{
+	fn load(state: &game_state::GameState) -> Result<Self>
+	{
+		if let Some(spec) = utils::load_user_data(&state.core, "debug_item.cfg")?
+		{
+			Ok(spec)
+		}
+		else
+		{
+			let spec = DebugItemSpec::default();
+			utils::save_user_data(&state.core, "debug_item.cfg", &spec)?;
+			Ok(spec)
+		}
+	}
+}
+
+// Debug tour through every cached sprite, playing each of its animations in turn.
+struct SpriteTest
+{
+	// Sprite and animation names.
+	entries: Vec<(String, String)>,
+	cur: usize,
+	animation_state: sprite::AnimationState,
+	next_time: f64,
+}
+
+impl SpriteTest
+{
+	fn new(state: &game_state::GameState) -> Self
+	{
+		let mut num_problems = 0;
+		for name in state.get_missing_assets()
+		{
+			println!("Sprite test: '{}' failed to load", name);
+			num_problems += 1;
+		}
+
+		let mut entries = vec![];
+		for name in state.get_sprite_names()
+		{
+			let sprite = match state.get_sprite(&name)
+			{
+				Ok(sprite) => sprite,
+				Err(e) =>
+				{
+					println!("Sprite test: '{}' is not available: {}", name, e);
+					num_problems += 1;
+					continue;
+				}
+			};
+			for animation in sprite.get_animation_names()
+			{
+				// Either of these would panic or hang in advance_state, so they're only reported.
+				if sprite.get_num_frames(&animation) == 0
+				{
+					println!("Sprite test: '{}' '{}' has no frames", name, animation);
+					num_problems += 1;
+				}
+				else if sprite.get_duration_ms(&animation) <= 0.
+				{
+					println!("Sprite test: '{}' '{}' has no duration", name, animation);
+					num_problems += 1;
+				}
+				else
+				{
+					entries.push((name.clone(), animation));
+				}
+			}
+		}
+		println!(
+			"Sprite test: {} animations, {} problems",
+			entries.len(),
+			num_problems
+		);
+
+		let mut sprite_test = Self {
+			entries: entries,
+			cur: 0,
+			animation_state: sprite::AnimationState::new("Default"),
+			next_time: 0.,
+		};
+		sprite_test.start_entry(state);
+		sprite_test
+	}
+
+	fn start_entry(&mut self, state: &game_state::GameState)
+	{
+		if let Some((name, animation)) = self.entries.get(self.cur)
+		{
+			let duration = state
+				.get_sprite(name)
+				.map(|sprite| sprite.get_duration_ms(animation) / 1000.)
+				.unwrap_or(0.);
+			self.animation_state = sprite::AnimationState::new(animation);
+			self.next_time = state.time() + utils::max(SPRITE_TEST_MIN_TIME, duration);
+		}
+	}
+
+	// Returns true once every animation has been shown.
+	fn logic(&mut self, state: &game_state::GameState) -> Result<bool>
+	{
+		if let Some((name, _)) = self.entries.get(self.cur)
+		{
+			let sprite = state.get_sprite(name)?;
+			sprite.advance_state(&mut self.animation_state, DT as f64);
+			if state.time() > self.next_time
+			{
+				self.cur += 1;
+				self.start_entry(state);
+			}
+		}
+		Ok(self.cur >= self.entries.len())
+	}
+
+	fn draw(&self, state: &game_state::GameState) -> Result<()>
+	{
+		if let Some((name, animation)) = self.entries.get(self.cur)
+		{
+			state
+				.core
+				.use_shader(Some(&*state.basic_shader.upgrade().unwrap()))
+				.unwrap();
+			state.core.set_depth_test(None);
+			state.prim.draw_filled_rectangle(
+				0.,
+				0.,
+				state.buffer_width(),
+				state.buffer_height(),
+				Color::from_rgba_f(0., 0., 0., 0.75),
+			);
+
+			let center = Point2::new(state.buffer_width() / 2., state.buffer_height() / 2.);
+			let sprite = state.get_sprite(name)?;
+			sprite.draw_frame_from_state(center, &self.animation_state, state);
+
+			let lh = state.ui_font().get_line_height() as f32;
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(1., 1., 1.),
+				center.x,
+				state.buffer_height() - 2. * lh,
+				FontAlign::Centre,
+				&format!(
+					"{} '{}' ({}/{})",
+					name,
+					animation,
+					self.cur + 1,
+					self.entries.len()
+				),
+			);
+		}
+		Ok(())
 	}
 }
 
@@ -74,6 +731,9 @@ pub struct Game
 	map: Map,
 	subscreens: ui::SubScreens,
 	inventory_screen: Option<InventoryScreen>,
+	advancing: bool,
+	benchmark: Option<Benchmark>,
+	sprite_test: Option<SpriteTest>,
 }
 
 impl Game
@@ -84,61 +744,10 @@ impl Game
 		state.sfx.play_music("data/game.ogg", 0.5, &state.core);
 		//dbg!(100. * comps::ItemPrefix::ManaRegen.get_value(24, 0.15291262));
 		//return Err("Foo".to_string().into());
-		state.cache_bitmap("data/circle.png")?;
-
-		state.cache_sprite("data/damager.cfg")?;
-		state.cache_sprite("data/spawn.cfg")?;
-		state.cache_sprite("data/shatter.cfg")?;
-		state.cache_sprite("data/explosion.cfg")?;
-		state.cache_sprite("data/doodad.cfg")?;
-		state.cache_sprite("data/exit.cfg")?;
-		state.cache_sprite("data/slam.cfg")?;
-		state.cache_sprite("data/archer.cfg")?;
-		state.cache_sprite("data/melee.cfg")?;
-		state.cache_sprite("data/player.cfg")?;
-		state.cache_sprite("data/fireball.cfg")?;
-		state.cache_sprite("data/fire_hit.cfg")?;
-		state.cache_sprite("data/normal_hit.cfg")?;
-		state.cache_sprite("data/cold_hit.cfg")?;
-		state.cache_sprite("data/lightning_hit.cfg")?;
-		state.cache_sprite("data/shadow.cfg")?;
-		state.cache_sprite("data/tree.cfg")?;
-		state.cache_sprite("data/terrain.cfg")?;
-		state.cache_sprite("data/crystal_red.cfg")?;
-		state.cache_sprite("data/crystal_blue.cfg")?;
-		state.cache_sprite("data/crystal_green.cfg")?;
-		state.cache_sprite("data/crystal_pips.cfg")?;
-		state.cache_sprite("data/soul.cfg")?;
-		state.cache_sprite("data/power_sphere.cfg")?;
-		state.cache_sprite("data/inventory_center_bkg.cfg")?;
-		state.cache_sprite("data/inventory_panel_tl.cfg")?;
-		state.cache_sprite("data/inventory_panel_bl.cfg")?;
-		state.cache_sprite("data/inventory_panel_r.cfg")?;
-		state.cache_sprite("data/inventory_cell.cfg")?;
-		state.cache_sprite("data/ring_red.cfg")?;
-		state.cache_sprite("data/ring_yellow.cfg")?;
-		state.cache_sprite("data/ring_blue.cfg")?;
-		state.cache_sprite("data/ring_explode.cfg")?;
-		state.cache_sprite("data/ring_fire.cfg")?;
-		state.cache_sprite("data/ring_cold.cfg")?;
-		state.cache_sprite("data/ring_lightning.cfg")?;
-		state.cache_sprite("data/ring_warmth.cfg")?;
-		state.cache_sprite("data/ring_dodge.cfg")?;
-		state.cache_sprite("data/ring_constrict.cfg")?;
-		state.cache_sprite("data/ring_leech.cfg")?;
-		state.cache_sprite("data/ring_armor.cfg")?;
-		state.cache_sprite("data/item.cfg")?;
-		state.cache_sprite("data/shocked.cfg")?;
-		state.cache_sprite("data/ignited.cfg")?;
-		state.cache_sprite("data/arrow_normal.cfg")?;
-		state.cache_sprite("data/arrow_cold.cfg")?;
-		state.cache_sprite("data/arrow_lightning.cfg")?;
-		state.cache_sprite("data/cold_enchanted.cfg")?;
-		state.cache_sprite("data/lightning_enchanted.cfg")?;
-		state.cache_sprite("data/fire_enchanted.cfg")?;
-		state.cache_sprite("data/platform.cfg")?;
-		state.cache_sprite("data/orb_small.cfg")?;
-		state.cache_sprite("data/orb_big.cfg")?;
+		for asset in GAME_ASSETS
+		{
+			state.cache_asset(*asset)?;
+		}
 
 		let mut rng = thread_rng();
 		let mut save = Save::new(rng.gen(), state);
@@ -151,16 +760,41 @@ impl Game
 			}
 		}
 
+		// Saves from before amulets and belts can have rings in their slots.
+		let (inventory, overflow) = save.inventory.with_misplaced_stored();
+		let mut map = Map::new(
+			inventory,
+			&save.map_file,
+			save.level,
+			save.stats,
+			save.challenges,
+			save.archetype,
+			save.map_seed,
+			save.daily,
+			save.objective_time,
+			VecDeque::new(),
+			save.loadouts,
+			state,
+		)?;
+		let player_pos = map.world.get::<&comps::Position>(map.player)?.pos;
+		for item in overflow
+		{
+			spawn_item(
+				player_pos + Vector3::new(0., 5., 0.),
+				Vector3::new(0., 0., 128.),
+				item,
+				None,
+				&mut map.world,
+			)?;
+		}
+
 		Ok(Self {
-			map: Map::new(
-				save.inventory,
-				&save.map_file,
-				save.level,
-				save.stats,
-				state,
-			)?,
+			map: map,
 			subscreens: ui::SubScreens::new(state),
 			inventory_screen: None,
+			advancing: false,
+			benchmark: None,
+			sprite_test: None,
 		})
 	}
 
@@ -200,43 +834,97 @@ impl Game
 		{
 			state.controls.clear_action_states();
 		}
-		if let Ok(advance) = self.map.logic(state)
+		// The map is frozen while fading out to the next one.
+		if !self.advancing
 		{
-			if advance
+			let logic_start = state.core.get_time();
+			if let Ok(advance) = self.map.logic(state)
 			{
-				let mut rng = thread_rng();
-				loop
+				if advance
 				{
-					let new_map_file = state
-						.game_spec
-						.maps
-						.iter()
-						.filter(|ms| self.map.level + 1 >= ms.min_level)
-						.choose(&mut rng)
-						.unwrap()
-						.map
-						.clone();
-					if *new_map_file == self.map.map_file
-					{
-						continue;
-					}
-					let inventory = (&*self
-						.map
-						.world
-						.get::<&comps::Inventory>(self.map.player)
-						.unwrap())
-						.clone();
-					self.map = Map::new(
-						inventory,
-						&new_map_file,
-						self.map.level + 1,
-						self.map.stats,
-						state,
-					)?;
-					break;
+					self.advancing = true;
+					state.start_fade_out();
+				}
+			}
+			if let Some(benchmark) = self.benchmark.as_mut()
+			{
+				benchmark
+					.logic_times
+					.push(state.core.get_time() - logic_start);
+				benchmark.collision_times.push(self.map.collision_time);
+			}
+		}
+		if let Some(benchmark) = self.benchmark.as_ref()
+		{
+			if state.core.get_time() > benchmark.end_time
+			{
+				if let Err(e) = benchmark.report(self.map.world.len(), state)
+				{
+					println!("Couldn't write benchmark: {}", e);
 				}
+				self.benchmark = None;
+				state.show_toast("Benchmark done");
+			}
+		}
+		if let Some(sprite_test) = self.sprite_test.as_mut()
+		{
+			if sprite_test.logic(state)?
+			{
+				self.sprite_test = None;
+				state.show_toast("Sprite test done");
 			}
 		}
+		if self.advancing && state.fade_out_done()
+		{
+			let (map_seed, mut rng) = match self.map.daily
+			{
+				Some(day) =>
+				{
+					let map_seed = daily_seed(day, self.map.level + 1);
+					(map_seed, StdRng::seed_from_u64(map_seed.wrapping_add(4)))
+				}
+				None => (thread_rng().gen(), StdRng::from_entropy()),
+			};
+			loop
+			{
+				let new_map_file = state
+					.game_spec
+					.maps
+					.iter()
+					.filter(|ms| self.map.level + 1 >= ms.min_level)
+					.choose(&mut rng)
+					.unwrap()
+					.map
+					.clone();
+				if *new_map_file == self.map.map_file
+				{
+					continue;
+				}
+				let inventory = (&*self
+					.map
+					.world
+					.get::<&comps::Inventory>(self.map.player)
+					.unwrap())
+					.clone();
+				self.map = Map::new(
+					inventory,
+					&new_map_file,
+					self.map.level + 1,
+					self.map.stats,
+					self.map.challenges.clone(),
+					self.map.archetype,
+					map_seed,
+					self.map.daily,
+					0.,
+					std::mem::take(&mut self.map.event_log),
+					std::mem::take(&mut self.map.loadouts),
+					state,
+				)?;
+				break;
+			}
+			self.advancing = false;
+			state.start_fade_in();
+		}
 
 		Ok(None)
 	}
@@ -255,6 +943,74 @@ impl Game
 					state.mouse_pos = Point2::new(x as i32, y as i32);
 				}
 			}
+			Event::KeyDown {
+				keycode: KeyCode::F5,
+				..
+			} if state.options.debug_draw =>
+			{
+				if let Err(e) = state.palettes.reload_all(&state.core)
+				{
+					println!("Couldn't reload palettes: {}", e);
+				}
+			}
+			Event::KeyDown {
+				keycode: KeyCode::F6,
+				..
+			} if state.options.debug_draw =>
+			{
+				state.reload_sprites();
+			}
+			Event::KeyDown {
+				keycode: KeyCode::F9,
+				..
+			} if state.options.debug_draw =>
+			{
+				if let Err(e) = self.map.reload_backgrounds(state)
+				{
+					println!("Couldn't reload backgrounds: {}", e);
+				}
+			}
+			Event::KeyDown {
+				keycode: KeyCode::F8,
+				..
+			} if state.options.debug_draw && self.benchmark.is_none() =>
+			{
+				self.map.setup_benchmark(state)?;
+				self.benchmark = Some(Benchmark::new(state));
+				state.show_toast("Benchmark started");
+			}
+			Event::KeyDown {
+				keycode: KeyCode::F10,
+				..
+			} if state.options.debug_draw =>
+			{
+				if self.sprite_test.is_none()
+				{
+					self.sprite_test = Some(SpriteTest::new(state));
+				}
+				else
+				{
+					self.sprite_test = None;
+				}
+			}
+			Event::KeyDown {
+				keycode: KeyCode::F7,
+				..
+			} if state.options.debug_draw => match DebugItemSpec::load(state)
+			{
+				Ok(spec) =>
+				{
+					let item = comps::make_item(
+						spec.kind,
+						spec.category,
+						spec.rarity,
+						spec.prefixes,
+						spec.suffixes,
+					);
+					self.map.spawn_debug_item(item)?;
+				}
+				Err(e) => println!("Couldn't load debug item: {}", e),
+			},
 			_ => (),
 		}
 		if self.subscreens.is_empty()
@@ -316,9 +1072,21 @@ impl Game
 		Ok(None)
 	}
 
+	pub fn time_scale(&self, state: &game_state::GameState) -> f32
+	{
+		self.map.time_scale(state)
+	}
+
 	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
 	{
+		let draw_start = state.core.get_time();
 		self.map.draw(state)?;
+		if let Some(benchmark) = self.benchmark.as_mut()
+		{
+			benchmark
+				.draw_times
+				.push(state.core.get_time() - draw_start);
+		}
 		state
 			.core
 			.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
@@ -326,6 +1094,10 @@ impl Game
 		{
 			inventory_screen.draw(&self.map, state)?;
 		}
+		if let Some(sprite_test) = self.sprite_test.as_ref()
+		{
+			sprite_test.draw(state)?;
+		}
 		if !self.subscreens.is_empty()
 		{
 			state
@@ -351,6 +1123,16 @@ impl Game
 	{
 		self.subscreens.resize(state);
 	}
+
+	// Called when the window is closed mid-run.
+	pub fn save_on_close(&self, state: &game_state::GameState) -> Result<()>
+	{
+		if !self.map.deleted_on_death
+		{
+			self.map.save(state)?;
+		}
+		Ok(())
+	}
 }
 
 fn get_item_color(rarity: comps::Rarity) -> Color
@@ -364,9 +1146,58 @@ fn get_item_color(rarity: comps::Rarity) -> Color
 	}
 }
 
+// The UI font only has ASCII glyphs.
+fn get_affix_comparison(
+	value: Option<f32>, other_value: Option<f32>, colors: game_state::ComparisonColors,
+) -> (&'static str, Color)
+{
+	let better = colors.better();
+	let worse = colors.worse();
+	match (value, other_value)
+	{
+		(Some(value), Some(other_value)) =>
+		{
+			if value > other_value
+			{
+				("+", better)
+			}
+			else if value < other_value
+			{
+				("-", worse)
+			}
+			else
+			{
+				("=", Color::from_rgb_f(0.6, 0.6, 0.6))
+			}
+		}
+		(Some(_), None) => ("new", better),
+		(None, _) => ("absent", worse),
+	}
+}
+
+fn get_item_drop_sound(rarity: comps::Rarity) -> Option<(&'static str, f32)>
+{
+	match rarity
+	{
+		comps::Rarity::Normal => None,
+		comps::Rarity::Magic => Some(("data/ui1.ogg", 0.5)),
+		comps::Rarity::Rare => Some(("data/ui2.ogg", 0.75)),
+		comps::Rarity::Unique => Some(("data/exit.ogg", 1.)),
+	}
+}
+
 struct InventoryScreen
 {
 	selection: i32,
+	message: Option<(String, f64)>,
+	// Set while shift is held, shows the stats the swap would result in.
+	preview: bool,
+	// Lowercase, items not matching it are dimmed.
+	filter: String,
+	typing_filter: bool,
+	// Key held to move the selection, its direction and when it next repeats.
+	held_dir: Option<(KeyCode, Vector2<f32>, f64)>,
+	last_move_sound_time: f64,
 }
 
 const CELL_OFFTS: [Vector2<f32>; 9] = [
@@ -410,16 +1241,131 @@ impl InventoryScreen
 
 		Self {
 			selection: selection,
+			message: None,
+			preview: false,
+			filter: String::new(),
+			typing_filter: false,
+			held_dir: None,
+			last_move_sound_time: 0.,
 		}
 	}
 
 	pub fn input(
-		&mut self, _event: &Event, map: &mut Map, state: &mut game_state::GameState,
+		&mut self, event: &Event, map: &mut Map, state: &mut game_state::GameState,
 	) -> Result<bool>
 	{
+		if self.typing_filter
+		{
+			if let Event::KeyChar {
+				keycode, unichar, ..
+			} = event
+			{
+				match keycode
+				{
+					KeyCode::Enter | KeyCode::PadEnter => self.typing_filter = false,
+					KeyCode::Escape =>
+					{
+						self.typing_filter = false;
+						self.filter.clear();
+					}
+					KeyCode::Backspace =>
+					{
+						self.filter.pop();
+					}
+					_ =>
+					{
+						if unichar.is_alphanumeric() || *unichar == ' '
+						{
+							self.filter.extend(unichar.to_lowercase());
+						}
+					}
+				}
+			}
+			// Typed keys shouldn't also move the player or the selection.
+			state.controls.clear_action_states();
+			state.game_ui_controls.clear_action_states();
+			return Ok(true);
+		}
+
+		if let (Event::KeyUp { keycode, .. }, Some((held_keycode, _, _))) = (event, self.held_dir)
+		{
+			if held_keycode == *keycode
+			{
+				self.held_dir = None;
+			}
+		}
+
+		match event
+		{
+			Event::KeyDown { keycode, .. }
+				if state
+					.game_ui_controls
+					.is_bound_key(controls::Action::UIPreview, *keycode) =>
+			{
+				self.preview = true
+			}
+			Event::KeyUp { keycode, .. }
+				if state
+					.game_ui_controls
+					.is_bound_key(controls::Action::UIPreview, *keycode) =>
+			{
+				self.preview = false
+			}
+			_ => (),
+		}
+
+		if state
+			.game_ui_controls
+			.get_action_state(controls::Action::UIFilter)
+			> 0.5
+		{
+			self.typing_filter = true;
+			self.held_dir = None;
+			return Ok(true);
+		}
+		let loadout_actions = [
+			controls::Action::UILoadout1,
+			controls::Action::UILoadout2,
+			controls::Action::UILoadout3,
+		];
+		for (index, &action) in loadout_actions.iter().enumerate().take(NUM_LOADOUTS)
+		{
+			if state.game_ui_controls.get_action_state(action) > 0.5
+			{
+				self.use_loadout(index, map, state)?;
+				return Ok(true);
+			}
+		}
+		if state
+			.game_ui_controls
+			.get_action_state(controls::Action::UIReroll)
+			> 0.5
+		{
+			self.reroll_item(map, state)?;
+			return Ok(true);
+		}
+		if state
+			.game_ui_controls
+			.get_action_state(controls::Action::UIExport)
+			> 0.5
+		{
+			self.export_build(map, state)?;
+			return Ok(true);
+		}
+		// Importing hands out items, so it's only for planning with debug drawing on.
+		if state
+			.game_ui_controls
+			.get_action_state(controls::Action::UIImport)
+			> 0.5 && state.options.debug_draw
+		{
+			self.import_build(map, state)?;
+			return Ok(true);
+		}
+
 		// LOL! Turned out pretty useful for arbitrary layout UIs...
 		let mut sel_dir = Vector2::zeros();
 		let mut do_swap = false;
+		let mut do_drop = false;
 		if state
 			.game_ui_controls
 			.get_action_state(controls::Action::UIDown)
@@ -455,6 +1401,122 @@ impl InventoryScreen
 		{
 			do_swap = true;
 		}
+		if state
+			.game_ui_controls
+			.get_action_state(controls::Action::UIDrop)
+			> 0.5
+		{
+			do_drop = true;
+		}
+		if sel_dir.norm() > 0.
+		{
+			if let Event::KeyDown { keycode, .. } = event
+			{
+				self.held_dir = Some((*keycode, sel_dir, state.time() + INVENTORY_REPEAT_DELAY));
+			}
+			self.move_selection(sel_dir, false, state)?;
+		}
+
+		if map
+			.world
+			.query_one_mut::<&comps::Corpse>(map.player)
+			.is_ok()
+		{
+			do_swap = false;
+			do_drop = false;
+		}
+
+		if do_swap || do_drop
+		{
+			let old_inventory = (*map.world.get::<&comps::Inventory>(map.player)?).clone();
+			// Dropping leaves the ground item alone and just empties the slot.
+			let nearby_item = match map.nearby_item
+			{
+				Some(_) if do_drop => None,
+				Some(nearby_item_id) =>
+				{
+					Some((*map.world.get::<&comps::Item>(nearby_item_id)?).clone())
+				}
+				None => None,
+			};
+			let picked_up = nearby_item
+				.as_ref()
+				.map(|item| (item.name.join(" "), item.rarity));
+			// Only the first 6 slots are equipped, the rest are storage.
+			if let Some(item) = nearby_item.as_ref()
+			{
+				if self.selection < 6 && !item.can_equip(map.level)
+				{
+					self.message = Some((
+						format!("Requires Height {}m", item.level_requirement * 50),
+						state.time() + 2.,
+					));
+					state.sfx.play_sound("data/ui2.ogg")?;
+					return Ok(true);
+				}
+				if !item.fits_slot(self.selection as usize)
+				{
+					let category = comps::ItemCategory::for_slot(self.selection as usize).unwrap();
+					self.message = Some((
+						format!("Only {}s Fit Here", category.to_str()),
+						state.time() + 2.,
+					));
+					state.sfx.play_sound("data/ui2.ogg")?;
+					return Ok(true);
+				}
+			}
+			let (new_inventory, drop_item) =
+				old_inventory.with_swap(self.selection as usize, nearby_item);
+			let swapped = (map.nearby_item.is_some() && !do_drop) || drop_item.is_some();
+
+			// Persist the swapped inventory first, so that a crash past this point leaves the
+			// item in the save rather than nowhere.
+			if swapped
+			{
+				map.save_with_inventory(&new_inventory, state)?;
+			}
+
+			*map.world.get::<&mut comps::Inventory>(map.player)? = new_inventory;
+			if let Some(nearby_item_id) = map.nearby_item
+			{
+				if !do_drop
+				{
+					map.world.despawn(nearby_item_id)?;
+					map.nearby_item = None;
+				}
+			}
+
+			if let Some(drop_item) = drop_item
+			{
+				let player_pos = map.world.get::<&comps::Position>(map.player).unwrap().pos;
+				let id = spawn_item(
+					player_pos + Vector3::new(0., 5., 0.),
+					Vector3::new(0., 0., 128.),
+					drop_item,
+					None,
+					&mut map.world,
+				)?;
+				map.nearby_item = Some(id);
+			}
+			if swapped
+			{
+				state.sfx.play_sound("data/inventory.ogg")?;
+			}
+			if let Some((name, rarity)) = picked_up
+			{
+				map.log_event(name, get_item_color(rarity), state.time());
+			}
+
+			map.update_player_stats(old_inventory, state);
+		}
+
+		Ok(sel_dir.norm() > 0. || do_swap || do_drop)
+	}
+
+	fn move_selection(
+		&mut self, sel_dir: Vector2<f32>, repeat: bool, state: &mut game_state::GameState,
+	) -> Result<()>
+	{
 		let cur_offt = CELL_OFFTS[self.selection as usize];
 
 		let mut best = (self.selection, std::f32::INFINITY);
@@ -471,68 +1533,199 @@ impl InventoryScreen
 			}
 		}
 		if best.0 != self.selection
+			&& (!repeat
+				|| state.time() - self.last_move_sound_time >= INVENTORY_REPEAT_SOUND_INTERVAL)
 		{
 			state.sfx.play_sound("data/ui1.ogg")?;
+			self.last_move_sound_time = state.time();
 		}
 		self.selection = best.0;
+		Ok(())
+	}
 
+	fn reroll_item(&mut self, map: &mut Map, state: &mut game_state::GameState) -> Result<()>
+	{
 		if map
 			.world
 			.query_one_mut::<&comps::Corpse>(map.player)
 			.is_ok()
 		{
-			do_swap = false;
+			return Ok(());
 		}
-
-		if do_swap
+		let old_inventory = (*map.world.get::<&comps::Inventory>(map.player)?).clone();
+		let mut new_inventory = old_inventory.clone();
+		let item = match new_inventory.slots[self.selection as usize].as_mut()
 		{
-			let drop_item = {
-				let mut inventory = map.world.get::<&mut comps::Inventory>(map.player).unwrap();
-				inventory.slots[self.selection as usize].take()
-			};
-			let mut swapped = false;
-			if let Some(nearby_item_id) = map.nearby_item
+			Some(item)
+				if item.rarity != comps::Rarity::Unique
+					&& !(item.prefixes.is_empty() && item.suffixes.is_empty()) =>
 			{
-				let nearby_item = map.world.remove_one::<comps::Item>(nearby_item_id)?;
-				map.world.despawn(nearby_item_id)?;
-				map.nearby_item = None;
-
-				let mut inventory = map.world.get::<&mut comps::Inventory>(map.player).unwrap();
-				inventory.slots[self.selection as usize] = Some(nearby_item);
-				swapped = true;
+				item
 			}
-
-			if let Some(drop_item) = drop_item
+			_ =>
 			{
-				let player_pos = map.world.get::<&comps::Position>(map.player).unwrap().pos;
-				let id = spawn_item(
-					player_pos + Vector3::new(0., 5., 0.),
-					Vector3::new(0., 0., 128.),
-					drop_item,
-					&mut map.world,
-				)?;
-				map.nearby_item = Some(id);
-				swapped = true;
+				self.message = Some(("Nothing To Reroll".to_string(), state.time() + 2.));
+				state.sfx.play_sound("data/ui2.ogg")?;
+				return Ok(());
 			}
-			if swapped
+		};
+		if map.stats.souls < SOUL_REROLL_COST
+		{
+			self.message = Some((
+				format!("Needs {} Souls", SOUL_REROLL_COST),
+				state.time() + 2.,
+			));
+			state.sfx.play_sound("data/ui2.ogg")?;
+			return Ok(());
+		}
+		item.reroll(&mut thread_rng());
+		map.stats.souls -= SOUL_REROLL_COST;
+		// Saved right away, so resuming can't undo the reroll.
+		map.save_with_inventory(&new_inventory, state)?;
+		*map.world.get::<&mut comps::Inventory>(map.player)? = new_inventory;
+		map.update_player_stats(old_inventory, state);
+		self.message = Some(("Item Rerolled".to_string(), state.time() + 2.));
+		state.sfx.play_sound("data/inventory.ogg")?;
+		Ok(())
+	}
+
+	fn export_build(&mut self, map: &mut Map, state: &mut game_state::GameState) -> Result<()>
+	{
+		let build = BuildSummary::new(
+			&*map.world.get::<&comps::Inventory>(map.player)?,
+			&map.world.get::<&comps::Stats>(map.player)?.values,
+			map.archetype,
+			map.level,
+		);
+		build.save(state)?;
+		self.message = Some(("Build Exported".to_string(), state.time() + 2.));
+		state.sfx.play_sound("data/ui1.ogg")?;
+		Ok(())
+	}
+
+	fn import_build(&mut self, map: &mut Map, state: &mut game_state::GameState) -> Result<()>
+	{
+		if map
+			.world
+			.query_one_mut::<&comps::Corpse>(map.player)
+			.is_ok()
+		{
+			return Ok(());
+		}
+		let build = match BuildSummary::load(state)?
+		{
+			Some(build) => build,
+			None =>
 			{
-				state.sfx.play_sound("data/inventory.ogg")?;
-				map.save(state)?;
+				self.message = Some(("No Build To Import".to_string(), state.time() + 2.));
+				state.sfx.play_sound("data/ui2.ogg")?;
+				return Ok(());
 			}
+		};
+		if build.version != game_state::VERSION
+		{
+			println!(
+				"Importing a build from version {} into {}",
+				build.version,
+				game_state::VERSION
+			);
+		}
+		// Not saved, so the import can be undone by resuming.
+		let old_inventory = (*map.world.get::<&comps::Inventory>(map.player)?).clone();
+		let (new_inventory, overflow, skipped) = old_inventory.with_build(&build.items, map.level);
+		*map.world.get::<&mut comps::Inventory>(map.player)? = new_inventory;
+		map.update_player_stats(old_inventory, state);
+
+		let player_pos = map.world.get::<&comps::Position>(map.player)?.pos;
+		for item in overflow
+		{
+			spawn_item(
+				player_pos + Vector3::new(0., 5., 0.),
+				Vector3::new(0., 0., 128.),
+				item,
+				None,
+				&mut map.world,
+			)?;
+		}
 
-			if let Ok((inventory, stats)) = map
-				.world
-				.query_one_mut::<(&comps::Inventory, &mut comps::Stats)>(map.player)
+		if skipped > 0
+		{
+			self.message = Some((
+				format!("{} Items Can't Be Equipped", skipped),
+				state.time() + 2.,
+			));
+			state.sfx.play_sound("data/ui2.ogg")?;
+		}
+		else
+		{
+			self.message = Some(("Build Imported".to_string(), state.time() + 2.));
+			state.sfx.play_sound("data/inventory.ogg")?;
+		}
+		Ok(())
+	}
+
+	// Holding shift saves the equipped items into the loadout, otherwise it's restored.
+	fn use_loadout(
+		&mut self, index: usize, map: &mut Map, state: &mut game_state::GameState,
+	) -> Result<()>
+	{
+		if map
+			.world
+			.query_one_mut::<&comps::Corpse>(map.player)
+			.is_ok()
+		{
+			return Ok(());
+		}
+		let name = format!("Loadout {}", index + 1);
+		let old_inventory = (*map.world.get::<&comps::Inventory>(map.player)?).clone();
+		if self.preview
+		{
+			map.loadouts.set(&name, old_inventory.slots[..6].to_vec());
+			map.save(state)?;
+			self.message = Some((format!("{} Saved", name), state.time() + 2.));
+			state.sfx.play_sound("data/ui1.ogg")?;
+			return Ok(());
+		}
+
+		let loadout = match map.loadouts.get(&name)
+		{
+			Some(loadout) => loadout,
+			None =>
 			{
-				stats.reset(state.time(), map.level, Some(inventory))
+				self.message = Some((format!("{} Is Empty", name), state.time() + 2.));
+				state.sfx.play_sound("data/ui2.ogg")?;
+				return Ok(());
 			}
-		}
+		};
+		let (new_inventory, missing) = old_inventory.with_loadout(&loadout.slots);
+		map.save_with_inventory(&new_inventory, state)?;
+		*map.world.get::<&mut comps::Inventory>(map.player)? = new_inventory;
+		map.update_player_stats(old_inventory, state);
 
-		Ok(sel_dir.norm() > 0. || do_swap)
+		if missing > 0
+		{
+			self.message = Some((format!("{} Items Are Missing", missing), state.time() + 2.));
+			state.sfx.play_sound("data/ui2.ogg")?;
+		}
+		else
+		{
+			self.message = Some((format!("{} Restored", name), state.time() + 2.));
+			state.sfx.play_sound("data/inventory.ogg")?;
+		}
+		Ok(())
 	}
 
 	pub fn logic(&mut self, map: &mut Map, state: &mut game_state::GameState) -> Result<()>
 	{
+		if let Some((keycode, sel_dir, repeat_time)) = self.held_dir
+		{
+			if state.time() >= repeat_time
+			{
+				self.held_dir = Some((keycode, sel_dir, repeat_time + INVENTORY_REPEAT_RATE));
+				self.move_selection(sel_dir, true, state)?;
+			}
+		}
+
 		let mut inventory = map.world.get::<&mut comps::Inventory>(map.player).unwrap();
 
 		for slot in &mut inventory.slots
@@ -607,6 +1800,19 @@ impl InventoryScreen
 		//	Color::from_rgb_f(0., 0., 0.),
 		//);
 
+		state.core.draw_text(
+			state.ui_font(),
+			Color::from_rgb_f(1., 0.8, 0.3),
+			stats_left + pad / 2.,
+			text_y,
+			FontAlign::Left,
+			&format!(
+				"Est. DPS: {}",
+				utils::format_number(stats.values.estimate_dps(), state.options.number_format)
+			),
+		);
+		text_y += lh;
+
 		let sign = if stats.values.life_regen > 0.
 		{
 			"+"
@@ -623,7 +1829,7 @@ impl InventoryScreen
 			FontAlign::Left,
 			&format!(
 				"Life: {} {} {}/s",
-				stats.values.max_life as i32,
+				utils::format_number(stats.values.max_life, state.options.number_format),
 				sign,
 				utils::nice_float(stats.values.life_regen.abs(), 0)
 			),
@@ -638,7 +1844,7 @@ impl InventoryScreen
 			FontAlign::Left,
 			&format!(
 				"Mana: {} + {}/s",
-				stats.values.max_mana as i32,
+				utils::format_number(stats.values.max_mana, state.options.number_format),
 				utils::nice_float(stats.values.mana_regen, 0)
 			),
 		);
@@ -746,7 +1952,7 @@ impl InventoryScreen
 			text_x,
 			text_y,
 			FontAlign::Left,
-			&format!("{}", stats.values.physical_damage as i32),
+			&utils::format_number(stats.values.physical_damage, state.options.number_format),
 		);
 		text_x += utils::draw_text(
 			&state.core,
@@ -764,7 +1970,7 @@ impl InventoryScreen
 			text_x,
 			text_y,
 			FontAlign::Left,
-			&format!("{}", stats.values.cold_damage as i32),
+			&utils::format_number(stats.values.cold_damage, state.options.number_format),
 		);
 		text_x += utils::draw_text(
 			&state.core,
@@ -782,7 +1988,7 @@ impl InventoryScreen
 			text_x,
 			text_y,
 			FontAlign::Left,
-			&format!("{}", stats.values.fire_damage as i32),
+			&utils::format_number(stats.values.fire_damage, state.options.number_format),
 		);
 		text_x += utils::draw_text(
 			&state.core,
@@ -800,7 +2006,7 @@ impl InventoryScreen
 			text_x,
 			text_y,
 			FontAlign::Left,
-			&format!("{}", stats.values.lightning_damage as i32),
+			&utils::format_number(stats.values.lightning_damage, state.options.number_format),
 		);
 		text_y += lh;
 
@@ -949,33 +2155,236 @@ impl InventoryScreen
 				);
 				text_y += lh;
 			}
+			if item.level_requirement > 0
+			{
+				let color = if item.can_equip(map.level)
+				{
+					Color::from_rgb_f(0.7, 0.7, 0.7)
+				}
+				else
+				{
+					Color::from_rgb_f(0.9, 0.3, 0.3)
+				};
+				state.core.draw_text(
+					state.ui_font(),
+					color,
+					ground_item_center.x,
+					text_y,
+					FontAlign::Centre,
+					&format!("Requires Height {}m", item.level_requirement * 50),
+				);
+				text_y += lh;
+			}
 			text_y += lh / 2.;
 
+			let equipped_item = inventory.slots[self.selection as usize].as_ref();
+			let glyph_x = |mod_string: &str| {
+				ground_item_center.x
+					+ state.ui_font().get_text_width(mod_string) as f32 / 2.
+					+ pad / 2.
+			};
+
 			for (prefix, tier, frac) in &item.prefixes
 			{
+				let mod_string = prefix.get_mod_string(*tier, *frac);
 				state.core.draw_text(
 					state.ui_font(),
 					Color::from_rgb_f(1., 1., 1.),
 					ground_item_center.x,
 					text_y,
 					FontAlign::Centre,
-					&prefix.get_mod_string(*tier, *frac),
+					&mod_string,
 				);
+				if let Some(equipped_item) = equipped_item
+				{
+					let other_value = equipped_item
+						.prefixes
+						.iter()
+						.find(|(other_prefix, _, _)| other_prefix == prefix)
+						.map(|(other_prefix, tier, frac)| other_prefix.get_value(*tier, *frac));
+					let (glyph, color) = get_affix_comparison(
+						Some(prefix.get_value(*tier, *frac)),
+						other_value,
+						state.options.comparison_colors,
+					);
+					state.core.draw_text(
+						state.ui_font(),
+						color,
+						glyph_x(&mod_string),
+						text_y,
+						FontAlign::Left,
+						glyph,
+					);
+				}
 				text_y += lh;
 			}
 			for (suffix, tier, frac) in &item.suffixes
 			{
+				let mod_string = suffix.get_mod_string(*tier, *frac);
 				state.core.draw_text(
 					state.ui_font(),
 					Color::from_rgb_f(1., 1., 1.),
 					ground_item_center.x,
 					text_y,
 					FontAlign::Centre,
-					&suffix.get_mod_string(*tier, *frac),
+					&mod_string,
 				);
+				if let Some(equipped_item) = equipped_item
+				{
+					let other_value = equipped_item
+						.suffixes
+						.iter()
+						.find(|(other_suffix, _, _)| other_suffix == suffix)
+						.map(|(other_suffix, tier, frac)| other_suffix.get_value(*tier, *frac));
+					let (glyph, color) = get_affix_comparison(
+						Some(suffix.get_value(*tier, *frac)),
+						other_value,
+						state.options.comparison_colors,
+					);
+					state.core.draw_text(
+						state.ui_font(),
+						color,
+						glyph_x(&mod_string),
+						text_y,
+						FontAlign::Left,
+						glyph,
+					);
+				}
 				text_y += lh;
 			}
 
+			// Affixes the equipped item has that this one would lose.
+			if let Some(equipped_item) = equipped_item
+			{
+				let absent_color = Color::from_rgb_f(0.5, 0.5, 0.5);
+				for (prefix, tier, frac) in &equipped_item.prefixes
+				{
+					if item
+						.prefixes
+						.iter()
+						.any(|(other_prefix, _, _)| other_prefix == prefix)
+					{
+						continue;
+					}
+					let mod_string = prefix.get_mod_string(*tier, *frac);
+					state.core.draw_text(
+						state.ui_font(),
+						absent_color,
+						ground_item_center.x,
+						text_y,
+						FontAlign::Centre,
+						&mod_string,
+					);
+					let (glyph, color) =
+						get_affix_comparison(None, None, state.options.comparison_colors);
+					state.core.draw_text(
+						state.ui_font(),
+						color,
+						glyph_x(&mod_string),
+						text_y,
+						FontAlign::Left,
+						glyph,
+					);
+					text_y += lh;
+				}
+				for (suffix, tier, frac) in &equipped_item.suffixes
+				{
+					if item
+						.suffixes
+						.iter()
+						.any(|(other_suffix, _, _)| other_suffix == suffix)
+					{
+						continue;
+					}
+					let mod_string = suffix.get_mod_string(*tier, *frac);
+					state.core.draw_text(
+						state.ui_font(),
+						absent_color,
+						ground_item_center.x,
+						text_y,
+						FontAlign::Centre,
+						&mod_string,
+					);
+					let (glyph, color) =
+						get_affix_comparison(None, None, state.options.comparison_colors);
+					state.core.draw_text(
+						state.ui_font(),
+						color,
+						glyph_x(&mod_string),
+						text_y,
+						FontAlign::Left,
+						glyph,
+					);
+					text_y += lh;
+				}
+			}
+
+			// The preview works on copies, so nothing is committed until the swap is done.
+			let preview_y = ground_item_top + panel_height + pad;
+			if self.preview
+			{
+				let (new_inventory, _) =
+					inventory.with_swap(self.selection as usize, Some((*item).clone()));
+				let mut new_stats = (*stats).clone();
+				new_stats.reset(
+					state.time(),
+					map.level,
+					Some(&new_inventory),
+					&map.challenges,
+					&map.map_mods,
+				);
+				let rows = [
+					(
+						"Est. DPS",
+						stats.values.estimate_dps(),
+						new_stats.values.estimate_dps(),
+					),
+					("Life", stats.values.max_life, new_stats.values.max_life),
+					("Mana", stats.values.max_mana, new_stats.values.max_mana),
+					("Armor", stats.values.armor, new_stats.values.armor),
+				];
+				let mut y = preview_y;
+				for (name, old_value, new_value) in rows
+				{
+					let (glyph, color) = get_affix_comparison(
+						Some(new_value),
+						Some(old_value),
+						state.options.comparison_colors,
+					);
+					state.core.draw_text(
+						state.ui_font(),
+						color,
+						ground_item_center.x,
+						y,
+						FontAlign::Centre,
+						&format!(
+							"{}: {} {}",
+							name,
+							utils::format_number(new_value, state.options.number_format),
+							glyph
+						),
+					);
+					y += lh;
+				}
+			}
+			else
+			{
+				state.core.draw_text(
+					state.ui_font(),
+					Color::from_rgb_f(0.5, 0.5, 0.5),
+					ground_item_center.x,
+					preview_y,
+					FontAlign::Centre,
+					&format!(
+						"Hold {} to preview",
+						state
+							.game_ui_controls
+							.get_controls()
+							.get_action_string(controls::Action::UIPreview)
+					),
+				);
+			}
+
 			let appearance = &item.appearance;
 			let sprite = state.get_sprite(&appearance.sprite)?;
 			let palette_index = state.palettes.get_palette_index(
@@ -1044,11 +2453,95 @@ impl InventoryScreen
 				0,
 			);
 		}
+
+		if let Some((message, time)) = self.message.as_ref()
+		{
+			if state.time() < *time
+			{
+				state.core.draw_text(
+					state.ui_font(),
+					Color::from_rgb_f(0.9, 0.3, 0.3),
+					center.x,
+					center.y + panel_height,
+					FontAlign::Centre,
+					message,
+				);
+			}
+		}
+
+		let filter_text = if self.typing_filter
+		{
+			format!("Filter: {}_", self.filter)
+		}
+		else if !self.filter.is_empty()
+		{
+			format!("Filter: {}", self.filter)
+		}
+		else
+		{
+			let ui_controls = state.game_ui_controls.get_controls();
+			format!(
+				"{} to filter, {} to drop, {}/{}/{} to load ({} saves), {} to export",
+				ui_controls.get_action_string(controls::Action::UIFilter),
+				ui_controls.get_action_string(controls::Action::UIDrop),
+				ui_controls.get_action_string(controls::Action::UILoadout1),
+				ui_controls.get_action_string(controls::Action::UILoadout2),
+				ui_controls.get_action_string(controls::Action::UILoadout3),
+				ui_controls.get_action_string(controls::Action::UIPreview),
+				ui_controls.get_action_string(controls::Action::UIExport),
+			)
+		};
+		state.core.draw_text(
+			state.ui_font(),
+			Color::from_rgb_f(0.7, 0.7, 0.7),
+			center.x,
+			center.y + panel_height + lh,
+			FontAlign::Centre,
+			&filter_text,
+		);
+		state.core.draw_text(
+			state.ui_font(),
+			Color::from_rgb_f(0.5, 0.5, 1.),
+			center.x,
+			center.y + panel_height + 2. * lh,
+			FontAlign::Centre,
+			&format!(
+				"Souls: {}, {} to reroll an item for {}",
+				map.stats.souls,
+				state
+					.game_ui_controls
+					.get_controls()
+					.get_action_string(controls::Action::UIReroll),
+				SOUL_REROLL_COST
+			),
+		);
+
 		state
 			.core
 			.use_shader(Some(&*state.palette_shader.upgrade().unwrap()))
 			.unwrap();
 		scene.draw_triangles(state);
+
+		state
+			.core
+			.use_shader(Some(&*state.basic_shader.upgrade().unwrap()))
+			.unwrap();
+		for (item, cell_offt) in inventory.slots.iter().zip(CELL_OFFTS.iter())
+		{
+			if let Some(item) = item
+			{
+				if !item.matches_query(&self.filter)
+				{
+					let pos = center + cell_offt;
+					state.prim.draw_filled_circle(
+						pos.x,
+						pos.y,
+						18.,
+						Color::from_rgba_f(0., 0., 0., 0.7),
+					);
+				}
+			}
+		}
 		Ok(())
 	}
 }
@@ -1255,7 +2748,8 @@ fn spawn_platform(
 }
 
 fn spawn_player(
-	pos: Point3<f32>, inventory: comps::Inventory, world: &mut hecs::World,
+	pos: Point3<f32>, inventory: comps::Inventory, archetype: comps::Archetype,
+	world: &mut hecs::World,
 ) -> Result<hecs::Entity>
 {
 	let entity = world.spawn((
@@ -1271,7 +2765,7 @@ fn spawn_player(
 			mass: 10.,
 			kind: comps::CollisionKind::BigPlayer,
 		},
-		comps::Stats::new(comps::StatValues::new_player()),
+		comps::Stats::new(comps::StatValues::new_player(archetype)),
 		comps::Attack::new(comps::AttackKind::BladeBlade),
 	));
 	world.insert(
@@ -1284,6 +2778,7 @@ fn spawn_player(
 			comps::Jump::new(),
 			comps::AffectedByGravity::new(),
 			comps::BladeBlade::new(),
+			comps::LastSafePosition { pos: pos.xy() },
 			comps::CastsShadow,
 			comps::Controller::new(),
 			comps::OnDeathEffect {
@@ -1304,19 +2799,33 @@ enum EnemyAffix
 	FireEnchanted,
 	LightningEnchanted,
 	MultiShot,
+	Piercing,
+	Ricochet,
+	CurseGround,
+	Molten,
+	Grounded,
+	Frostborn,
 }
 
 fn spawn_enemy(
-	pos: Point3<f32>, crystal_id: hecs::Entity, rarity: comps::Rarity, ranged: bool, level: i32,
+	pos: Point3<f32>, crystal_id: Option<hecs::Entity>, rarity: comps::Rarity, ranged: bool,
+	level: i32, challenges: &[comps::Challenge], map_mods: &[comps::MapMod], time: f64,
 	world: &mut hecs::World, rng: &mut impl Rng,
 ) -> Result<hecs::Entity>
 {
-	let (ai, mut appearance, attack, dead_sound) = if ranged
+	let (mut ai, mut appearance, attack, dead_sound) = if ranged
 	{
 		(
 			comps::AI::new_ranged(pos),
 			comps::Appearance::new("data/archer.cfg"),
-			comps::AttackKind::Fireball(rarity),
+			if level >= 3 && rng.gen_bool(0.2)
+			{
+				comps::AttackKind::ChargedFireball(rarity)
+			}
+			else
+			{
+				comps::AttackKind::Fireball(rarity)
+			},
 			"data/ranged_dead.ogg",
 		)
 	}
@@ -1329,6 +2838,17 @@ fn spawn_enemy(
 			"data/melee_dead.ogg",
 		)
 	};
+	// Stronger enemies notice the player from further away and roam wider.
+	let engage_factor = match rarity
+	{
+		comps::Rarity::Normal => 1.,
+		comps::Rarity::Magic => 1.1,
+		comps::Rarity::Rare => 1.25,
+		comps::Rarity::Unique => unreachable!(),
+	};
+	ai.sense_range *= engage_factor;
+	ai.leash_range *= engage_factor;
+	ai.crystal = crystal_id;
 
 	appearance.palette = Some(
 		match rarity
@@ -1356,7 +2876,7 @@ fn spawn_enemy(
 		comps::Rarity::Unique => unreachable!(),
 	};
 
-	let values = comps::StatValues::new_enemy(level, rarity, ranged);
+	let mut values = comps::StatValues::new_enemy(level, rarity, ranged, challenges, map_mods);
 
 	let all_affixes = [
 		EnemyAffix::ExtraStrong,
@@ -1365,6 +2885,12 @@ fn spawn_enemy(
 		EnemyAffix::FireEnchanted,
 		EnemyAffix::LightningEnchanted,
 		EnemyAffix::MultiShot,
+		EnemyAffix::Piercing,
+		EnemyAffix::Ricochet,
+		EnemyAffix::CurseGround,
+		EnemyAffix::Molten,
+		EnemyAffix::Grounded,
+		EnemyAffix::Frostborn,
 	];
 
 	let mut affixes = vec![];
@@ -1405,7 +2931,7 @@ fn spawn_enemy(
 	let mut inventory = comps::Inventory::new();
 	let mut effects = vec![];
 	let mut inventory_idx = 0;
-	let item_level = (-9. + 0. * level as f32 + 10. * (1.1_f32).powf(level as f32)) as i32;
+	let item_level = comps::enemy_item_level(level);
 	for affix in &affixes
 	{
 		match affix
@@ -1417,6 +2943,8 @@ fn spawn_enemy(
 					-1,
 				));
 				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
 					name: vec![],
 					appearance: comps::Appearance::new("data/ring_red.cfg"),
 					rarity: comps::Rarity::Rare,
@@ -1439,6 +2967,8 @@ fn spawn_enemy(
 					-1,
 				));
 				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
 					name: vec![],
 					appearance: comps::Appearance::new("data/ring_red.cfg"),
 					rarity: comps::Rarity::Rare,
@@ -1461,6 +2991,8 @@ fn spawn_enemy(
 					-1,
 				));
 				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
 					name: vec![],
 					appearance: comps::Appearance::new("data/ring_red.cfg"),
 					rarity: comps::Rarity::Rare,
@@ -1479,6 +3011,8 @@ fn spawn_enemy(
 			EnemyAffix::MultiShot =>
 			{
 				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
 					name: vec![],
 					appearance: comps::Appearance::new("data/ring_red.cfg"),
 					rarity: comps::Rarity::Rare,
@@ -1487,9 +3021,57 @@ fn spawn_enemy(
 				});
 				inventory_idx += 1;
 			}
+			EnemyAffix::Piercing =>
+			{
+				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
+					name: vec![],
+					appearance: comps::Appearance::new("data/ring_red.cfg"),
+					rarity: comps::Rarity::Rare,
+					prefixes: vec![(comps::ItemPrefix::ProjectilePierce, 1, 1.)],
+					suffixes: vec![],
+				});
+				inventory_idx += 1;
+			}
+			EnemyAffix::CurseGround => (),
+			// Immunities are shown as pips above the enemy when drawing.
+			EnemyAffix::Molten =>
+			{
+				values
+					.status_immunities
+					.insert(comps::StatusImmunities::IGNITE);
+			}
+			EnemyAffix::Grounded =>
+			{
+				values
+					.status_immunities
+					.insert(comps::StatusImmunities::SHOCK);
+			}
+			EnemyAffix::Frostborn =>
+			{
+				values
+					.status_immunities
+					.insert(comps::StatusImmunities::FREEZE);
+			}
+			EnemyAffix::Ricochet =>
+			{
+				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
+					name: vec![],
+					appearance: comps::Appearance::new("data/ring_red.cfg"),
+					rarity: comps::Rarity::Rare,
+					prefixes: vec![(comps::ItemPrefix::ProjectileBounce, 1, 1.)],
+					suffixes: vec![],
+				});
+				inventory_idx += 1;
+			}
 			EnemyAffix::ExtraFast =>
 			{
 				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
 					name: vec![],
 					appearance: comps::Appearance::new("data/ring_red.cfg"),
 					rarity: comps::Rarity::Rare,
@@ -1504,6 +3086,8 @@ fn spawn_enemy(
 			EnemyAffix::ExtraStrong =>
 			{
 				inventory.slots[inventory_idx] = Some(comps::Item {
+					level_requirement: 0,
+					category: comps::ItemCategory::Ring,
 					name: vec![],
 					appearance: comps::Appearance::new("data/ring_red.cfg"),
 					rarity: comps::Rarity::Rare,
@@ -1525,7 +3109,7 @@ fn spawn_enemy(
 		dbg!(&affixes);
 		dbg!(&inventory.slots);
 		let mut values = comps::Stats::new(values.clone());
-		values.reset(0., 0, Some(&inventory));
+		values.reset(0., 0, Some(&inventory), &[], &[]);
 		dbg!(values.values);
 	}
 
@@ -1560,6 +3144,16 @@ fn spawn_enemy(
 			offt_y: 16.,
 		},
 	));
+	if affixes.contains(&EnemyAffix::CurseGround)
+	{
+		world.insert_one(entity, comps::CurseGround)?;
+	}
+	world.insert_one(
+		entity,
+		comps::SpawnDelay {
+			active_at: time + SPAWN_DELAY,
+		},
+	)?;
 	Ok(entity)
 }
 
@@ -1635,8 +3229,17 @@ fn spawn_crystal(
 	Ok(entity)
 }
 
+fn player_magic_find(player: hecs::Entity, world: &hecs::World) -> f32
+{
+	world
+		.get::<&comps::Stats>(player)
+		.map(|stats| utils::clamp(stats.values.magic_find, 0., 1.))
+		.unwrap_or(0.)
+}
+
 fn spawn_from_crystal(
-	id: hecs::Entity, level: i32, world: &mut hecs::World, rng: &mut impl Rng,
+	id: hecs::Entity, level: i32, challenges: &[comps::Challenge], map_mods: &[comps::MapMod],
+	magic_find: f32, time: f64, world: &mut hecs::World, rng: &mut impl Rng,
 ) -> Result<()>
 {
 	let mut vals = None;
@@ -1666,10 +3269,14 @@ fn spawn_from_crystal(
 			(1, 0, 0)
 		};
 
+		// Magic find trades pack size for rarer packs.
 		let rarity = [
-			(comps::Rarity::Normal, weights.0),
-			(comps::Rarity::Magic, weights.1),
-			(comps::Rarity::Rare, weights.2),
+			(comps::Rarity::Normal, weights.0 as f32),
+			(comps::Rarity::Magic, weights.1 as f32 * (1. + magic_find)),
+			(
+				comps::Rarity::Rare,
+				weights.2 as f32 * (1. + 2. * magic_find),
+			),
 		]
 		.choose_weighted(rng, |&(_, w)| w)
 		.unwrap()
@@ -1679,6 +3286,10 @@ fn spawn_from_crystal(
 		{
 			count = 1;
 		}
+		else if rng.gen_bool(0.5 * magic_find as f64)
+		{
+			count -= 1;
+		}
 
 		let enemy_rng_base = StdRng::from_seed(rng.gen());
 		for _ in 0..count
@@ -1693,10 +3304,13 @@ fn spawn_from_crystal(
 			)?;
 			spawn_enemy(
 				pos,
-				id,
+				Some(id),
 				rarity,
 				enemy_rng.gen_bool(0.5),
 				level,
+				challenges,
+				map_mods,
+				time,
 				world,
 				&mut enemy_rng,
 			)?;
@@ -1708,18 +3322,45 @@ fn spawn_from_crystal(
 		0
 	};
 
-	if vals.is_some()
+	if vals.is_some()
+	{
+		let crystal = world.query_one_mut::<&mut comps::Crystal>(id).unwrap();
+		crystal.enemies += count;
+		if crystal.first_spawn_time.is_none()
+		{
+			crystal.first_spawn_time = Some(time);
+		}
+	}
+	Ok(())
+}
+
+fn blade_blade_mana_cost(level: i32, values: &comps::StatValues) -> f32
+{
+	let base_cost = 5. + level as f32 * 2.;
+	utils::max(0., base_cost * (1. - values.mana_cost_reduction))
+}
+
+fn corpse_explosion_mana_cost(level: i32, values: &comps::StatValues) -> f32
+{
+	let base_cost = 10. + level as f32 * 3.;
+	utils::max(0., base_cost * (1. - values.mana_cost_reduction))
+}
+
+fn item_despawn_time(rarity: comps::Rarity, state: &game_state::GameState) -> Option<f64>
+{
+	match rarity
 	{
-		world
-			.query_one_mut::<&mut comps::Crystal>(id)
-			.unwrap()
-			.enemies += count;
+		comps::Rarity::Normal | comps::Rarity::Magic if state.options.item_despawn =>
+		{
+			Some(state.time() + ITEM_DESPAWN_TIME)
+		}
+		_ => None,
 	}
-	Ok(())
 }
 
 fn spawn_item(
-	pos: Point3<f32>, vel_pos: Vector3<f32>, item: comps::Item, world: &mut hecs::World,
+	pos: Point3<f32>, vel_pos: Vector3<f32>, item: comps::Item, despawn_time: Option<f64>,
+	world: &mut hecs::World,
 ) -> Result<hecs::Entity>
 {
 	let (palette, color) = match item.rarity
@@ -1757,12 +3398,17 @@ fn spawn_item(
 			offt_y: 6.,
 		},
 	));
+	if let Some(despawn_time) = despawn_time
+	{
+		world.insert_one(entity, comps::TimeToDie::new(despawn_time))?;
+	}
 	Ok(entity)
 }
 
 fn spawn_corpse(
 	pos: Point3<f32>, vel_pos: Vector3<f32>, appearance: comps::Appearance,
-	inventory: comps::Inventory, stats: Option<comps::Stats>, world: &mut hecs::World,
+	inventory: comps::Inventory, stats: Option<comps::Stats>, collision: bool,
+	world: &mut hecs::World,
 ) -> Result<hecs::Entity>
 {
 	let stats = if let Some(mut stats) = stats
@@ -1789,7 +3435,14 @@ fn spawn_corpse(
 		comps::Solid {
 			size: 8.,
 			mass: 1.,
-			kind: comps::CollisionKind::SmallPlayer,
+			kind: if collision
+			{
+				comps::CollisionKind::SmallPlayer
+			}
+			else
+			{
+				comps::CollisionKind::Debris
+			},
 		},
 		stats,
 		comps::Controller::new(),
@@ -1800,7 +3453,7 @@ fn spawn_corpse(
 
 fn spawn_fireball(
 	pos: Point3<f32>, velocity_pos: Vector3<f32>, acceleration_pos: Vector3<f32>,
-	damage_stat_values: comps::StatValues, rarity: comps::Rarity, time: f64,
+	damage_stat_values: comps::StatValues, rarity: comps::Rarity, size: f32, time: f64,
 	world: &mut hecs::World,
 ) -> Result<hecs::Entity>
 {
@@ -1815,7 +3468,7 @@ fn spawn_fireball(
 			pos: acceleration_pos,
 		},
 		comps::Solid {
-			size: 8.,
+			size: size,
 			mass: 0.,
 			kind: comps::CollisionKind::SmallEnemy,
 		},
@@ -1838,7 +3491,25 @@ fn spawn_fireball(
 				sprites.sound,
 			)],
 		},
+		comps::EnemyProjectile,
 	));
+	if damage_stat_values.projectile_pierce > 0
+	{
+		world.insert_one(
+			entity,
+			comps::Pierce::new(damage_stat_values.projectile_pierce),
+		)?;
+	}
+	if damage_stat_values.projectile_bounce > 0
+	{
+		world.insert_one(
+			entity,
+			comps::Bounce {
+				remaining: damage_stat_values.projectile_bounce,
+				last_hit: None,
+			},
+		)?;
+	}
 	Ok(entity)
 }
 
@@ -1866,6 +3537,27 @@ fn spawn_soul(
 	Ok(entity)
 }
 
+fn spawn_player_soul(
+	pos: Point3<f32>, player_pos: Point3<f32>, player: hecs::Entity, world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let entity = world.spawn((
+		comps::Appearance::new("data/soul.cfg"),
+		comps::Position::new(pos),
+		comps::Velocity::new(Vector3::zeros()),
+		comps::Stats::new(comps::StatValues::new_fireball()),
+		comps::OnDeathEffect {
+			effects: vec![comps::Effect::CollectSoul],
+		},
+		comps::Light {
+			color: Color::from_rgb_f(0.8, 0.5, 1.),
+			offt_y: 0.,
+		},
+		comps::PlaceToDie::new_follow(player_pos, player),
+	));
+	Ok(entity)
+}
+
 fn spawn_power_sphere(
 	pos: Point3<f32>, target: Point3<f32>, crystal_id: hecs::Entity, world: &mut hecs::World,
 ) -> Result<hecs::Entity>
@@ -1908,6 +3600,32 @@ fn spawn_damager(
 	Ok(entity)
 }
 
+fn spawn_ground_aoe(
+	pos: Point3<f32>, values: comps::StatValues, time: f64, world: &mut hecs::World,
+) -> Result<hecs::Entity>
+{
+	let mut values = values;
+	values.scale_damage(comps::GROUND_AOE_DAMAGE);
+	values.is_invincible = true;
+	let duration = comps::GROUND_AOE_DURATION * values.skill_duration;
+	let entity = world.spawn((
+		comps::Appearance::new_with_bias("data/fire_enchanted.cfg", -1),
+		comps::Position::new(pos),
+		comps::GroundAoe {
+			radius: comps::GROUND_AOE_RADIUS,
+			interval: comps::GROUND_AOE_INTERVAL,
+			time_to_tick: time,
+		},
+		comps::TimeToDie::new(time + duration as f64),
+		comps::Stats::new(values),
+		comps::Light {
+			color: Color::from_rgb_f(0.6, 0.2, 0.6),
+			offt_y: 0.,
+		},
+	));
+	Ok(entity)
+}
+
 fn spawn_explosion(
 	pos: Point3<f32>, appearance: &str, color: Option<Color>, world: &mut hecs::World,
 ) -> Result<hecs::Entity>
@@ -1943,6 +3661,39 @@ fn vec_to_dir_name(vec: Vector2<f32>) -> &'static str
 	}
 }
 
+fn vec_to_diagonal_dir_name(vec: Vector2<f32>) -> Option<&'static str>
+{
+	if vec.norm() == 0.
+	{
+		return None;
+	}
+	let sector = (vec.y.atan2(vec.x) / (PI / 4.)).round() as i32;
+	match sector.rem_euclid(8)
+	{
+		1 => Some("DownRight"),
+		3 => Some("DownLeft"),
+		5 => Some("UpLeft"),
+		7 => Some("UpRight"),
+		_ => None,
+	}
+}
+
+// Picks the diagonal variant of an animation if the sprite has one, falling back to the 4-direction
+// name otherwise.
+fn directional_animation(prefix: &str, dir: Vector2<f32>, sprite: Option<&sprite::Sprite>)
+	-> String
+{
+	if let (Some(diagonal), Some(sprite)) = (vec_to_diagonal_dir_name(dir), sprite)
+	{
+		let name = format!("{}{}", prefix, diagonal);
+		if sprite.has_animation(&name)
+		{
+			return name;
+		}
+	}
+	format!("{}{}", prefix, vec_to_dir_name(dir))
+}
+
 #[derive(Debug, Copy, Clone)]
 struct GridInner
 {
@@ -1977,11 +3728,14 @@ fn get_float_property(property: &str, obj: &tiled::Object) -> Result<Option<f32>
 		match p
 		{
 			tiled::PropertyValue::FloatValue(v) => Ok(Some(*v)),
-			other => Err(format!(
-				"Invalid value for '{}' in object {:?}: {:?}",
-				property, obj, other
-			)
-			.into()),
+			other => Err(Error::with_kind(
+				ErrorKind::MapLoad,
+				format!(
+					"Invalid value for '{}' in object {:?}: {:?}",
+					property, obj, other
+				),
+				None,
+			)),
 		}
 	}
 	else
@@ -1997,11 +3751,14 @@ fn get_object_property(property: &str, obj: &tiled::Object) -> Result<Option<u32
 		match p
 		{
 			tiled::PropertyValue::ObjectValue(v) => Ok(Some(*v)),
-			other => Err(format!(
-				"Invalid value for '{}' in object {:?}: {:?}",
-				property, obj, other
-			)
-			.into()),
+			other => Err(Error::with_kind(
+				ErrorKind::MapLoad,
+				format!(
+					"Invalid value for '{}' in object {:?}: {:?}",
+					property, obj, other
+				),
+				None,
+			)),
 		}
 	}
 	else
@@ -2010,6 +3767,52 @@ fn get_object_property(property: &str, obj: &tiled::Object) -> Result<Option<u32
 	}
 }
 
+// Parses the 'backgrounds' map property: comma separated "tmx sprite parallax" entries, listed
+// back to front.
+fn get_backgrounds_property(map: &tiled::Map) -> Result<Vec<(String, String, f32)>>
+{
+	let value = match map.properties.get("backgrounds")
+	{
+		Some(tiled::PropertyValue::StringValue(v)) => v,
+		Some(other) =>
+		{
+			return Err(Error::with_kind(
+				ErrorKind::MapLoad,
+				format!("Invalid value for 'backgrounds': {:?}", other),
+				None,
+			))
+		}
+		None => return Ok(vec![]),
+	};
+	let mut backgrounds = vec![];
+	for entry in value.split(',')
+	{
+		let parts: Vec<_> = entry.split_whitespace().collect();
+		if parts.is_empty()
+		{
+			continue;
+		}
+		let parallax = match parts[..]
+		{
+			[_, _, parallax] => parallax.parse::<f32>().ok(),
+			_ => None,
+		};
+		if let Some(parallax) = parallax
+		{
+			backgrounds.push((parts[0].to_string(), parts[1].to_string(), parallax));
+		}
+		else
+		{
+			return Err(Error::with_kind(
+				ErrorKind::MapLoad,
+				format!("Invalid background entry: '{}'", entry),
+				None,
+			));
+		}
+	}
+	Ok(backgrounds)
+}
+
 fn get_object_center(obj: &tiled::Object) -> Result<Point2<f32>>
 {
 	match obj.shape
@@ -2023,7 +3826,125 @@ fn get_object_center(obj: &tiled::Object) -> Result<Point2<f32>>
 			obj.y as f32 + height / 2.,
 		)),
 		tiled::ObjectShape::Point(x, y) => Ok(Point2::new(x, y)),
-		_ => Err(format!("Invalid shape {:?} for object {:?}", obj.shape, obj.id()).into()),
+		_ => Err(Error::with_kind(
+			ErrorKind::MapLoad,
+			format!("Invalid shape {:?} for object {:?}", obj.shape, obj.id()),
+			None,
+		)),
+	}
+}
+
+// Floor corners of each terrain.png frame as bits (top-left, top-right, bottom-right, bottom-left),
+// from the wang set in Terrain.tsx.
+const TERRAIN_CORNERS: [u8; 16] = [0, 15, 12, 9, 3, 6, 4, 8, 1, 2, 5, 10, 11, 7, 14, 13];
+const CORNER_OFFSETS: [(i32, i32); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+// One of the 8 rotations and reflections of a map. Transposing first and then mirroring covers all of them.
+#[derive(Debug, Copy, Clone)]
+struct MapTransform
+{
+	transpose: bool,
+	mirror_x: bool,
+	mirror_y: bool,
+}
+
+impl MapTransform
+{
+	fn from_seed(seed: u64) -> Self
+	{
+		let mut rng = StdRng::seed_from_u64(seed);
+		Self {
+			transpose: rng.gen_bool(0.5),
+			mirror_x: rng.gen_bool(0.5),
+			mirror_y: rng.gen_bool(0.5),
+		}
+	}
+
+	fn transform_size(&self, width: i32, height: i32) -> (i32, i32)
+	{
+		if self.transpose
+		{
+			(height, width)
+		}
+		else
+		{
+			(width, height)
+		}
+	}
+
+	// Transforms a point in a map of the given size, in pixels.
+	fn transform_point(&self, pos: Point2<f32>, width: f32, height: f32) -> Point2<f32>
+	{
+		let (mut x, mut y) = if self.transpose
+		{
+			(pos.y, pos.x)
+		}
+		else
+		{
+			(pos.x, pos.y)
+		};
+		let (width, height) = if self.transpose
+		{
+			(height, width)
+		}
+		else
+		{
+			(width, height)
+		};
+		if self.mirror_x
+		{
+			x = width - x;
+		}
+		if self.mirror_y
+		{
+			y = height - y;
+		}
+		Point2::new(x, y)
+	}
+
+	// Transforms tile coordinates in a map of the given size, in tiles.
+	fn transform_tile(&self, x: i32, y: i32, width: i32, height: i32) -> (i32, i32)
+	{
+		let (mut x, mut y) = if self.transpose { (y, x) } else { (x, y) };
+		let (width, height) = self.transform_size(width, height);
+		if self.mirror_x
+		{
+			x = width - 1 - x;
+		}
+		if self.mirror_y
+		{
+			y = height - 1 - y;
+		}
+		(x, y)
+	}
+
+	// Edge and corner frames are directional, so pick the frame whose corners match the transformed
+	// ones.
+	fn transform_frame(&self, id: i32) -> i32
+	{
+		if id < 0 || id as usize >= TERRAIN_CORNERS.len()
+		{
+			return id;
+		}
+		let corners = TERRAIN_CORNERS[id as usize];
+		let mut new_corners = 0;
+		for (bit, &(x, y)) in CORNER_OFFSETS.iter().enumerate()
+		{
+			if corners & (1 << bit) != 0
+			{
+				let new_corner = self.transform_tile(x, y, 2, 2);
+				let new_bit = CORNER_OFFSETS
+					.iter()
+					.position(|&c| c == new_corner)
+					.unwrap();
+				new_corners |= 1 << new_bit;
+			}
+		}
+		TERRAIN_CORNERS
+			.iter()
+			.position(|&c| c == new_corners)
+			.map(|i| i as i32)
+			.unwrap_or(id)
 	}
 }
 
@@ -2037,6 +3958,8 @@ struct Tiles
 	platforms: Vec<(Point2<f32>, Vec<(Point2<f32>, f64)>)>,
 	crystals: Vec<Point2<f32>>,
 	doodads: Vec<Point2<f32>>,
+	// Background tmx, sprite and parallax factor, back to front.
+	backgrounds: Vec<(String, String, f32)>,
 	sprite: String,
 	level: i32,
 }
@@ -2046,6 +3969,7 @@ impl Tiles
 	fn new(filename: &str, sprite: &str, level: i32) -> Result<Self>
 	{
 		let map = tiled::Loader::new().load_tmx_map(&Path::new(&filename))?;
+		let backgrounds = get_backgrounds_property(&map)?;
 
 		let mut tiles = vec![];
 		let mut width = 0;
@@ -2141,6 +4065,15 @@ impl Tiles
 			}
 		}
 
+		if width == 0 || height == 0 || tiles.len() != width * height
+		{
+			return Err(Error::with_kind(
+				ErrorKind::MapLoad,
+				format!("'{}' has no tile layer", filename),
+				None,
+			));
+		}
+
 		let mut resolved_platforms = vec![];
 		for (start, waypoint_ids) in platforms
 		{
@@ -2166,10 +4099,69 @@ impl Tiles
 			platforms: resolved_platforms,
 			crystals: crystals,
 			doodads: doodads,
+			backgrounds: backgrounds,
 			level: level,
 		})
 	}
 
+	// Loads the background layers, defaulting to the trees.
+	fn load_backgrounds(&self, state: &mut game_state::GameState) -> Result<Vec<(Tiles, f32)>>
+	{
+		if self.backgrounds.is_empty()
+		{
+			let tree = Tiles::new(
+				&format!("data/tree_{}.tmx", self.level % 2),
+				"data/tree.cfg",
+				0,
+			)?;
+			return Ok(vec![(tree, 0.25)]);
+		}
+		let mut layers = vec![];
+		for (filename, sprite, parallax) in &self.backgrounds
+		{
+			state.cache_sprite(sprite)?;
+			layers.push((Tiles::new(filename, sprite, 0)?, *parallax));
+		}
+		Ok(layers)
+	}
+
+	fn transform(&mut self, transform: MapTransform)
+	{
+		let (new_width, new_height) = transform.transform_size(self.width, self.height);
+		let mut new_tiles = vec![0; self.tiles.len()];
+		for y in 0..self.height
+		{
+			for x in 0..self.width
+			{
+				let (new_x, new_y) = transform.transform_tile(x, y, self.width, self.height);
+				new_tiles[(new_y * new_width + new_x) as usize] =
+					transform.transform_frame(self.tiles[(y * self.width + x) as usize]);
+			}
+		}
+
+		let w = self.width as f32 * TILE_SIZE;
+		let h = self.height as f32 * TILE_SIZE;
+		let f = |pos: Point2<f32>| transform.transform_point(pos, w, h);
+		self.start = self.start.map(f);
+		self.exit = self.exit.map(f);
+		for pos in self.crystals.iter_mut().chain(self.doodads.iter_mut())
+		{
+			*pos = f(*pos);
+		}
+		for (start, waypoints) in &mut self.platforms
+		{
+			*start = f(*start);
+			for (pos, _) in waypoints
+			{
+				*pos = f(*pos);
+			}
+		}
+
+		self.tiles = new_tiles;
+		self.width = new_width;
+		self.height = new_height;
+	}
+
 	fn draw(
 		&self, pos: Point2<f32>, scene: &mut Scene, z_shift: f32, state: &game_state::GameState,
 		lit: bool,
@@ -2191,7 +4183,7 @@ impl Tiles
 				let (atlas_bmp, offt) = sprite.get_frame("Default", tile_idx);
 
 				let tile_pos = Vector2::new(x as f32 * TILE_SIZE, y as f32 * TILE_SIZE);
-				let pos = utils::round_point(pos + tile_pos) + offt;
+				let pos = state.snap_point(pos + tile_pos) + offt;
 				scene.add_bitmap(
 					Point3::new(pos.x, pos.y, tile_pos.y + z_shift),
 					atlas_bmp,
@@ -2221,11 +4213,96 @@ impl Tiles
 		TileKind::from_id(self.tiles[tile_y as usize * self.width as usize + tile_x as usize])
 	}
 
+	// Distance to the nearest non-floor point, up to max_dist, sampled along a few directions.
+	fn open_distance(&self, pos: Point2<f32>, max_dist: f32) -> f32
+	{
+		let num_dirs = 16;
+		let step = 4.;
+		let mut best = max_dist;
+		for i in 0..num_dirs
+		{
+			let theta = 2. * std::f32::consts::PI * i as f32 / num_dirs as f32;
+			let dir = Vector2::new(theta.cos(), theta.sin());
+			let mut dist = step;
+			while dist < best
+			{
+				if !self.tile_is_floor(pos + dist * dir)
+				{
+					best = dist;
+					break;
+				}
+				dist += step;
+			}
+		}
+		best
+	}
+
+	// Debug only, the change is not saved to the map file.
+	fn toggle_tile(&mut self, tile: Point2<i32>)
+	{
+		if tile.x < 0 || tile.x >= self.width || tile.y < 0 || tile.y >= self.height
+		{
+			return;
+		}
+		let idx = tile.y as usize * self.width as usize + tile.x as usize;
+		self.tiles[idx] = match TileKind::from_id(self.tiles[idx])
+		{
+			TileKind::Empty => 1,
+			TileKind::Floor => 0,
+		};
+	}
+
 	fn tile_is_floor(&self, pos: Point2<f32>) -> bool
 	{
 		self.get_tile_kind(pos) == TileKind::Floor
 	}
 
+	fn to_tile_coords(&self, pos: Point2<f32>) -> Point2<i32>
+	{
+		Point2::new(
+			utils::clamp(
+				(pos.x / TILE_SIZE).floor() as i32,
+				0,
+				utils::max(0, self.width - 1),
+			),
+			utils::clamp(
+				(pos.y / TILE_SIZE).floor() as i32,
+				0,
+				utils::max(0, self.height - 1),
+			),
+		)
+	}
+
+	fn tile_center(&self, tile: Point2<i32>) -> Point2<f32>
+	{
+		Point2::new(
+			(tile.x as f32 + 0.5) * TILE_SIZE,
+			(tile.y as f32 + 0.5) * TILE_SIZE,
+		)
+	}
+
+	fn tile_coords_are_floor(&self, tile: Point2<i32>) -> bool
+	{
+		if tile.x < 0 || tile.x >= self.width || tile.y < 0 || tile.y >= self.height
+		{
+			return false;
+		}
+		TileKind::from_id(self.tiles[tile.y as usize * self.width as usize + tile.x as usize])
+			== TileKind::Floor
+	}
+
+	fn clamp_to_bounds(&self, pos: Point2<f32>, size: f32) -> Point2<f32>
+	{
+		let max_x = self.width as f32 * TILE_SIZE;
+		let max_y = self.height as f32 * TILE_SIZE;
+		let size_x = utils::min(size, max_x / 2.);
+		let size_y = utils::min(size, max_y / 2.);
+		Point2::new(
+			utils::clamp(pos.x, size_x, max_x - size_x),
+			utils::clamp(pos.y, size_y, max_y - size_y),
+		)
+	}
+
 	pub fn get_escape_dir(
 		&self, pos: Point2<f32>, size: f32, avoid_kind: TileKind,
 	) -> Option<Vector2<f32>>
@@ -2293,26 +4370,79 @@ impl Tiles
 }
 
 fn spawn_crystals_from_map(
-	tiles: &Tiles, map_seed: u64, level: i32, rng: &mut impl Rng, world: &mut hecs::World,
-) -> Result<()>
+	tiles: &Tiles, map_seed: u64, random_kinds: bool, level: i32, challenges: &[comps::Challenge],
+	map_mods: &[comps::MapMod], magic_find: f32, time: f64, rng: &mut impl Rng,
+	world: &mut hecs::World,
+) -> Result<i32>
 {
-	let mut crystal_rng = StdRng::seed_from_u64(map_seed);
+	let mut garrison = 0;
+	let crystal_seed = if random_kinds { rng.gen() } else { map_seed };
+	let mut crystal_rng = StdRng::seed_from_u64(crystal_seed);
 	for crystal in &tiles.crystals
 	{
-		let crystal = spawn_crystal(
-			Point3::new(crystal.x, crystal.y, 0.),
-			*[
-				comps::ItemKind::Red,
-				comps::ItemKind::Blue,
-				comps::ItemKind::Green,
-			]
-			.choose(&mut crystal_rng)
-			.unwrap(),
-			world,
-		)?;
-		spawn_from_crystal(crystal, level, world, rng)?;
+		let crystal = spawn_crystal(
+			Point3::new(crystal.x, crystal.y, 0.),
+			*[
+				comps::ItemKind::Red,
+				comps::ItemKind::Blue,
+				comps::ItemKind::Green,
+			]
+			.choose(&mut crystal_rng)
+			.unwrap(),
+			world,
+		)?;
+		spawn_from_crystal(
+			crystal, level, challenges, map_mods, magic_find, time, world, rng,
+		)?;
+		garrison += world.get::<&comps::Crystal>(crystal)?.enemies;
+	}
+	Ok(garrison)
+}
+
+// Where a pathing enemy should head to reach the goal. The path is kept between ticks, and only
+// replanned when the goal tile changes, the enemy strays off it or it gets stale.
+fn path_move_pos(
+	ai: &mut comps::AI, pos: Point2<f32>, goal: Point2<f32>, tiles: &Tiles,
+	astar: &mut astar::AStarContext, time: f64,
+) -> Point2<f32>
+{
+	if !ai.pathing || !tiles.tile_is_floor(pos)
+	{
+		return goal;
+	}
+	let from = tiles.to_tile_coords(pos);
+	let to = tiles.to_tile_coords(goal);
+	if from == to
+	{
+		ai.path.clear();
+		return goal;
+	}
+	// Drop the tiles already walked past.
+	let strayed = match ai.path.iter().position(|&tile| tile == from)
+	{
+		Some(idx) =>
+		{
+			ai.path.truncate(idx + 1);
+			false
+		}
+		None => !ai.path.is_empty(),
+	};
+	if strayed || to != ai.path_goal || time > ai.next_path_time
+	{
+		ai.path = astar.solve(from, to, |tile| !tiles.tile_coords_are_floor(tile), |_| 0.);
+		ai.path_goal = to;
+		ai.next_path_time = time + PATH_REPLAN_INTERVAL;
+	}
+	// The last entry is the starting tile, and the one before it is the destination when they're
+	// adjacent.
+	if ai.path.len() > 2
+	{
+		tiles.tile_center(ai.path[ai.path.len() - 2])
+	}
+	else
+	{
+		goal
 	}
-	Ok(())
 }
 
 struct Map
@@ -2320,7 +4450,8 @@ struct Map
 	world: hecs::World,
 	player: hecs::Entity,
 	tiles: Tiles,
-	bkg_tiles: Tiles,
+	bkg_layers: Vec<(Tiles, f32)>,
+	astar: astar::AStarContext,
 	camera_pos: comps::Position,
 	camera_lookahead: Vector2<f32>,
 	show_depth: bool,
@@ -2333,19 +4464,61 @@ struct Map
 	stats: GameStats,
 	map_file: String,
 	deleted_on_death: bool,
+	active_inventory: Option<comps::Inventory>,
+	challenges: Vec<comps::Challenge>,
+	map_mods: Vec<comps::MapMod>,
+	archetype: comps::Archetype,
+	daily: Option<i64>,
+	target: Option<hecs::Entity>,
+	start_time: f64,
+	clear_time: Option<f64>,
+	best_time: Option<f64>,
+	new_best_time: bool,
+	hint: Option<(game_state::Hint, f64, Option<Point2<f32>>)>,
+	event_log: VecDeque<(String, Color, f64)>,
+	// Saved with the run, so loadouts only hold items this run found.
+	loadouts: Loadouts,
+	// Items dropped by crystals, oldest first.
+	dropped_items: VecDeque<hecs::Entity>,
+	last_tell_time: f64,
+	// Kill count when the crystals spawned, their total garrison, and whether no crystal has been
+	// elevated since.
+	ghost_kills: i32,
+	ghost_garrison: i32,
+	ghost_route: bool,
+	ghost_clear: bool,
+	ghost_badge: bool,
+	slowmo_until: f64,
+	second_chance_ready: bool,
+	objective: comps::Objective,
+	// Latched once the objective is met and the exit spawns.
+	objective_done: bool,
+	objective_start_time: f64,
+	elite: Option<hecs::Entity>,
+	// Time spent in collision detection on the last tick, for the benchmark.
+	collision_time: f64,
 }
 
 impl Map
 {
 	fn new(
 		inventory: comps::Inventory, map_file: &str, level: i32, stats: GameStats,
-		state: &mut game_state::GameState,
+		challenges: Vec<comps::Challenge>, archetype: comps::Archetype, map_seed: u64,
+		daily: Option<i64>, objective_time: f64, event_log: VecDeque<(String, Color, f64)>,
+		loadouts: Loadouts, state: &mut game_state::GameState,
 	) -> Result<Self>
 	{
 		let mut world = hecs::World::new();
+		let mut rng = thread_rng();
 
-		let tiles = Tiles::new(map_file, "data/terrain.cfg", level)?;
-		let bkg_tiles = Tiles::new(&format!("data/tree_{}.tmx", level % 2), "data/tree.cfg", 0)?;
+		let mut tiles = Tiles::new(map_file, "data/terrain.cfg", level)?;
+		// Daily runs ignore the layout options so everyone gets the same maps.
+		if state.options.transform_maps || daily.is_some()
+		{
+			tiles.transform(MapTransform::from_seed(map_seed.wrapping_add(1)));
+		}
+		let bkg_layers = tiles.load_backgrounds(state)?;
+		let map_mods = comps::roll_map_mods(map_seed.wrapping_add(2));
 
 		for (start, waypoints) in &tiles.platforms
 		{
@@ -2363,22 +4536,86 @@ impl Map
 			spawn_doodad(Point3::new(pos.x, pos.y, 0.), level, state, &mut world)?;
 		}
 
-		let mut rng = thread_rng();
-
-		tiles.exit.expect(&format!("No exit in map: {}!", map_file));
-		let start = tiles
-			.start
-			.expect(&format!("No start in map: {}!", map_file));
+		tiles.exit.ok_or_else(|| {
+			Error::with_kind(
+				ErrorKind::MapLoad,
+				format!("No exit in map: {}!", map_file),
+				None,
+			)
+		})?;
+		let start = tiles.start.ok_or_else(|| {
+			Error::with_kind(
+				ErrorKind::MapLoad,
+				format!("No start in map: {}!", map_file),
+				None,
+			)
+		})?;
 		let spawn_pos = Point3::new(start.x, start.y, 0.);
-		let player = spawn_player(spawn_pos, inventory, &mut world)?;
-		let map_seed = rng.gen::<u64>();
-		spawn_crystals_from_map(&tiles, map_seed, level, &mut rng, &mut world)?;
+		if map_mods.contains(&comps::MapMod::ExtraCrystal)
+		{
+			let mut mod_rng = StdRng::seed_from_u64(map_seed.wrapping_add(3));
+			let mut candidates = vec![];
+			for y in 0..tiles.height
+			{
+				for x in 0..tiles.width
+				{
+					let tile = Point2::new(x, y);
+					if !tiles.tile_coords_are_floor(tile)
+					{
+						continue;
+					}
+					let center = tiles.tile_center(tile);
+					if (center - start).norm() > 256.
+						&& tiles.open_distance(center, 32.) >= 32.
+						&& tiles.crystals.iter().all(|c| (c - center).norm() > 128.)
+					{
+						candidates.push(center);
+					}
+				}
+			}
+			if let Some(&pos) = candidates.choose(&mut mod_rng)
+			{
+				tiles.crystals.push(pos);
+			}
+		}
+		let player = spawn_player(spawn_pos, inventory, archetype, &mut world)?;
+		let ghost_garrison = spawn_crystals_from_map(
+			&tiles,
+			map_seed,
+			state.options.random_crystal_kinds && daily.is_none(),
+			level,
+			&challenges,
+			&map_mods,
+			player_magic_find(player, &world) + comps::map_mods_magic_find(&map_mods),
+			state.time(),
+			&mut rng,
+			&mut world,
+		)?;
+		let best_time = match BestTimes::load(state)
+		{
+			Ok(best_times) => best_times.get(map_file),
+			Err(e) =>
+			{
+				println!("Couldn't load best times: {}", e);
+				None
+			}
+		};
+		let ghost_badge = match RunHistory::load(state)
+		{
+			Ok(run_history) => run_history.ghost_clears.iter().any(|f| f == map_file),
+			Err(e) =>
+			{
+				println!("Couldn't load run history: {}", e);
+				false
+			}
+		};
 
-		let map = Self {
+		let mut map = Self {
 			world: world,
 			player: player,
+			astar: astar::AStarContext::new(utils::max(tiles.width, tiles.height) as usize),
 			tiles: tiles,
-			bkg_tiles: bkg_tiles,
+			bkg_layers: bkg_layers,
 			camera_pos: comps::Position::new(spawn_pos),
 			camera_lookahead: Vector2::zeros(),
 			show_depth: false,
@@ -2391,34 +4628,602 @@ impl Map
 			stats: stats,
 			map_file: map_file.to_string(),
 			deleted_on_death: false,
+			active_inventory: None,
+			challenges: challenges,
+			map_mods: map_mods,
+			archetype: archetype,
+			daily: daily,
+			target: None,
+			start_time: state.time(),
+			clear_time: None,
+			best_time: best_time,
+			new_best_time: false,
+			hint: None,
+			event_log: event_log,
+			loadouts: loadouts,
+			dropped_items: VecDeque::new(),
+			last_tell_time: 0.,
+			ghost_kills: stats.num_elves_killed,
+			ghost_garrison: ghost_garrison,
+			ghost_route: true,
+			ghost_clear: false,
+			ghost_badge: ghost_badge,
+			slowmo_until: 0.,
+			second_chance_ready: state.options.second_chance_per_map || !stats.second_chance_used,
+			objective: comps::roll_objective(map_seed.wrapping_add(5), level),
+			objective_done: false,
+			objective_start_time: state.time() - objective_time,
+			elite: None,
+			collision_time: 0.,
 		};
+		map.spawn_elite(state)?;
+		map.log_event(
+			format!("Reached {}m", level * 50),
+			Color::from_rgb_f(1.0, 1.0, 0.6),
+			state.time(),
+		);
+		for map_mod in map.map_mods.clone()
+		{
+			map.log_event(
+				format!("Map: {}", map_mod.to_str()),
+				Color::from_rgb_f(0.9, 0.5, 0.3),
+				state.time(),
+			);
+		}
+		map.log_event(
+			format!("Objective: {}", map.objective.to_str()),
+			Color::from_rgb_f(0.6, 1.0, 1.0),
+			state.time(),
+		);
 		map.save(state)?;
 		Ok(map)
 	}
 
+	// The exit guardian waits on the exit for the kill objective.
+	fn spawn_elite(&mut self, state: &game_state::GameState) -> Result<()>
+	{
+		self.elite = None;
+		if self.objective != comps::Objective::KillElite
+		{
+			return Ok(());
+		}
+		let mut rng = StdRng::seed_from_u64(self.map_seed.wrapping_add(6));
+		let exit = self.tiles.exit.unwrap();
+		let elite = spawn_enemy(
+			Point3::new(exit.x, exit.y, 0.),
+			None,
+			comps::Rarity::Rare,
+			rng.gen_bool(0.5),
+			self.level,
+			&self.challenges,
+			&self.map_mods,
+			state.time(),
+			&mut self.world,
+			&mut rng,
+		)?;
+		self.world
+			.get::<&mut comps::OnDeathEffect>(elite)?
+			.effects
+			.push(comps::Effect::BossDeath);
+		self.elite = Some(elite);
+		Ok(())
+	}
+
+	fn objective_complete(&self, state: &game_state::GameState) -> bool
+	{
+		let all_crystals_done = self.num_crystals_done >= self.tiles.crystals.len() as i32;
+		match self.objective
+		{
+			comps::Objective::BreakCrystals => all_crystals_done,
+			comps::Objective::Survive =>
+			{
+				state.time() - self.objective_start_time >= comps::SURVIVE_TIME
+			}
+			comps::Objective::Sneak => self.num_crystals_done == 0 || all_crystals_done,
+			comps::Objective::KillElite => self
+				.elite
+				.map(|elite| !self.world.contains(elite))
+				.unwrap_or(true),
+		}
+	}
+
 	fn save(&self, state: &game_state::GameState) -> Result<()>
+	{
+		let inventory = (&*self.world.get::<&comps::Inventory>(self.player)?).clone();
+		self.save_with_inventory(&inventory, state)
+	}
+
+	fn save_with_inventory(
+		&self, inventory: &comps::Inventory, state: &game_state::GameState,
+	) -> Result<()>
 	{
 		let save = Save {
 			level: self.level,
 			map_seed: self.map_seed,
 			map_file: self.map_file.clone(),
 			stats: self.stats,
-			inventory: (&*self.world.get::<&comps::Inventory>(self.player)?).clone(),
+			inventory: inventory.clone(),
+			challenges: self.challenges.clone(),
+			archetype: self.archetype,
+			daily: self.daily,
+			objective_time: state.time() - self.objective_start_time,
+			loadouts: self.loadouts.clone(),
 		};
 		println!("Saving");
 		utils::save_user_data(&state.core, "save.cfg", &save)
 	}
 
+	fn absorb_projectile_contact(
+		&mut self, id: hecs::Entity, other_id: hecs::Entity,
+	) -> Result<bool>
+	{
+		if let Ok(mut pierce) = self.world.get::<&mut comps::Pierce>(id)
+		{
+			if pierce.remaining > 0
+			{
+				pierce.remaining -= 1;
+				return Ok(true);
+			}
+		}
+		if let Ok(mut bounce) = self.world.get::<&mut comps::Bounce>(id)
+		{
+			if bounce.remaining > 0
+			{
+				let pos = self.world.get::<&comps::Position>(id)?.pos;
+				let other_pos = self.world.get::<&comps::Position>(other_id)?.pos;
+				let diff = (pos - other_pos).xy();
+				if diff.norm() > 0.
+				{
+					bounce.remaining -= 1;
+					let mut velocity = self.world.get::<&mut comps::Velocity>(id)?;
+					let new_velocity = utils::reflect(velocity.pos.xy(), diff.normalize());
+					velocity.pos.set_xy(new_velocity);
+					return Ok(true);
+				}
+			}
+		}
+		Ok(false)
+	}
+
+	fn is_valid_target(&self, id: hecs::Entity, player_pos: Point3<f32>) -> bool
+	{
+		if id == self.player || self.world.get::<&comps::Corpse>(id).is_ok()
+		{
+			return false;
+		}
+		let in_range = self
+			.world
+			.get::<&comps::Position>(id)
+			.map(|position| (position.pos - player_pos).xy().norm() < TARGET_RANGE)
+			.unwrap_or(false);
+		let is_enemy = self
+			.world
+			.get::<&comps::Stats>(id)
+			.map(|stats| !stats.dead && stats.values.team == comps::Team::Enemy)
+			.unwrap_or(false);
+		in_range && is_enemy
+	}
+
+	fn cull_dropped_items(&mut self, max_items: i32) -> Result<()>
+	{
+		let world = &self.world;
+		self.dropped_items
+			.retain(|&id| world.get::<&comps::Item>(id).is_ok());
+		if max_items <= 0
+		{
+			return Ok(());
+		}
+		while self.dropped_items.len() > max_items as usize
+		{
+			// Lowest rarity goes first, oldest first within a rarity. Uniques and the item the player
+			// is standing next to are never culled.
+			let mut best: Option<(usize, i32)> = None;
+			for (i, &id) in self.dropped_items.iter().enumerate()
+			{
+				if Some(id) == self.nearby_item
+				{
+					continue;
+				}
+				let rarity = self.world.get::<&comps::Item>(id)?.rarity;
+				if rarity == comps::Rarity::Unique
+				{
+					continue;
+				}
+				if best
+					.map(|(_, best_rarity)| (rarity as i32) < best_rarity)
+					.unwrap_or(true)
+				{
+					best = Some((i, rarity as i32));
+				}
+			}
+			if let Some((i, _)) = best
+			{
+				let id = self.dropped_items.remove(i).unwrap();
+				self.world.despawn(id)?;
+			}
+			else
+			{
+				break;
+			}
+		}
+		Ok(())
+	}
+
+	fn spawn_debug_item(&mut self, item: comps::Item) -> Result<()>
+	{
+		let player_pos = self.world.get::<&comps::Position>(self.player)?.pos;
+		spawn_item(
+			player_pos + Vector3::new(0., 16., 0.),
+			Vector3::new(0., 0., 128.),
+			item,
+			None,
+			&mut self.world,
+		)?;
+		Ok(())
+	}
+
+	fn is_spawning(&self, id: hecs::Entity, time: f64) -> bool
+	{
+		self.world
+			.get::<&comps::SpawnDelay>(id)
+			.map(|spawn_delay| time < spawn_delay.active_at)
+			.unwrap_or(false)
+	}
+
+	fn player_in_combat(&self) -> bool
+	{
+		self.world
+			.query::<&comps::AI>()
+			.iter()
+			.any(|(_, ai)| match ai.state
+			{
+				comps::AIState::Chase(target) | comps::AIState::Attack(target) =>
+				{
+					target == self.player
+				}
+				_ => false,
+			})
+	}
+
+	// Resets the player's stats after an inventory change, unless swaps are deferred.
+	fn update_player_stats(
+		&mut self, old_inventory: comps::Inventory, state: &game_state::GameState,
+	)
+	{
+		if state.options.defer_item_swaps && self.player_in_combat()
+		{
+			if self.active_inventory.is_none()
+			{
+				self.active_inventory = Some(old_inventory);
+			}
+		}
+		else if let Ok((inventory, stats)) = self
+			.world
+			.query_one_mut::<(&comps::Inventory, &mut comps::Stats)>(self.player)
+		{
+			stats.reset(
+				state.time(),
+				self.level,
+				Some(inventory),
+				&self.challenges,
+				&self.map_mods,
+			)
+		}
+	}
+
+	fn magic_find(&self) -> f32
+	{
+		player_magic_find(self.player, &self.world) + comps::map_mods_magic_find(&self.map_mods)
+	}
+
+	fn log_event(&mut self, text: String, color: Color, time: f64)
+	{
+		self.event_log.push_back((text, color, time));
+		while self.event_log.len() > EVENT_LOG_SIZE
+		{
+			self.event_log.pop_front();
+		}
+	}
+
+	fn update_hints(&mut self, state: &mut game_state::GameState) -> Result<()>
+	{
+		if let Some((hint, end_time, _)) = self.hint
+		{
+			if state.time() < end_time
+			{
+				return Ok(());
+			}
+			state.seen_hints.mark_seen(hint);
+			if let Err(e) = game_state::save_seen_hints(&state.core, &state.seen_hints)
+			{
+				println!("Couldn't save hints: {}", e);
+			}
+			self.hint = None;
+		}
+		if !state.options.show_hints
+		{
+			return Ok(());
+		}
+
+		let player_pos = match self.world.get::<&comps::Position>(self.player)
+		{
+			Ok(position) => position.pos,
+			Err(_) => return Ok(()),
+		};
+		let mut nearest_crystal = None;
+		let mut best_dist = HINT_CRYSTAL_RANGE;
+		for (_, (position, _)) in self
+			.world
+			.query::<(&comps::Position, &comps::Crystal)>()
+			.iter()
+		{
+			let dist = (position.pos - player_pos).xy().norm();
+			if dist < best_dist
+			{
+				best_dist = dist;
+				nearest_crystal = Some(position.pos.xy());
+			}
+		}
+		let exit_open = self.objective_done;
+
+		let candidates = [
+			(game_state::Hint::Move, true, None),
+			(
+				game_state::Hint::BreakCrystal,
+				nearest_crystal.is_some(),
+				nearest_crystal,
+			),
+			(
+				game_state::Hint::Inventory,
+				self.nearby_item.is_some(),
+				None,
+			),
+			(game_state::Hint::Exit, exit_open, self.tiles.exit),
+		];
+		for (hint, triggered, anchor) in candidates
+		{
+			if triggered && !state.seen_hints.has_seen(hint)
+			{
+				self.hint = Some((hint, state.time() + HINT_DURATION, anchor));
+				break;
+			}
+		}
+		Ok(())
+	}
+
+	// Re-reads the background list from the map file, so backgrounds can be swapped while playing.
+	fn reload_backgrounds(&mut self, state: &mut game_state::GameState) -> Result<()>
+	{
+		self.tiles.backgrounds =
+			Tiles::new(&self.map_file, &self.tiles.sprite, self.level)?.backgrounds;
+		self.bkg_layers = self.tiles.load_backgrounds(state)?;
+		Ok(())
+	}
+
 	fn camera_to_world(&self, pos: Point2<f32>, state: &game_state::GameState) -> Point2<f32>
 	{
-		self.camera_pos.pos.xy() + pos.coords
-			- Vector2::new(state.buffer_width() / 2., state.buffer_height() / 2.)
+		// Undo the zoom about the screen center.
+		let center = Vector2::new(state.buffer_width() / 2., state.buffer_height() / 2.);
+		self.camera_pos.pos.xy() + (pos.coords - center) / self.zoom(state)
+	}
+
+	fn blade_blade_radius_factor(
+		&self, pos: Point2<f32>, aoe_radius_factor: f32, state: &game_state::GameState,
+	) -> f32
+	{
+		// Over a platform or in the air there's nothing to clip against.
+		if !state.options.blade_wall_clip || !self.tiles.tile_is_floor(pos)
+		{
+			return 1.;
+		}
+		let radius = comps::BLADE_BLADE_RADIUS * aoe_radius_factor;
+		utils::max(
+			BLADE_WALL_MIN_FACTOR,
+			self.tiles.open_distance(pos, radius) / radius,
+		)
+	}
+
+	// Fills the map with enemies, items and lights to stress the logic and the renderer.
+	fn setup_benchmark(&mut self, state: &game_state::GameState) -> Result<()>
+	{
+		let mut rng = thread_rng();
+		let mut floor_tiles = vec![];
+		for y in 0..self.tiles.height
+		{
+			for x in 0..self.tiles.width
+			{
+				let tile = Point2::new(x, y);
+				if self.tiles.tile_coords_are_floor(tile)
+				{
+					floor_tiles.push(self.tiles.tile_center(tile));
+				}
+			}
+		}
+		if floor_tiles.is_empty()
+		{
+			return Ok(());
+		}
+		let crystal_id = self
+			.world
+			.query::<&comps::Crystal>()
+			.iter()
+			.map(|(id, _)| id)
+			.next();
+		for i in 0..BENCHMARK_ENEMIES
+		{
+			let pos = *floor_tiles.choose(&mut rng).unwrap();
+			spawn_enemy(
+				Point3::new(pos.x, pos.y, 0.),
+				crystal_id,
+				comps::Rarity::Normal,
+				i % 2 == 0,
+				self.level,
+				&self.challenges,
+				&self.map_mods,
+				state.time(),
+				&mut self.world,
+				&mut rng,
+			)?;
+		}
+		for _ in 0..BENCHMARK_ITEMS
+		{
+			let pos = *floor_tiles.choose(&mut rng).unwrap();
+			let item = comps::make_item(
+				comps::ItemKind::Red,
+				comps::ItemCategory::Ring,
+				comps::Rarity::Normal,
+				vec![],
+				vec![],
+			);
+			spawn_item(
+				Point3::new(pos.x, pos.y, 0.),
+				Vector3::zeros(),
+				item,
+				None,
+				&mut self.world,
+			)?;
+		}
+		for _ in 0..BENCHMARK_LIGHTS
+		{
+			let pos = *floor_tiles.choose(&mut rng).unwrap();
+			self.world.spawn((
+				comps::Position::new(Point3::new(pos.x, pos.y, 0.)),
+				comps::Light {
+					color: Color::from_rgb_f(rng.gen(), rng.gen(), rng.gen()),
+					offt_y: 0.,
+				},
+			));
+		}
+		Ok(())
+	}
+
+	fn tile_under_mouse(&self, state: &game_state::GameState) -> Point2<i32>
+	{
+		let mouse_pos = Point2::new(state.mouse_pos.x as f32, state.mouse_pos.y as f32);
+		let pos = self.camera_to_world(mouse_pos, state);
+		Point2::new(
+			(pos.x / TILE_SIZE).floor() as i32,
+			(pos.y / TILE_SIZE).floor() as i32,
+		)
+	}
+
+	fn time_scale(&self, state: &game_state::GameState) -> f32
+	{
+		if state.time() < self.slowmo_until
+		{
+			SLOWMO_SPEED
+		}
+		else
+		{
+			1.
+		}
+	}
+
+	fn zoom(&self, state: &game_state::GameState) -> f32
+	{
+		let left = self.slowmo_until - state.time();
+		if left <= 0.
+		{
+			return 1.;
+		}
+		// Eases in and back out over the slow motion.
+		let f = utils::min(left / SLOWMO_DURATION, 1.) as f32;
+		1. + (SLOWMO_ZOOM - 1.) * (std::f32::consts::PI * f).sin()
+	}
+
+	fn camera_shift(&self, state: &game_state::GameState) -> Vector2<f32>
+	{
+		self.camera_lookahead - self.camera_pos.draw_pos(state.alpha).xy().coords
+			+ Vector2::new(state.buffer_width() / 2., state.buffer_height() / 2.)
+	}
+
+	fn draw_aggro_debug(&self, camera_shift: Vector2<f32>, state: &game_state::GameState)
+	{
+		for (_, (position, ai)) in self.world.query::<(&comps::Position, &comps::AI)>().iter()
+		{
+			let pos = position.draw_pos(state.alpha).xy() + camera_shift;
+			let leash = ai.leash.xy() + camera_shift;
+			state.prim.draw_circle(
+				pos.x,
+				pos.y,
+				ai.sense_range,
+				Color::from_rgb_f(0.5, 0.5, 0.),
+				1.,
+			);
+			state.prim.draw_circle(
+				pos.x,
+				pos.y,
+				ai.attack_range,
+				Color::from_rgb_f(0.5, 0., 0.),
+				1.,
+			);
+			state.prim.draw_circle(
+				leash.x,
+				leash.y,
+				ai.leash_range,
+				Color::from_rgb_f(0., 0.2, 0.5),
+				1.,
+			);
+		}
 	}
 
-	fn camera_shift(&self, state: &game_state::GameState) -> Vector2<f32>
+	fn draw_pathing_debug(&self, camera_shift: Vector2<f32>, state: &game_state::GameState)
 	{
-		self.camera_lookahead - self.camera_pos.draw_pos(state.alpha).xy().coords
-			+ Vector2::new(state.buffer_width() / 2., state.buffer_height() / 2.)
+		for y in 0..self.tiles.height
+		{
+			for x in 0..self.tiles.width
+			{
+				let tile = Point2::new(x, y);
+				let center = self.tiles.tile_center(tile) + camera_shift;
+				if center.x < -TILE_SIZE
+					|| center.y < -TILE_SIZE
+					|| center.x > state.buffer_width() + TILE_SIZE
+					|| center.y > state.buffer_height() + TILE_SIZE
+				{
+					continue;
+				}
+				if self.tiles.tile_coords_are_floor(tile)
+				{
+					state.prim.draw_filled_circle(
+						center.x,
+						center.y,
+						1.,
+						Color::from_rgb_f(0.5, 0.5, 0.5),
+					);
+				}
+				else
+				{
+					let r = TILE_SIZE / 2. - 2.;
+					state.prim.draw_rectangle(
+						center.x - r,
+						center.y - r,
+						center.x + r,
+						center.y + r,
+						Color::from_rgb_f(0.6, 0.1, 0.1),
+						1.,
+					);
+				}
+			}
+		}
+
+		for (_, (position, ai)) in self.world.query::<(&comps::Position, &comps::AI)>().iter()
+		{
+			let draw_pos = position.draw_pos(state.alpha);
+			let mut prev = Point2::new(draw_pos.x, draw_pos.y) + camera_shift;
+			for tile in ai.path.iter().rev().skip(1)
+			{
+				let next = self.tiles.tile_center(*tile) + camera_shift;
+				state.prim.draw_line(
+					prev.x,
+					prev.y,
+					next.x,
+					next.y,
+					Color::from_rgb_f(0.9, 0.9, 0.3),
+					1.,
+				);
+				prev = next;
+			}
+		}
 	}
 
 	fn logic(&mut self, state: &mut game_state::GameState) -> Result<bool>
@@ -2437,6 +5242,12 @@ impl Map
 			return Ok(false);
 		}
 
+		// Pending item swaps.
+		if self.active_inventory.is_some() && !self.player_in_combat()
+		{
+			self.active_inventory = None;
+		}
+
 		// Stats.
 		for (_, (stats, attack)) in self
 			.world
@@ -2450,12 +5261,35 @@ impl Map
 			if stats.life > 0.
 			{
 				let inventory = self.world.get::<&comps::Inventory>(id).ok();
+				let (active_inventory, challenges, map_mods) = if id == self.player
+				{
+					(
+						self.active_inventory.as_ref(),
+						&self.challenges[..],
+						&self.map_mods[..],
+					)
+				}
+				else
+				{
+					(None, &[][..], &[][..])
+				};
 				stats.reset(
 					state.time(),
 					if id == self.player { self.level } else { 0 },
-					inventory.as_deref(),
+					active_inventory.or(inventory.as_deref()),
+					challenges,
+					map_mods,
 				);
-				stats.logic(state);
+				if self.challenges.contains(&comps::Challenge::Enrage)
+				{
+					if let Ok(ai) = self.world.get::<&comps::AI>(id)
+					{
+						stats
+							.values
+							.scale_damage(ai.enrage_multiplier(state.time()));
+					}
+				}
+				stats.logic(state.time(), state.options.regen_delay);
 			}
 			else
 			{
@@ -2470,10 +5304,16 @@ impl Map
 		{
 			if !stats.dead
 			{
-				controller.want_attack = state
+				let want_attack = state
 					.controls
 					.get_action_state(controls::Action::BladeBlade)
 					> 0.5;
+				controller.attack_released = controller.want_attack && !want_attack;
+				if want_attack && !controller.want_attack
+				{
+					controller.attack_press_time = Some(state.time());
+				}
+				controller.want_attack = want_attack;
 
 				let dx = state.controls.get_action_state(controls::Action::MoveRight)
 					- state.controls.get_action_state(controls::Action::MoveLeft);
@@ -2484,6 +5324,13 @@ impl Map
 				{
 					diff = diff.normalize();
 				}
+				// Eases the move and aim direction towards the stick instead of snapping to it.
+				let smoothing = state.controls.get_stick_smoothing();
+				if smoothing > 0.
+				{
+					let f = 1. - (-DT / smoothing).exp();
+					diff = controller.want_move + (diff - controller.want_move) * f;
+				}
 				controller.want_move = diff;
 				controller.want_jump =
 					state.controls.get_action_state(controls::Action::Jump) > 0.5;
@@ -2491,7 +5338,8 @@ impl Map
 		}
 
 		// AI
-		for (_, (position, ai, controller, stats)) in self
+		let mut engaged_crystals = vec![];
+		for (id, (position, ai, controller, stats)) in self
 			.world
 			.query::<(
 				&mut comps::Position,
@@ -2501,7 +5349,11 @@ impl Map
 			)>()
 			.iter()
 		{
-			if stats.freeze_time > state.time()
+			if ai.combat_start.is_some() || stats.last_damaged_time.is_finite()
+			{
+				engaged_crystals.extend(ai.crystal);
+			}
+			if stats.freeze_time > state.time() || self.is_spawning(id, state.time())
 			{
 				continue;
 			}
@@ -2509,8 +5361,9 @@ impl Map
 			let wander_time = 0.5;
 			let chase_time = 1.;
 			let attack_time = 1.;
-			let sense_range = 128.;
-			let leash_range = 64.;
+			let search_time = 2.;
+			let sense_range = ai.sense_range;
+			let leash_range = ai.leash_range;
 			let attack_range = ai.attack_range;
 
 			// TODO: Better target acquisition.
@@ -2563,6 +5416,26 @@ impl Map
 				}
 			}
 
+			if let Some(target_position) = target_position.as_ref()
+			{
+				if target.is_some()
+				{
+					ai.last_known_target_pos = Some(target_position.pos);
+				}
+			}
+
+			if target.is_some()
+			{
+				if ai.combat_start.is_none()
+				{
+					ai.combat_start = Some(state.time());
+				}
+			}
+			else
+			{
+				ai.combat_start = None;
+			}
+
 			let mut next_state = None;
 			match ai.state
 			{
@@ -2628,11 +5501,35 @@ impl Map
 						}
 						else
 						{
-							let diff = (target_position.pos.xy() - position.pos.xy()).normalize();
-							controller.want_move = diff;
+							// Approach a point on a ring around the target so the pack surrounds
+							// it instead of clumping.
+							let angle = (id.id() as f32 * 2.4) % (2. * PI);
+							let mut approach_pos = target_position.pos.xy()
+								+ 0.75 * attack_range * Vector2::new(angle.cos(), angle.sin());
+							if !self.tiles.tile_is_floor(approach_pos)
+							{
+								approach_pos = target_position.pos.xy();
+							}
+							let move_pos = path_move_pos(
+								ai,
+								position.pos.xy(),
+								approach_pos,
+								&self.tiles,
+								&mut self.astar,
+								state.time(),
+							);
+							let diff = move_pos - position.pos.xy();
+							if diff.norm() > 0.
+							{
+								controller.want_move = diff.normalize();
+							}
 						}
 					}
-					if state.time() > ai.next_state_time
+					if target.is_none() && ai.last_known_target_pos.is_some()
+					{
+						next_state = Some(comps::AIState::Search);
+					}
+					else if state.time() > ai.next_state_time
 					{
 						if let Some(target) = target
 						{
@@ -2664,11 +5561,45 @@ impl Map
 							next_state = Some(comps::AIState::Chase(cur_target));
 						}
 					}
+					else if ai.last_known_target_pos.is_some()
+					{
+						next_state = Some(comps::AIState::Search);
+					}
 					else
 					{
 						next_state = Some(comps::AIState::Idle);
 					}
 				}
+				comps::AIState::Search =>
+				{
+					controller.want_attack = false;
+					controller.want_move = Vector2::zeros();
+					if let Some(last_known_pos) = ai.last_known_target_pos
+					{
+						let move_pos = path_move_pos(
+							ai,
+							position.pos.xy(),
+							last_known_pos.xy(),
+							&self.tiles,
+							&mut self.astar,
+							state.time(),
+						);
+						let diff = move_pos - position.pos.xy();
+						if (last_known_pos.xy() - position.pos.xy()).norm() > 8. && diff.norm() > 0.
+						{
+							controller.want_move = diff.normalize();
+						}
+					}
+					if let Some(target) = target
+					{
+						next_state = Some(comps::AIState::Chase(target));
+					}
+					else if state.time() > ai.next_state_time
+					{
+						ai.last_known_target_pos = None;
+						next_state = Some(comps::AIState::Idle);
+					}
+				}
 			}
 			if let Some(next_state) = next_state
 			{
@@ -2677,10 +5608,12 @@ impl Map
 					comps::AIState::Idle =>
 					{
 						ai.next_state_time = state.time() + idle_time;
+						ai.path.clear();
 					}
 					comps::AIState::Wander =>
 					{
 						ai.next_state_time = state.time() + wander_time;
+						ai.path.clear();
 					}
 					comps::AIState::Chase(_) =>
 					{
@@ -2690,10 +5623,24 @@ impl Map
 					{
 						ai.next_state_time = state.time() + attack_time;
 					}
+					comps::AIState::Search =>
+					{
+						ai.next_state_time = state.time() + search_time;
+					}
 				}
 				ai.state = next_state;
 			}
 		}
+		for crystal_id in engaged_crystals
+		{
+			if let Ok(crystal) = self.world.query_one_mut::<&mut comps::Crystal>(crystal_id)
+			{
+				if crystal.engage_time.is_none()
+				{
+					crystal.engage_time = Some(state.time());
+				}
+			}
+		}
 
 		// Controller.
 		for (_, (position, acceleration, stats, controller)) in self
@@ -2747,7 +5694,7 @@ impl Map
 				}
 
 				if id == self.player
-					&& self.num_crystals_done >= self.tiles.crystals.len() as i32
+					&& self.objective_done
 					&& (position.pos.xy() - self.tiles.exit.unwrap()).norm() < 16.
 				{
 					velocity.pos.z += 2048.;
@@ -2758,6 +5705,35 @@ impl Map
 						1.,
 					)?;
 					self.time_to_next_map = Some(state.time() + 1.0);
+
+					let clear_time = state.time() - self.start_time;
+					self.clear_time = Some(clear_time);
+					// Only crystal clears are comparable between runs of the same map.
+					let crystal_clear = self.objective == comps::Objective::BreakCrystals;
+					if crystal_clear && self.best_time.map(|t| clear_time < t).unwrap_or(true)
+					{
+						let mut best_times = BestTimes::load(state)?;
+						best_times.set(&self.map_file, clear_time);
+						best_times.save(state)?;
+						self.new_best_time = self.best_time.is_some();
+						self.best_time = Some(clear_time);
+					}
+
+					// Ghost clear: no crystal was ever elevated, and no more elves died than the
+					// crystals held at the start.
+					let ghost_kills = self.stats.num_elves_killed - self.ghost_kills;
+					if crystal_clear
+						&& state.options.ghost_clear
+						&& self.ghost_route
+						&& ghost_kills <= self.ghost_garrison
+					{
+						self.ghost_clear = true;
+						self.ghost_badge = true;
+						self.stats.souls += GHOST_CLEAR_SOULS;
+						let mut run_history = RunHistory::load(state)?;
+						run_history.record_ghost_clear(&self.map_file);
+						run_history.save(state)?;
+					}
 				}
 			}
 			if want_jump && state.time() - jump.jump_time < 0.25
@@ -2769,17 +5745,73 @@ impl Map
 				affected_by_gravity.factor = 1.;
 			}
 		}
-		for (_, (attack, controller)) in self
+		let attack_buffer = state.options.attack_buffer_ms as f64 / 1000.;
+		for (_, (attack, controller, stats, blade_blade)) in self
 			.world
-			.query::<(&mut comps::Attack, &comps::Controller)>()
+			.query::<(
+				&mut comps::Attack,
+				&mut comps::Controller,
+				&comps::Stats,
+				Option<&comps::BladeBlade>,
+			)>()
 			.iter()
 		{
-			if controller.want_attack
+			// A press that landed while the previous attack was still going starts the next one.
+			let buffered = controller
+				.attack_press_time
+				.map(|press_time| state.time() - press_time <= attack_buffer)
+				.unwrap_or(false);
+			let mut want_attack = controller.want_attack;
+			// Only recast when the blades need topping up or are about to expire.
+			if want_attack && state.options.auto_recast && !buffered
+			{
+				if let Some(blade_blade) = blade_blade
+				{
+					want_attack = blade_blade.num_blades < 10
+						|| blade_blade.time_to_remove - state.time()
+							< stats.values.skill_duration as f64;
+				}
+			}
+			if want_attack || buffered
 			{
+				if !attack.want_attack
+				{
+					controller.attack_press_time = None;
+				}
 				attack.want_attack = true;
 				attack.target_position = controller.target_position;
 			}
 		}
+		for (_, (attack, controller, stats, position)) in self
+			.world
+			.query::<(
+				&mut comps::Attack,
+				&comps::Controller,
+				&comps::Stats,
+				&comps::Position,
+			)>()
+			.iter()
+		{
+			if let comps::AttackKind::ChargedFireball(_) = attack.kind
+			{
+				if stats.freeze_time > state.time() || !controller.want_attack
+				{
+					attack.charge = 0.;
+					continue;
+				}
+				if attack.charge == 0.
+				{
+					state.sfx.play_positional_sound_with_pitch(
+						"data/ranged_attack.ogg",
+						position.pos.xy(),
+						self.camera_pos.pos.xy(),
+						1.,
+						0.5,
+					)?;
+				}
+				attack.charge += DT * stats.values.cast_speed;
+			}
+		}
 
 		// Appearance animation state handling.
 		for (_, (appearance, position, acceleration, velocity)) in self
@@ -2792,19 +5824,18 @@ impl Map
 			)>()
 			.iter()
 		{
+			let sprite = state.get_sprite(&appearance.sprite).ok();
 			if acceleration.pos.norm() > 0.
 			{
-				appearance
-					.animation_state
-					.set_new_animation(format!("Move{}", vec_to_dir_name(acceleration.pos.xy())));
+				let animation = directional_animation("Move", acceleration.pos.xy(), sprite);
+				appearance.animation_state.set_new_animation(animation);
 				appearance.speed = (velocity.pos - velocity.ground_pos).norm() / 196.;
 			}
 			else
 			{
 				let dir = Vector2::new(position.dir.cos(), position.dir.sin());
-				appearance
-					.animation_state
-					.set_new_animation(format!("Stand{}", vec_to_dir_name(dir)));
+				let animation = directional_animation("Stand", dir, sprite);
+				appearance.animation_state.set_new_animation(animation);
 				appearance.speed = 1.;
 			}
 		}
@@ -2828,18 +5859,10 @@ impl Map
 				{
 					Vector2::new(position.dir.cos(), position.dir.sin())
 				};
-				if velocity.pos.z > 0.
-				{
-					appearance
-						.animation_state
-						.set_new_animation(format!("Jump{}", vec_to_dir_name(dir)));
-				}
-				else
-				{
-					appearance
-						.animation_state
-						.set_new_animation(format!("Fall{}", vec_to_dir_name(dir)));
-				}
+				let sprite = state.get_sprite(&appearance.sprite).ok();
+				let prefix = if velocity.pos.z > 0. { "Jump" } else { "Fall" };
+				let animation = directional_animation(prefix, dir, sprite);
+				appearance.animation_state.set_new_animation(animation);
 				appearance.speed = velocity.pos.z.abs() / 196.;
 			}
 		}
@@ -2856,9 +5879,9 @@ impl Map
 			if attack.want_attack
 			{
 				let dir = Vector2::new(position.dir.cos(), position.dir.sin());
-				appearance
-					.animation_state
-					.set_new_animation(format!("Attack{}", vec_to_dir_name(dir)));
+				let sprite = state.get_sprite(&appearance.sprite).ok();
+				let animation = directional_animation("Attack", dir, sprite);
+				appearance.animation_state.set_new_animation(animation);
 				appearance.speed = stats.values.cast_speed;
 			}
 		}
@@ -2870,19 +5893,26 @@ impl Map
 			appearance.animation_state.set_new_animation("Default");
 			appearance.speed = 1.;
 		}
-		for (id, (appearance, _)) in self
+		for (id, (appearance, _, time_to_die)) in self
 			.world
-			.query::<(&mut comps::Appearance, &comps::Item)>()
+			.query::<(
+				&mut comps::Appearance,
+				&comps::Item,
+				Option<&comps::TimeToDie>,
+			)>()
 			.iter()
 		{
-			let animation = if Some(id) == self.nearby_item
+			let mut nearby = Some(id) == self.nearby_item;
+			if let Some(time_to_die) = time_to_die
 			{
-				"Nearby"
+				// Flash before despawning.
+				let time_left = time_to_die.time - state.time();
+				if time_left < ITEM_DESPAWN_WARNING && (time_left * 8.) as i32 % 2 == 0
+				{
+					nearby = !nearby;
+				}
 			}
-			else
-			{
-				"Default"
-			};
+			let animation = if nearby { "Nearby" } else { "Default" };
 			appearance.animation_state.set_new_animation(animation);
 			appearance.speed = 1.;
 		}
@@ -2919,13 +5949,50 @@ impl Map
 			appearance.material = comps::Material::Lit;
 			appearance.speed = 0.;
 		}
-		for (_, appearance) in self.world.query::<&mut comps::Appearance>().iter()
+		for (id, (appearance, position)) in self
+			.world
+			.query::<(&mut comps::Appearance, Option<&comps::Position>)>()
+			.iter()
 		{
 			let sprite = state.get_sprite(&appearance.sprite)?;
 			sprite.advance_state(
 				&mut appearance.animation_state,
 				(appearance.speed * DT) as f64,
 			);
+			let events = appearance.animation_state.drain_events();
+			if let Some(position) = position
+			{
+				for event in events
+				{
+					match event.as_str()
+					{
+						"Footstep" if id == self.player =>
+						{
+							state.sfx.play_positional_sound(
+								"data/land.ogg",
+								position.pos.xy(),
+								self.camera_pos.pos.xy(),
+								0.25,
+							)?;
+						}
+						"Windup" =>
+						{
+							if state.options.enemy_tell_sound
+								&& state.time() - self.last_tell_time > ENEMY_TELL_INTERVAL
+							{
+								self.last_tell_time = state.time();
+								state.sfx.play_positional_sound(
+									ENEMY_TELL_SOUND,
+									position.pos.xy(),
+									self.camera_pos.pos.xy(),
+									0.6,
+								)?;
+							}
+						}
+						_ => (),
+					}
+				}
+			}
 		}
 		for (_, (stats, status_appearance)) in self
 			.world
@@ -2963,13 +6030,13 @@ impl Map
 			)>()
 			.iter()
 		{
-			if attack.want_attack
+			if attack.want_attack && !self.is_spawning(id, state.time())
 			{
 				for _ in 0..appearance.animation_state.get_num_activations()
 				{
 					let mana_cost = if id == self.player
 					{
-						5. + self.level as f32 * 2.
+						blade_blade_mana_cost(self.level, &stats.values)
 					}
 					else
 					{
@@ -3017,12 +6084,52 @@ impl Map
 											dir * 100.,
 											stat_values,
 											rarity,
+											8.,
 											time,
 											&mut map.world,
 										)
 									}))
 								}
 							}
+							comps::AttackKind::ChargedFireball(rarity) =>
+							{
+								// Activations during the windup don't fire.
+								if attack.charge < comps::CHARGE_TIME
+								{
+									continue;
+								}
+								let f = utils::min(
+									attack.charge / comps::CHARGE_TIME,
+									comps::CHARGE_MAX_FACTOR,
+								);
+								attack.charge = 0.;
+								state.sfx.play_positional_sound(
+									"data/ranged_attack.ogg",
+									position.pos.xy(),
+									self.camera_pos.pos.xy(),
+									1.5,
+								)?;
+								let dir = (attack.target_position - position.pos
+									+ Vector3::new(0., 0., 2.))
+								.normalize();
+								let pos = position.pos.clone();
+								let time = state.time();
+								let mut stat_values = stats.values;
+								stat_values.scale_damage(comps::CHARGE_DAMAGE_MULTIPLIER * f);
+								let speed = 100. * (1. + 0.5 * f);
+								spawn_fns.push(Box::new(move |map| {
+									spawn_fireball(
+										pos + Vector3::new(0., 0., 10.),
+										dir * speed,
+										dir * speed,
+										stat_values,
+										rarity,
+										8. + 4. * f,
+										time,
+										&mut map.world,
+									)
+								}));
+							}
 							comps::AttackKind::Slam =>
 							{
 								let dir = Vector3::new(position.dir.cos(), position.dir.sin(), 0.);
@@ -3042,6 +6149,14 @@ impl Map
 									)
 								}));
 								slam_activations.push((id, pos, stats.values, 16.));
+								if self.world.get::<&comps::CurseGround>(id).is_ok()
+								{
+									let values = stats.values;
+									let time = state.time();
+									spawn_fns.push(Box::new(move |map| {
+										spawn_ground_aoe(pos, values, time, &mut map.world)
+									}));
+								}
 							}
 							comps::AttackKind::Explode =>
 							{
@@ -3063,7 +6178,7 @@ impl Map
 										)
 									}));
 								}
-								let radius = 32. * stats.values.area_of_effect.sqrt();
+								let radius = 32. * stats.values.aoe_radius_factor();
 								slam_activations.push((id, pos, stats.values, radius));
 							}
 							comps::AttackKind::BladeBlade =>
@@ -3088,12 +6203,20 @@ impl Map
 				blade_blade.time_to_remove = state.time() + 2. * skill_duration as f64;
 				if blade_blade.num_blades < 10
 				{
-					state.sfx.play_positional_sound(
-						"data/blade_blade.ogg",
-						position.pos.xy(),
-						self.camera_pos.pos.xy(),
-						1.,
-					)?;
+					let sound_interval = state.options.blade_sound_interval_ms as f64 / 1000.;
+					if state.time() - blade_blade.last_sound_time >= sound_interval
+					{
+						// Rises in pitch and gets quieter as the blades stack up.
+						let f = blade_blade.num_blades as f32 / 10.;
+						state.sfx.play_positional_sound_with_pitch(
+							"data/blade_blade.ogg",
+							position.pos.xy(),
+							self.camera_pos.pos.xy(),
+							1. - 0.4 * f,
+							1. + 0.3 * f,
+						)?;
+						blade_blade.last_sound_time = state.time();
+					}
 					self.stats.num_blades_cast += 1;
 				}
 				blade_blade.num_blades = utils::min(10, blade_blade.num_blades + 1);
@@ -3137,11 +6260,15 @@ impl Map
 		if let Some(player_pos) = player_pos
 		{
 			let mut best = None;
-			for (id, (position, _)) in self
+			for (id, (position, item)) in self
 				.world
 				.query::<(&comps::Position, &comps::Item)>()
 				.iter()
 			{
+				if state.options.pickup_filter.ignores(item)
+				{
+					continue;
+				}
 				let dist = (player_pos - position.pos).xy().norm();
 				if dist < 32.
 				{
@@ -3214,6 +6341,28 @@ impl Map
 			}
 		}
 
+		// Riders take on the platform velocity from this tick rather than the previous one, otherwise
+		// they drift relative to the platform whenever it starts or stops.
+		let mut ground_updates = vec![];
+		for (id, velocity) in self.world.query::<&comps::Velocity>().iter()
+		{
+			if let Some(ground_id) = velocity.ground_id
+			{
+				if let Ok(ground_velocity) = self.world.get::<&comps::Velocity>(ground_id)
+				{
+					ground_updates.push((id, ground_velocity.pos));
+				}
+			}
+		}
+		for (id, ground_pos) in ground_updates
+		{
+			if let Ok(mut velocity) = self.world.get::<&mut comps::Velocity>(id)
+			{
+				velocity.pos += ground_pos - velocity.ground_pos;
+				velocity.ground_pos = ground_pos;
+			}
+		}
+
 		// Velocity.
 		for (_, (position, acceleration, velocity)) in self
 			.world
@@ -3227,6 +6376,7 @@ impl Map
 			if position.pos.z > 0.
 			{
 				velocity.ground_pos = Vector3::zeros();
+				velocity.ground_id = None;
 				continue;
 			}
 			let decel = 2048.;
@@ -3296,12 +6446,94 @@ impl Map
 			}
 		}
 
+		// Auto-facing, overrides the movement direction while the player attacks.
+		let mut player_pos = None;
+		if let Ok((position, stats)) = self
+			.world
+			.query_one_mut::<(&comps::Position, &comps::Stats)>(self.player)
+		{
+			if stats.attacking && !stats.dead
+			{
+				player_pos = Some(position.pos.xy());
+			}
+		}
+		if let Some(player_pos) = player_pos
+		{
+			let mut face_target = None;
+			match state.options.auto_face
+			{
+				game_state::AutoFace::Classic => (),
+				game_state::AutoFace::NearestEnemy =>
+				{
+					let mut best_dist = AUTO_FACE_RANGE;
+					for (_, (position, stats, _)) in self
+						.world
+						.query::<(&comps::Position, &comps::Stats, &comps::AI)>()
+						.iter()
+					{
+						let dist = (position.pos.xy() - player_pos).norm();
+						if !stats.dead
+							&& stats.values.team == comps::Team::Enemy
+							&& dist < best_dist
+						{
+							best_dist = dist;
+							face_target = Some(position.pos.xy());
+						}
+					}
+				}
+				game_state::AutoFace::Cursor =>
+				{
+					if state.track_mouse
+					{
+						let mouse_pos =
+							Point2::new(state.mouse_pos.x as f32, state.mouse_pos.y as f32);
+						face_target = Some(self.camera_to_world(mouse_pos, state));
+					}
+				}
+			}
+			if let Some(face_target) = face_target
+			{
+				let diff = face_target - player_pos;
+				if let Ok(position) = self
+					.world
+					.query_one_mut::<&mut comps::Position>(self.player)
+				{
+					if diff.norm() > 0.
+					{
+						position.dir = diff.y.atan2(diff.x);
+					}
+				}
+			}
+		}
+
 		// PlaceToDie
-		for (id, (position, place_to_die)) in self
+		for (id, (position, place_to_die, velocity)) in self
 			.world
-			.query::<(&comps::Position, &mut comps::PlaceToDie)>()
+			.query::<(
+				&comps::Position,
+				&mut comps::PlaceToDie,
+				Option<&mut comps::Velocity>,
+			)>()
 			.iter()
 		{
+			if let Some(follow) = place_to_die.follow
+			{
+				// Moving targets are chased rather than overshot.
+				if let Ok(follow_position) = self.world.get::<&comps::Position>(follow)
+				{
+					place_to_die.target = follow_position.pos + Vector3::new(0., 0., 16.);
+				}
+				let diff = place_to_die.target - position.pos;
+				if diff.norm() < 16.
+				{
+					to_die.push((true, id));
+				}
+				else if let Some(velocity) = velocity
+				{
+					velocity.pos = 256. * diff.normalize();
+				}
+				continue;
+			}
 			let dist = (position.pos - place_to_die.target).norm();
 			if dist > place_to_die.old_dist
 			{
@@ -3311,6 +6543,7 @@ impl Map
 		}
 
 		// Collision detection
+		let collision_start = state.core.get_time();
 		let mut grid = spatial_grid::SpatialGrid::new(
 			self.tiles.width as usize,
 			self.tiles.height as usize,
@@ -3335,12 +6568,125 @@ impl Map
 			));
 		}
 
+		// Targeting
+		let player_pos = self.world.get::<&comps::Position>(self.player)?.pos;
+		if let Some(target) = self.target
+		{
+			if !self.is_valid_target(target, player_pos)
+			{
+				self.target = None;
+			}
+		}
+		if state
+			.controls
+			.get_action_state(controls::Action::CycleTarget)
+			> 0.5
+		{
+			state
+				.controls
+				.clear_action_state(controls::Action::CycleTarget);
+			let rv = Vector2::new(TARGET_RANGE, TARGET_RANGE);
+			let mut candidates: Vec<_> = grid
+				.query_rect(player_pos.xy() - rv, player_pos.xy() + rv, |other| {
+					self.is_valid_target(other.inner.id, player_pos)
+				})
+				.iter()
+				.map(|entry| (entry.inner.id, (entry.inner.pos - player_pos).xy().norm()))
+				.collect();
+			candidates.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+			candidates.dedup_by_key(|c| c.0);
+
+			let next_idx = self
+				.target
+				.and_then(|target| candidates.iter().position(|c| c.0 == target))
+				.map(|idx| idx + 1)
+				.unwrap_or(0);
+			self.target = candidates.get(next_idx).or(candidates.first()).map(|c| c.0);
+			if self.target.is_some()
+			{
+				state.sfx.play_sound("data/ui1.ogg")?;
+			}
+		}
+		if state
+			.controls
+			.get_action_state(controls::Action::CorpseExplosion)
+			> 0.5
+		{
+			state
+				.controls
+				.clear_action_state(controls::Action::CorpseExplosion);
+			let range = comps::CORPSE_EXPLOSION_RANGE;
+			let rv = Vector2::new(range, range);
+			let mut corpses = vec![];
+			for entry in grid
+				.query_rect(player_pos.xy() - rv, player_pos.xy() + rv, |other| {
+					other.inner.id != self.player
+						&& self.world.get::<&comps::Corpse>(other.inner.id).is_ok()
+				})
+				.iter()
+			{
+				let id = entry.inner.id;
+				if (entry.inner.pos - player_pos).xy().norm() > range
+					|| corpses.iter().any(|&(other_id, _, _)| other_id == id)
+				{
+					continue;
+				}
+				if let Ok(stats) = self.world.get::<&comps::Stats>(id)
+				{
+					// Only enemy corpses keep their old stats.
+					if stats.values.team == comps::Team::Enemy
+					{
+						corpses.push((id, entry.inner.pos, stats.values.max_life));
+					}
+				}
+			}
+			if let Ok(mut stats) = self.world.get::<&mut comps::Stats>(self.player)
+			{
+				let mana_cost = corpse_explosion_mana_cost(self.level, &stats.values);
+				if stats.dead || corpses.is_empty() || mana_cost > stats.mana
+				{
+					state.sfx.play_sound("data/ui2.ogg")?;
+				}
+				else
+				{
+					stats.mana -= mana_cost;
+					state.sfx.play_positional_sound(
+						"data/explosion.ogg",
+						player_pos.xy(),
+						self.camera_pos.pos.xy(),
+						1.,
+					)?;
+					for (id, pos, max_life) in corpses
+					{
+						let mut values = stats.values;
+						values.physical_damage = comps::CORPSE_EXPLOSION_DAMAGE * max_life;
+						values.cold_damage = 0.;
+						values.fire_damage = 0.;
+						values.lightning_damage = 0.;
+						to_die.push((false, id));
+						slam_activations.push((
+							self.player,
+							pos,
+							values,
+							comps::CORPSE_EXPLOSION_RADIUS,
+						));
+						spawn_fns.push(Box::new(move |map| {
+							spawn_explosion(
+								pos,
+								&"data/explosion.cfg",
+								Some(Color::from_rgb_f(0.9, 0.3, 0.3)),
+								&mut map.world,
+							)
+						}));
+					}
+				}
+			}
+		}
+
+		// Solids don't change during the tick, so use the copies stored in the grid rather than
+		// looking them up for every pair.
 		let mut colliding_pairs = vec![];
-		for (a, b) in grid.all_pairs(|a, b| {
-			let a_solid = self.world.get::<&comps::Solid>(a.inner.id).unwrap();
-			let b_solid = self.world.get::<&comps::Solid>(b.inner.id).unwrap();
-			a_solid.kind.collides_with(b_solid.kind)
-		})
+		for (a, b) in grid.all_pairs(|a, b| a.inner.solid.kind.collides_with(b.inner.solid.kind))
 		{
 			colliding_pairs.push((a.inner, b.inner));
 		}
@@ -3355,8 +6701,8 @@ impl Map
 				let pos1 = self.world.get::<&comps::Position>(id1)?.pos;
 				let pos2 = self.world.get::<&comps::Position>(id2)?.pos;
 
-				let solid1 = *self.world.get::<&comps::Solid>(id1)?;
-				let solid2 = *self.world.get::<&comps::Solid>(id2)?;
+				let solid1 = inner1.solid;
+				let solid2 = inner2.solid;
 
 				let diff = pos2.xy() - pos1.xy();
 				let diff_norm = utils::max(0.1, diff.norm());
@@ -3401,6 +6747,7 @@ impl Map
 									}
 									velocity.pos.z = 0.;
 									velocity.ground_pos = platform_velocity;
+									velocity.ground_id = Some(id2);
 								}
 								position.pos.z = 0.
 							}
@@ -3426,6 +6773,7 @@ impl Map
 									}
 									velocity.pos.z = 0.;
 									velocity.ground_pos = platform_velocity;
+									velocity.ground_id = Some(id1);
 								}
 								position.pos.z = 0.
 							}
@@ -3478,11 +6826,25 @@ impl Map
 						position.pos.add_xy(push_dir);
 					}
 				}
+				if solid.kind.stays_in_bounds()
+				{
+					let clamped_pos = self.tiles.clamp_to_bounds(position.pos.xy(), solid.size);
+					position.pos.set_xy(clamped_pos);
+				}
 				if position.pos.z == 0.
 				{
 					if self.tiles.tile_is_floor(position.pos.xy())
 					{
 						velocity.ground_pos = Vector3::zeros();
+						velocity.ground_id = None;
+						if id == self.player
+						{
+							if let Ok(mut last_safe_position) =
+								self.world.get::<&mut comps::LastSafePosition>(id)
+							{
+								last_safe_position.pos = position.pos.xy();
+							}
+						}
 					}
 				}
 				else if position.pos.z < 0.
@@ -3501,6 +6863,7 @@ impl Map
 						position.pos.z = 0.;
 						velocity.pos.z = 0.;
 						velocity.ground_pos = Vector3::zeros();
+						velocity.ground_id = None;
 					}
 					else
 					{
@@ -3512,6 +6875,20 @@ impl Map
 						if let Some(push_dir) = push_dir
 						{
 							position.pos.add_xy(push_dir);
+							if let Ok(mut bounce) = self.world.get::<&mut comps::Bounce>(id)
+							{
+								if bounce.remaining > 0 && push_dir.norm() > 0.
+								{
+									bounce.remaining -= 1;
+									let normal = push_dir.normalize();
+									velocity
+										.pos
+										.set_xy(utils::reflect(velocity.pos.xy(), normal));
+									velocity.pos.z = velocity.pos.z.abs();
+									position.pos.z = 0.;
+									continue;
+								}
+							}
 						}
 					}
 					if let Ok(on_contact_effect) = self.world.get::<&comps::OnContactEffect>(id)
@@ -3523,11 +6900,45 @@ impl Map
 		}
 
 		// BladeBlade
-		for (id, (position, blade_blade, stats)) in self
+		for (id, (position, blade_blade, stats, controller)) in self
 			.world
-			.query::<(&comps::Position, &mut comps::BladeBlade, &comps::Stats)>()
+			.query::<(
+				&comps::Position,
+				&mut comps::BladeBlade,
+				&comps::Stats,
+				Option<&comps::Controller>,
+			)>()
 			.iter()
 		{
+			// Stored blades don't hit or expire, they all go off at once when the attack is
+			// released.
+			if stats.values.stored_blades
+			{
+				let released = controller
+					.map(|controller| controller.attack_released)
+					.unwrap_or(false);
+				if released && blade_blade.num_blades > 0
+				{
+					state.sfx.play_positional_sound(
+						"data/explosion.ogg",
+						position.pos.xy(),
+						self.camera_pos.pos.xy(),
+						1.,
+					)?;
+					let mut values = stats.values;
+					values.scale_damage(
+						comps::BLADE_BLADE_RELEASE_DAMAGE_PER_BLADE * blade_blade.num_blades as f32,
+					);
+					slam_activations.push((
+						id,
+						position.pos,
+						values,
+						comps::BLADE_BLADE_RELEASE_RADIUS,
+					));
+					blade_blade.num_blades = 0;
+				}
+				continue;
+			}
 			if state.time() > blade_blade.time_to_remove
 			{
 				blade_blade.num_blades = utils::max(0, blade_blade.num_blades - 1);
@@ -3535,15 +6946,44 @@ impl Map
 			}
 			if state.time() > blade_blade.time_to_hit && blade_blade.num_blades > 0
 			{
-				blade_blade.time_to_hit = state.time() + 0.5 / blade_blade.num_blades as f64;
-				slam_activations.push((id, position.pos, stats.values, 32.));
+				let (interval, damage) =
+					blade_blade.hit_interval_and_damage(stats.values.blade_speed);
+				blade_blade.time_to_hit = state.time() + interval as f64;
+				let mut values = stats.values;
+				values.scale_damage(damage);
+				let radius_factor = self.blade_blade_radius_factor(
+					position.pos.xy(),
+					stats.values.aoe_radius_factor(),
+					state,
+				);
+				slam_activations.push((
+					id,
+					position.pos,
+					values,
+					comps::BLADE_BLADE_RADIUS * radius_factor,
+				));
+			}
+		}
+
+		// Ground AoE
+		for (id, (position, ground_aoe, stats)) in self
+			.world
+			.query::<(&comps::Position, &mut comps::GroundAoe, &comps::Stats)>()
+			.iter()
+		{
+			if state.time() > ground_aoe.time_to_tick
+			{
+				ground_aoe.time_to_tick = state.time() + ground_aoe.interval as f64;
+				slam_activations.push((id, position.pos, stats.values, ground_aoe.radius));
 			}
 		}
 
+		self.collision_time = state.core.get_time() - collision_start;
+
 		// Slam activations
 		for (id, pos, values, radius) in slam_activations
 		{
-			let r = radius * values.area_of_effect.sqrt();
+			let r = utils::min(radius * values.aoe_radius_factor(), comps::MAX_SLAM_RADIUS);
 			let rv = Vector2::new(r, r);
 			let entries = grid.query_rect(pos.xy() - rv, pos.xy() + rv, |other| {
 				let other_id = other.inner.id;
@@ -3592,8 +7032,7 @@ impl Map
 		}
 
 		// Crystal
-		let mut do_spawn_exit = false;
-		let old_crystals_done = self.num_crystals_done;
+		let mut num_crystals_broken = 0;
 		for (id, (position, crystal)) in self
 			.world
 			.query::<(&comps::Position, &comps::Crystal)>()
@@ -3610,24 +7049,84 @@ impl Map
 				self.stats.num_crystals_done += 1;
 				to_die.push((true, id));
 				self.num_crystals_done += 1;
-				if self.num_crystals_done >= self.tiles.crystals.len() as i32
+				num_crystals_broken += 1;
+
+				// Quick clear bonus.
+				let window = state.options.quick_clear_window as f64;
+				if let Some(engage_time) = crystal.engage_time
+				{
+					if window > 0.
+						&& state.options.quick_clear_items > 0
+						&& state.time() - engage_time <= window
+					{
+						state.show_toast("Quick Clear!");
+						effects.push((
+							id,
+							None,
+							vec![
+								comps::Effect::SpawnItems(crystal.kind);
+								state.options.quick_clear_items as usize
+							],
+						));
+					}
+				}
+			}
+		}
+
+		for _ in 0..num_crystals_broken
+		{
+			self.log_event(
+				"Crystal broken".to_string(),
+				Color::from_rgb_f(0.6, 1.0, 1.0),
+				state.time(),
+			);
+		}
+
+		// Objective.
+		if self.objective == comps::Objective::Sneak && self.num_crystals_done > 0
+		{
+			self.objective = comps::Objective::BreakCrystals;
+			if self.objective_done
+			{
+				self.objective_done = false;
+				for (id, _) in self.world.query_mut::<&comps::Exit>()
 				{
-					do_spawn_exit = true;
+					to_die.push((false, id));
 				}
 			}
+			self.log_event(
+				format!("Objective: {}", self.objective.to_str()),
+				Color::from_rgb_f(0.6, 1.0, 1.0),
+				state.time(),
+			);
 		}
 
 		// Spawn exit.
-		if do_spawn_exit && (old_crystals_done < self.num_crystals_done)
+		if !self.objective_done && self.objective_complete(state)
 		{
+			self.objective_done = true;
 			let exit = self.tiles.exit.unwrap();
 			spawn_exit(Point3::new(exit.x, exit.y, 0.), &mut self.world)?;
 		}
 
+		// Tutorial hints.
+		self.update_hints(state)?;
+
 		// Camera
 		if let Ok(position) = self.world.get::<&comps::Position>(self.player)
 		{
-			self.camera_pos.pos += 0.25 * (position.pos - self.camera_pos.pos);
+			// Only chase the part of the offset that leaves the deadzone.
+			let half_w = state.options.camera_deadzone_width / 2.;
+			let half_h = state.options.camera_deadzone_height / 2.;
+			let diff = position.pos - self.camera_pos.pos;
+			let target_diff = Vector3::new(
+				diff.x - utils::clamp(diff.x, -half_w, half_w),
+				diff.y - utils::clamp(diff.y, -half_h, half_h),
+				diff.z,
+			);
+			// The camera moves once per tick and is interpolated when drawn, so this stays smooth.
+			let camera_factor = CAMERA_SPEED_SCALE * state.options.camera_speed as f32;
+			self.camera_pos.pos += camera_factor * target_diff;
 			// TODO: Think about this.
 			self.camera_lookahead = -0. * (position.pos.xy() - self.camera_pos.pos.xy());
 		}
@@ -3641,6 +7140,77 @@ impl Map
 			}
 		}
 
+		// Debug only, so that enemy damage can be watched for a long time without resets.
+		if state.options.debug_draw && state.options.instant_respawn
+		{
+			if let Ok(stats) = self.world.query_one_mut::<&mut comps::Stats>(self.player)
+			{
+				if stats.life <= 0. && !stats.dead
+				{
+					stats.life = stats.values.max_life;
+					stats.ignite_instances.clear();
+					self.stats.near_deaths += 1;
+					self.log_event(
+						format!("Would Have Died ({})", self.stats.near_deaths),
+						Color::from_rgb_f(1., 0.3, 0.3),
+						state.time(),
+					);
+				}
+			}
+		}
+
+		// A revive keeps the player alive, so the run (and a hardcore save) carries on as normal.
+		let mut revive_pos = None;
+		if self.second_chance_ready
+		{
+			if let Ok((position, stats)) = self
+				.world
+				.query_one_mut::<(&comps::Position, &mut comps::Stats)>(self.player)
+			{
+				if stats.life <= 0. && !stats.dead && stats.values.second_chance
+				{
+					stats.life = SECOND_CHANCE_LIFE * stats.values.max_life;
+					stats.invincible_until = state.time() + SECOND_CHANCE_INVINCIBILITY;
+					stats.ignite_instances.clear();
+					revive_pos = Some(position.pos);
+				}
+			}
+		}
+		if let Some(revive_pos) = revive_pos
+		{
+			self.second_chance_ready = false;
+			self.stats.second_chance_used = true;
+			for (_, (position, velocity, _)) in
+				self.world
+					.query_mut::<(&comps::Position, &mut comps::Velocity, &comps::AI)>()
+			{
+				let diff = (position.pos - revive_pos).xy();
+				let dist = diff.norm();
+				if dist < SECOND_CHANCE_RADIUS && dist > 0.
+				{
+					let push = SECOND_CHANCE_KNOCKBACK * (1. - dist / SECOND_CHANCE_RADIUS);
+					velocity.pos += push * Vector3::new(diff.x / dist, diff.y / dist, 0.5);
+				}
+			}
+			spawn_explosion(
+				revive_pos,
+				"data/spawn.cfg",
+				Some(Color::from_rgb_f(1., 1., 1.)),
+				&mut self.world,
+			)?;
+			state.sfx.play_positional_sound(
+				"data/spawn.ogg",
+				revive_pos.xy(),
+				self.camera_pos.pos.xy(),
+				1.,
+			)?;
+			self.log_event(
+				"Second Chance!".to_string(),
+				Color::from_rgb_f(1., 1., 1.),
+				state.time(),
+			);
+		}
+
 		// Die on zero life.
 		let mut player_died = false;
 		for (id, stats) in self.world.query::<&comps::Stats>().iter()
@@ -3662,8 +7232,27 @@ impl Map
 			path_buf.push("save.cfg");
 			if path_buf.exists()
 			{
-				std::fs::remove_file(&path_buf)
-					.map_err(|_| format!("Couldn't delete '{}'", path_buf.to_str().unwrap()))?;
+				std::fs::remove_file(&path_buf).map_err(|e| {
+					Error::with_kind(
+						ErrorKind::Io,
+						format!("Couldn't delete '{}'", path_buf.to_str().unwrap()),
+						Some(Box::new(e)),
+					)
+				})?;
+			}
+
+			if !self.challenges.is_empty() || self.daily.is_some()
+			{
+				let mut run_history = RunHistory::load(state)?;
+				for &challenge in &self.challenges
+				{
+					run_history.record(challenge, self.level);
+				}
+				if let Some(day) = self.daily
+				{
+					run_history.record_daily(utils::format_day(day), self.level);
+				}
+				run_history.save(state)?;
 			}
 		}
 
@@ -3686,6 +7275,30 @@ impl Map
 				}
 			}
 		}
+		let soft_fall_pos = self
+			.world
+			.get::<&comps::LastSafePosition>(self.player)
+			.map(|last_safe_position| last_safe_position.pos)
+			.ok();
+		if do_reset && state.options.soft_fall && soft_fall_pos.is_some()
+		{
+			do_reset = false;
+			if let Ok((position, velocity, stats)) = self.world.query_one_mut::<(
+				&mut comps::Position,
+				&mut comps::Velocity,
+				&mut comps::Stats,
+			)>(self.player)
+			{
+				let pos = soft_fall_pos.unwrap();
+				position.pos = Point3::new(pos.x, pos.y, 0.);
+				position.snapshot();
+				velocity.pos = Vector3::zeros();
+				stats.life = utils::max(
+					1.,
+					stats.life - SOFT_FALL_LIFE_PENALTY * stats.values.max_life,
+				);
+			}
+		}
 		if do_reset
 		{
 			self.stats.num_resets += 1;
@@ -3724,14 +7337,25 @@ impl Map
 			}
 
 			self.num_crystals_done = 0;
+			self.ghost_kills = self.stats.num_elves_killed;
+			self.ghost_route = true;
+			self.objective = comps::roll_objective(self.map_seed.wrapping_add(5), self.level);
+			self.objective_done = false;
+			self.objective_start_time = state.time();
 
-			spawn_crystals_from_map(
+			self.ghost_garrison = spawn_crystals_from_map(
 				&self.tiles,
 				self.map_seed,
+				state.options.random_crystal_kinds && self.daily.is_none(),
 				self.level,
+				&self.challenges,
+				&self.map_mods,
+				self.magic_find(),
+				state.time(),
 				&mut rng,
 				&mut self.world,
 			)?;
+			self.spawn_elite(state)?;
 		}
 
 		// On death effects.
@@ -3746,11 +7370,37 @@ impl Map
 		// Effects.
 		for (id, other_id, effects) in effects
 		{
+			if let Some(other_id) = other_id
+			{
+				if let Ok(mut pierce) = self.world.get::<&mut comps::Pierce>(id)
+				{
+					if pierce.hit_ids.contains(&other_id)
+					{
+						continue;
+					}
+					pierce.hit_ids.push(other_id);
+				}
+				if let Ok(mut bounce) = self.world.get::<&mut comps::Bounce>(id)
+				{
+					if bounce.last_hit == Some(other_id)
+					{
+						continue;
+					}
+					bounce.last_hit = Some(other_id);
+				}
+			}
 			for effect in effects
 			{
 				match (effect, other_id)
 				{
-					(comps::Effect::Die, _) => to_die.push((false, id)),
+					(comps::Effect::Die, Some(other_id)) =>
+					{
+						if !self.absorb_projectile_contact(id, other_id)?
+						{
+							to_die.push((false, id));
+						}
+					}
+					(comps::Effect::Die, None) => to_die.push((false, id)),
 					(comps::Effect::SpawnExplosion(explosion, color, sound), other_id) =>
 					{
 						let mut pos = None;
@@ -3781,7 +7431,8 @@ impl Map
 					{
 						let mut damage_report = comps::DamageReport::miss();
 
-						if let Some(other_id) = other_id
+						if let Some(other_id) =
+							other_id.filter(|&other_id| !self.is_spawning(other_id, state.time()))
 						{
 							let mut could_damage = false;
 							if let Ok(stats) =
@@ -3790,8 +7441,11 @@ impl Map
 								if team.can_damage(stats.values.team)
 								{
 									could_damage = true;
-									let new_damage_report =
-										stats.apply_damage(&damage_stat_values, state, &mut rng);
+									let new_damage_report = stats.apply_damage(
+										&damage_stat_values,
+										state.time(),
+										&mut rng,
+									);
 									damage_report = new_damage_report;
 								}
 							}
@@ -3842,11 +7496,15 @@ impl Map
 						}
 						if let Ok(stats) = self.world.query_one_mut::<&mut comps::Stats>(id)
 						{
+							stats.life += damage_report.crit_life_leech;
+							stats.mana += damage_report.crit_mana_leech;
+							stats.clamp_life();
+							stats.mana = utils::clamp(stats.mana, 0., stats.values.max_mana);
 							if stats.values.instant_leech
 							{
 								stats.life += damage_report.life_leech;
 								stats.mana += damage_report.mana_leech;
-								stats.life = utils::clamp(stats.life, 0., stats.values.max_life);
+								stats.clamp_life();
 								stats.mana = utils::clamp(stats.mana, 0., stats.values.max_mana);
 							}
 							else
@@ -3949,8 +7607,13 @@ impl Map
 						let exploded = stats.as_ref().map(|s| s.exploded).unwrap_or(false);
 
 						// Always leave a corpse for the player...
+						// Frozen and exploded enemies shatter without a corpse, so the
+						// corpse collision option never applies to them.
 						if (!frozen && !exploded) || id == self.player
 						{
+							// The player's corpse keeps colliding so the death screen is
+							// unaffected by the option.
+							let collision = state.options.corpse_collision || id == self.player;
 							if let Ok((position, appearance, velocity)) = self
 								.world
 								.query_one_mut::<(&comps::Position, &comps::Appearance, &comps::Velocity)>(
@@ -3974,6 +7637,7 @@ impl Map
 										appearance,
 										inventory,
 										stats,
+										collision,
 										&mut map.world,
 									)?;
 									if id == map.player
@@ -4005,18 +7669,23 @@ impl Map
 					{
 						self.stats.num_elves_killed += 1;
 						let mut crystal_pos = None;
-						if let Ok((position, _)) = self
-							.world
-							.query_one_mut::<(&comps::Position, &mut comps::Crystal)>(crystal_id)
+						if let Some(crystal_id) = crystal_id
 						{
-							crystal_pos = Some(position.pos);
+							if let Ok((position, _)) = self
+								.world
+								.query_one_mut::<(&comps::Position, &mut comps::Crystal)>(
+									crystal_id,
+								)
+							{
+								crystal_pos = Some((crystal_id, position.pos));
+							}
 						}
 						let mut src_pos = None;
 						if let Ok(position) = self.world.query_one_mut::<&comps::Position>(id)
 						{
 							src_pos = Some(position.pos);
 						}
-						if let (Some(pos), Some(target)) = (src_pos, crystal_pos)
+						if let (Some(pos), Some((crystal_id, target))) = (src_pos, crystal_pos)
 						{
 							spawn_fns.push(Box::new(move |map| {
 								spawn_soul(
@@ -4027,6 +7696,32 @@ impl Map
 								)
 							}));
 						}
+						// Some kills also give the player a soul, on top of the crystal's.
+						let mut player_pos = None;
+						if let Ok(position) =
+							self.world.query_one_mut::<&comps::Position>(self.player)
+						{
+							player_pos = Some(position.pos);
+						}
+						if let (Some(pos), Some(player_pos)) = (src_pos, player_pos)
+						{
+							if rng.gen_bool(PLAYER_SOUL_CHANCE)
+							{
+								let player = self.player;
+								spawn_fns.push(Box::new(move |map| {
+									spawn_player_soul(
+										pos + Vector3::new(0., 0., 16.),
+										player_pos + Vector3::new(0., 0., 16.),
+										player,
+										&mut map.world,
+									)
+								}));
+							}
+						}
+					}
+					(comps::Effect::CollectSoul, _) =>
+					{
+						self.stats.souls += 1;
 					}
 					(comps::Effect::UnlockCrystal(crystal_id), _) =>
 					{
@@ -4084,7 +7779,17 @@ impl Map
 						}
 						if let Some(spawn_pos) = do_spawn
 						{
-							spawn_from_crystal(crystal_id, self.level, &mut self.world, &mut rng)?;
+							self.ghost_route = false;
+							spawn_from_crystal(
+								crystal_id,
+								self.level,
+								&self.challenges,
+								&self.map_mods,
+								self.magic_find(),
+								state.time(),
+								&mut self.world,
+								&mut rng,
+							)?;
 							state.sfx.play_positional_sound(
 								"data/spawn.ogg",
 								spawn_pos.xy(),
@@ -4093,8 +7798,16 @@ impl Map
 							)?;
 						}
 					}
+					(comps::Effect::BossDeath, _) =>
+					{
+						if state.options.boss_slowmo
+						{
+							self.slowmo_until = state.time() + SLOWMO_DURATION;
+						}
+					}
 					(comps::Effect::SpawnItems(kind), _) =>
 					{
+						let magic_find = self.magic_find();
 						if let Ok((position, crystal)) = self
 							.world
 							.query_one_mut::<(&comps::Position, &comps::Crystal)>(id)
@@ -4105,16 +7818,35 @@ impl Map
 									rng.gen_range(-4.0..4.0),
 									0.,
 								);
-							let crystal_level = crystal.level;
-							let level = self.level;
+							let item = comps::generate_item(
+								kind,
+								crystal.level,
+								self.level,
+								magic_find,
+								&mut rng,
+							);
+							if let Some((sound, volume)) = get_item_drop_sound(item.rarity)
+							{
+								state.sfx.play_positional_sound(
+									sound,
+									pos.xy(),
+									self.camera_pos.pos.xy(),
+									volume,
+								)?;
+							}
+							let despawn_time = item_despawn_time(item.rarity, state);
+							let max_items = state.options.max_dropped_items;
 							spawn_fns.push(Box::new(move |map| {
-								let mut rng = thread_rng();
-								spawn_item(
+								let id = spawn_item(
 									pos,
 									Vector3::new(0., 0., 128.),
-									comps::generate_item(kind, crystal_level, level, &mut rng),
+									item,
+									despawn_time,
 									&mut map.world,
-								)
+								)?;
+								map.dropped_items.push_back(id);
+								map.cull_dropped_items(max_items)?;
+								Ok(id)
 							}));
 						}
 					}
@@ -4143,14 +7875,30 @@ impl Map
 			//println!("died {id:?}");
 			self.world.despawn(id)?;
 		}
+		if let Some(nearby_item) = self.nearby_item
+		{
+			if !self.world.contains(nearby_item)
+			{
+				self.nearby_item = None;
+			}
+		}
 
 		Ok(false)
 	}
 
 	fn input(
-		&mut self, _event: &Event, _state: &mut game_state::GameState,
+		&mut self, event: &Event, state: &mut game_state::GameState,
 	) -> Result<Option<game_state::NextScreen>>
 	{
+		match *event
+		{
+			Event::MouseButtonDown { button: 3, .. } if state.options.debug_draw =>
+			{
+				let tile = self.tile_under_mouse(state);
+				self.tiles.toggle_tile(tile);
+			}
+			_ => (),
+		}
 		Ok(None)
 	}
 
@@ -4165,9 +7913,16 @@ impl Map
 			state.buffer_height(),
 			-state.buffer_height(),
 		);
+		// The world zooms about the screen center, the HUD doesn't.
+		let zoom = self.zoom(state);
+		let center = Vector3::new(state.buffer_width() / 2., state.buffer_height() / 2., 0.);
+		let world_mat = ortho_mat
+			* Matrix4::new_translation(&center)
+			* Matrix4::new_nonuniform_scaling(&Vector3::new(zoom, zoom, 1.))
+			* Matrix4::new_translation(&-center);
 		state
 			.core
-			.use_projection_transform(&utils::mat4_to_transform(ortho_mat));
+			.use_projection_transform(&utils::mat4_to_transform(world_mat));
 
 		let camera_shift = self.camera_shift(state);
 
@@ -4175,7 +7930,7 @@ impl Map
 		state.core.set_target_bitmap(state.light_buffer.as_ref());
 		state
 			.core
-			.use_projection_transform(&utils::mat4_to_transform(ortho_mat));
+			.use_projection_transform(&utils::mat4_to_transform(world_mat));
 		state
 			.core
 			.use_shader(Some(&*state.basic_shader.upgrade().unwrap()))
@@ -4195,7 +7950,7 @@ impl Map
 		for (_, (position, light)) in self.world.query_mut::<(&comps::Position, &comps::Light)>()
 		{
 			let draw_pos = position.draw_pos(state.alpha);
-			let pos = utils::round_point(
+			let pos = state.snap_point(
 				Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - light.offt_y) + camera_shift,
 			);
 
@@ -4249,12 +8004,26 @@ impl Map
 			.iter()
 		{
 			let draw_pos = position.draw_pos(state.alpha);
-			let pos = utils::round_point(
-				Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 8.) + camera_shift,
-			);
-			let radius = stats.values.area_of_effect.sqrt();
+			let pos = state
+				.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 8.) + camera_shift);
+			let radius = stats.values.aoe_radius_factor()
+				* self.blade_blade_radius_factor(
+					position.pos.xy(),
+					stats.values.aoe_radius_factor(),
+					state,
+				);
 
-			draw_blade_blade(pos, 0., radius, blade_blade.num_blades, 1., 1., 1., state);
+			draw_blade_blade(
+				pos,
+				0.,
+				radius,
+				blade_blade.num_blades,
+				1.,
+				1.,
+				1.,
+				comps::blade_blade_color(&stats.values),
+				state,
+			);
 		}
 
 		let rc_buffer = game_state::light_pass(state);
@@ -4264,7 +8033,7 @@ impl Map
 		state.core.set_target_bitmap(state.buffer1.as_ref());
 		state
 			.core
-			.use_projection_transform(&utils::mat4_to_transform(ortho_mat));
+			.use_projection_transform(&utils::mat4_to_transform(world_mat));
 		state
 			.core
 			.use_shader(Some(&*state.palette_shader.upgrade().unwrap()))
@@ -4280,15 +8049,18 @@ impl Map
 			.ok();
 
 		state.core.set_depth_test(None);
-		let mut scene = Scene::new();
-		self.bkg_tiles.draw(
-			Point2::new(camera_shift.x * 0.25, camera_shift.y * 0.25),
-			&mut scene,
-			-self.camera_pos.pos.y * 0.25,
-			state,
-			false,
-		)?;
-		scene.draw_triangles(state);
+		for (bkg_tiles, parallax) in &self.bkg_layers
+		{
+			let mut scene = Scene::new();
+			bkg_tiles.draw(
+				Point2::new(camera_shift.x * parallax, camera_shift.y * parallax),
+				&mut scene,
+				-self.camera_pos.pos.y * parallax,
+				state,
+				false,
+			)?;
+			scene.draw_triangles(state);
+		}
 
 		state
 			.core
@@ -4327,7 +8099,7 @@ impl Map
 
 			let draw_pos = position.draw_pos(state.alpha);
 			let pos =
-				utils::round_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z) + camera_shift);
+				state.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z) + camera_shift);
 
 			let (atlas_bmp, offt) = sprite.get_frame_from_state(&appearance.animation_state);
 
@@ -4363,9 +8135,8 @@ impl Map
 				)?;
 
 				let draw_pos = position.draw_pos(state.alpha);
-				let pos = utils::round_point(
-					Point2::new(draw_pos.x, draw_pos.y - draw_pos.z) + camera_shift,
-				);
+				let pos = state
+					.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z) + camera_shift);
 
 				let (atlas_bmp, offt) = sprite.get_frame_from_state(&appearance.animation_state);
 
@@ -4393,8 +8164,8 @@ impl Map
 				.get_palette_index(&sprite.get_palettes()[0])?;
 
 			let draw_pos = position.draw_pos(state.alpha);
-			let pos = utils::round_point(
-				utils::round_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z)) + camera_shift,
+			let pos = state.snap_point(
+				state.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z)) + camera_shift,
 			);
 			let (atlas_bmp, offt) = sprite.get_frame("Default", crystal.level);
 
@@ -4470,7 +8241,7 @@ impl Map
 				.get_palette_index(&sprite.get_palettes()[0])?;
 
 			let draw_pos = position.draw_pos(state.alpha);
-			let pos = utils::round_point(Point2::new(draw_pos.x, draw_pos.y) + camera_shift);
+			let pos = state.snap_point(Point2::new(draw_pos.x, draw_pos.y) + camera_shift);
 			let (atlas_bmp, offt) = sprite.get_frame("Default", 0);
 
 			scene.add_bitmap(
@@ -4499,10 +8270,14 @@ impl Map
 			.iter()
 		{
 			let draw_pos = position.draw_pos(state.alpha);
-			let pos = utils::round_point(
-				Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 8.) + camera_shift,
-			);
-			let radius = stats.values.area_of_effect.sqrt();
+			let pos = state
+				.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 8.) + camera_shift);
+			let radius = stats.values.aoe_radius_factor()
+				* self.blade_blade_radius_factor(
+					position.pos.xy(),
+					stats.values.aoe_radius_factor(),
+					state,
+				);
 
 			draw_blade_blade(
 				pos,
@@ -4512,6 +8287,7 @@ impl Map
 				1.,
 				1.,
 				1.,
+				comps::blade_blade_color(&stats.values),
 				state,
 			);
 		}
@@ -4525,26 +8301,47 @@ impl Map
 		let mut life_bar_bkg_vertices = vec![];
 		let mut life_bar_fgd_vertices = vec![];
 		let mut life_bar_indices = vec![];
+		let mut resistance_vertices = vec![];
+		let mut resistance_indices = vec![];
 
-		for (_, (position, stats)) in self.world.query_mut::<(&comps::Position, &comps::Stats)>()
+		let enrage_enabled = self.challenges.contains(&comps::Challenge::Enrage);
+		for (_, (position, stats, ai)) in
+			self.world
+				.query_mut::<(&comps::Position, &comps::Stats, Option<&comps::AI>)>()
 		{
 			if stats.values.team != comps::Team::Enemy
 			{
 				continue;
 			}
-			if stats.life == stats.values.max_life
+			let enrage = match ai
+			{
+				Some(ai) if enrage_enabled => ai.enrage_multiplier(state.time()),
+				_ => 1.,
+			};
+			if stats.life == stats.values.max_life && enrage == 1.
 			{
 				continue;
 			}
 			let draw_pos = position.draw_pos(state.alpha);
-			let pos = utils::round_point(
-				Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 32.5) + camera_shift,
-			);
+			let pos = state
+				.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 32.5) + camera_shift);
 			let w1 = 16.;
 			let w2 = 15.;
 			let h1 = 1.5;
 			let h2 = 0.5;
 
+			// Enraged enemies get a pulsing orange outline.
+			let bkg_color = if enrage > 1.
+			{
+				let f = (enrage - 1.) / (comps::ENRAGE_MAX_MULTIPLIER - 1.);
+				let pulse = 0.75 + 0.25 * (10. * state.time()).sin() as f32;
+				Color::from_rgb_f(f * pulse, 0.5 * f * pulse, 0.)
+			}
+			else
+			{
+				Color::from_rgb_f(0., 0., 0.)
+			};
+
 			let idx = life_bar_bkg_vertices.len() as i32;
 			life_bar_indices.extend([idx + 0, idx + 1, idx + 2, idx + 0, idx + 2, idx + 3]);
 
@@ -4562,7 +8359,7 @@ impl Map
 					z: 0.,
 					u: 0.,
 					v: 0.,
-					color: Color::from_rgb_f(0., 0., 0.),
+					color: bkg_color,
 				});
 			}
 
@@ -4584,6 +8381,63 @@ impl Map
 					color: Color::from_rgb_f(1., 0.1, 0.1),
 				});
 			}
+
+			if state.options.show_resistances
+			{
+				let resistances = [
+					(
+						stats.values.physical_resistance,
+						Color::from_rgb_f(0.9, 0.9, 0.9),
+					),
+					(
+						stats.values.fire_resistance,
+						Color::from_rgb_f(0.9, 0.3, 0.3),
+					),
+					(
+						stats.values.lightning_resistance,
+						Color::from_rgb_f(0.9, 0.9, 0.3),
+					),
+					(
+						stats.values.cold_resistance,
+						Color::from_rgb_f(0.3, 0.3, 0.9),
+					),
+				];
+				let mut x = pos.x + w1 + 2.;
+				for (resistance, color) in resistances
+				{
+					if resistance < HIGH_RESISTANCE
+					{
+						continue;
+					}
+					let idx = resistance_vertices.len() as i32;
+					resistance_indices.extend([
+						idx + 0,
+						idx + 1,
+						idx + 2,
+						idx + 0,
+						idx + 2,
+						idx + 3,
+					]);
+					let locs = [
+						Point2::new(x, pos.y - h1),
+						Point2::new(x + 2. * h1, pos.y - h1),
+						Point2::new(x + 2. * h1, pos.y + h1),
+						Point2::new(x, pos.y + h1),
+					];
+					for loc in locs
+					{
+						resistance_vertices.push(Vertex {
+							x: loc.x,
+							y: loc.y,
+							z: 0.,
+							u: 0.,
+							v: 0.,
+							color: color,
+						});
+					}
+					x += 2. * h1 + 1.;
+				}
+			}
 		}
 		state.prim.draw_indexed_prim(
 			&life_bar_bkg_vertices[..],
@@ -4601,78 +8455,356 @@ impl Map
 			life_bar_indices.len() as u32,
 			PrimType::TriangleList,
 		);
+		state.prim.draw_indexed_prim(
+			&resistance_vertices[..],
+			Option::<&Bitmap>::None,
+			&resistance_indices[..],
+			0,
+			resistance_indices.len() as u32,
+			PrimType::TriangleList,
+		);
+
+		// Telegraph for enemies winding up a charged fireball.
+		for (_, (position, stats, attack)) in
+			self.world
+				.query_mut::<(&comps::Position, &comps::Stats, &comps::Attack)>()
+		{
+			if let comps::AttackKind::ChargedFireball(rarity) = attack.kind
+			{
+				if attack.charge == 0.
+				{
+					continue;
+				}
+				let f = utils::min(attack.charge / comps::CHARGE_TIME, 1.);
+				let draw_pos = position.draw_pos(state.alpha);
+				let pos = state.snap_point(
+					Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 10.) + camera_shift,
+				);
+				let color = comps::damage_sprites(&stats.values, rarity).color;
+				let pulse = if f == 1.
+				{
+					0.75 + 0.25 * (20. * state.time()).sin() as f32
+				}
+				else
+				{
+					1.
+				};
+				state
+					.prim
+					.draw_filled_circle(pos.x, pos.y, 2. + 6. * f * pulse, color);
+				state.prim.draw_circle(pos.x, pos.y, 10., color, 1.);
+			}
+		}
+
+		// Status immunities are pips above the head, so the rarity palette stays visible.
+		for (_, (position, stats)) in self.world.query_mut::<(&comps::Position, &comps::Stats)>()
+		{
+			if stats.values.team != comps::Team::Enemy
+			{
+				continue;
+			}
+			let icons: Vec<_> = [
+				(
+					comps::StatusImmunities::IGNITE,
+					Color::from_rgb_f(0.9, 0.3, 0.3),
+				),
+				(
+					comps::StatusImmunities::SHOCK,
+					Color::from_rgb_f(0.9, 0.9, 0.3),
+				),
+				(
+					comps::StatusImmunities::FREEZE,
+					Color::from_rgb_f(0.3, 0.3, 0.9),
+				),
+			]
+			.into_iter()
+			.filter(|(immunity, _)| stats.values.status_immunities.contains(*immunity))
+			.collect();
+			if icons.is_empty()
+			{
+				continue;
+			}
+			let draw_pos = position.draw_pos(state.alpha);
+			let pos = state
+				.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 38.) + camera_shift);
+			let mut x = pos.x - 3. * (icons.len() - 1) as f32;
+			for (_, color) in icons
+			{
+				state
+					.prim
+					.draw_filled_circle(x, pos.y, 2.5, Color::from_rgb_f(0., 0., 0.));
+				state.prim.draw_filled_circle(x, pos.y, 1.5, color);
+				x += 6.;
+			}
+		}
+
+		if state.options.projectile_previews
+		{
+			let mut preview_vertices = vec![];
+			for (_, (position, velocity, acceleration, time_to_die, light, _)) in
+				self.world.query_mut::<(
+					&comps::Position,
+					&comps::Velocity,
+					&comps::Acceleration,
+					&comps::TimeToDie,
+					&comps::Light,
+					&comps::EnemyProjectile,
+				)>()
+			{
+				let duration =
+					utils::min(PROJECTILE_PREVIEW_TIME, time_to_die.time - state.time()) as f32;
+				if duration <= 0.
+				{
+					continue;
+				}
+				let draw_pos = position.draw_pos(state.alpha);
+				let (r, g, b, _) = light.color.to_rgba_f();
+				let color = Color::from_rgba_f(0.3 * r, 0.3 * g, 0.3 * b, 0.3);
+				for i in 0..PROJECTILE_PREVIEW_SEGMENTS
+				{
+					for j in 0..2
+					{
+						let t = duration * (i + j) as f32 / PROJECTILE_PREVIEW_SEGMENTS as f32;
+						let pos = draw_pos + velocity.pos * t + 0.5 * acceleration.pos * t * t;
+						let pos = Point2::new(pos.x, pos.y - pos.z) + camera_shift;
+						preview_vertices.push(Vertex {
+							x: pos.x,
+							y: pos.y,
+							z: 0.,
+							u: 0.,
+							v: 0.,
+							color: color,
+						});
+					}
+				}
+			}
+			state.prim.draw_prim(
+				&preview_vertices[..],
+				Option::<&Bitmap>::None,
+				0,
+				preview_vertices.len() as u32,
+				PrimType::LineList,
+			);
+		}
+
+		if let Some(position) = self
+			.target
+			.and_then(|target| self.world.get::<&comps::Position>(target).ok())
+		{
+			let draw_pos = position.draw_pos(state.alpha);
+			let pos = state
+				.snap_point(Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 12.) + camera_shift);
+			let r = 14.;
+			let color = Color::from_rgb_f(0.9, 0.9, 0.3);
+			state.prim.draw_circle(pos.x, pos.y, r, color, 1.);
+			for (dx, dy) in [(1., 0.), (-1., 0.), (0., 1.), (0., -1.)]
+			{
+				state.prim.draw_line(
+					pos.x + dx * (r - 4.),
+					pos.y + dy * (r - 4.),
+					pos.x + dx * (r + 4.),
+					pos.y + dy * (r + 4.),
+					color,
+					1.,
+				);
+			}
+		}
+
+		if state.options.debug_draw
+		{
+			self.draw_pathing_debug(camera_shift, state);
+			self.draw_aggro_debug(camera_shift, state);
+		}
 
 		state
 			.core
 			.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
-		let pad = 2.;
 		let lh = state.ui_font().get_line_height() as f32;
-		if let Ok(stats) = self.world.query_one_mut::<&comps::Stats>(self.player)
+
+		// Remaining enemies above the crystal pips.
+		if state.options.crystal_enemy_count
 		{
-			let orb_radius = if self.inventory_shown { 23. } else { 31. };
+			for (_, (position, crystal)) in self
+				.world
+				.query_mut::<(&comps::Position, &comps::Crystal)>()
+			{
+				if crystal.first_spawn_time.is_none() || crystal.enemies <= 0
+				{
+					continue;
+				}
+				let draw_pos = position.draw_pos(state.alpha);
+				let pos = state.snap_point(
+					Point2::new(draw_pos.x, draw_pos.y - draw_pos.z - 56.) + camera_shift,
+				);
+				state.core.draw_text(
+					state.ui_font(),
+					Color::from_rgb_f(0.8, 1., 1.),
+					pos.x,
+					pos.y - lh,
+					FontAlign::Centre,
+					&format!("{}", crystal.enemies),
+				);
+			}
+		}
 
-			let (orb_left, orb_right, orb_y, orb_top, orb_sprite) = if self.inventory_shown
+		if state.options.debug_draw
+		{
+			let tile = self.tile_under_mouse(state);
+			let kind = if self.tiles.tile_coords_are_floor(tile)
 			{
-				(
-					state.buffer_width() / 2. - orb_radius - pad - 2.,
-					state.buffer_width() / 2. + orb_radius + pad + 2.,
-					16. + pad + orb_radius,
-					16. + pad - lh,
-					"data/orb_small.cfg",
-				)
+				TileKind::Floor
 			}
 			else
 			{
-				(
-					pad + orb_radius,
-					state.buffer_width() as f32 - pad - orb_radius,
-					state.buffer_height() as f32 - pad - orb_radius,
-					state.buffer_height() as f32 - 2. * orb_radius - pad - lh,
-					"data/orb_big.cfg",
-				)
+				TileKind::Empty
+			};
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(1., 1., 1.),
+				state.mouse_pos.x as f32 + 8.,
+				state.mouse_pos.y as f32 + 8.,
+				FontAlign::Left,
+				&format!("{}, {}: {:?}", tile.x, tile.y, kind),
+			);
+		}
+		state
+			.core
+			.use_projection_transform(&utils::mat4_to_transform(ortho_mat));
+		let layout = if self.inventory_shown
+		{
+			state.options.hud_layout.with_inventory_shown()
+		}
+		else
+		{
+			state.options.hud_layout
+		};
+		let (bw, bh) = (state.buffer_width(), state.buffer_height());
+		if let Ok(stats) = self.world.query_one_mut::<&comps::Stats>(self.player)
+		{
+			let orb_radius = layout.orb_radius;
+			let orb_sprite = if orb_radius < 31.
+			{
+				"data/orb_small.cfg"
+			}
+			else
+			{
+				"data/orb_big.cfg"
 			};
+			let life_pos = layout.life_orb.resolve(bw, bh);
+			let mana_pos = layout.mana_orb.resolve(bw, bh);
 
 			let life = if stats.dead { 0. } else { stats.life };
 
+			if stats.values.elemental_aegis
+			{
+				let element = comps::aegis_element(state.time());
+				let color = match element
+				{
+					0 => Color::from_rgb_f(0.3, 0.3, 0.9),
+					1 => Color::from_rgb_f(0.9, 0.3, 0.3),
+					_ => Color::from_rgb_f(0.9, 0.9, 0.3),
+				};
+				state.core.draw_text(
+					state.ui_font(),
+					color,
+					life_pos.x,
+					life_pos.y - orb_radius - 2. * lh,
+					FontAlign::Centre,
+					&format!("Aegis: {}", comps::AEGIS_ELEMENTS[element]),
+				);
+			}
+
+			if state.options.debug_draw && state.options.instant_respawn
+			{
+				state.core.draw_text(
+					state.ui_font(),
+					Color::from_rgb_f(1., 0.3, 0.3),
+					life_pos.x,
+					life_pos.y - orb_radius - 3. * lh,
+					FontAlign::Centre,
+					&format!("Near Deaths: {}", self.stats.near_deaths),
+				);
+			}
+
+			let shield_text = if stats.shield >= 1.
+			{
+				format!(
+					" +{}",
+					utils::format_number(stats.shield, state.options.number_format)
+				)
+			}
+			else
+			{
+				"".to_string()
+			};
 			state.core.draw_text(
 				state.ui_font(),
 				Color::from_rgb_f(1., 1., 1.),
-				orb_left,
-				orb_top,
+				life_pos.x,
+				life_pos.y - orb_radius - lh,
 				FontAlign::Centre,
-				&format!("{}/{}", life as i32, stats.values.max_life as i32,),
+				&format!(
+					"{}/{}{}",
+					utils::format_number(life, state.options.number_format),
+					utils::format_number(stats.values.max_life, state.options.number_format),
+					shield_text,
+				),
 			);
 
 			draw_orb(
 				state,
 				orb_radius,
-				orb_left,
-				orb_y,
+				life_pos.x,
+				life_pos.y,
 				life / stats.values.max_life,
-				Color::from_rgb_f(0.9, 0.2, 0.2),
+				if stats.values.life_regen > 0.
+					&& stats.regen_suppressed(state.time(), state.options.regen_delay)
+				{
+					Color::from_rgb_f(0.6, 0.15, 0.15)
+				}
+				else
+				{
+					Color::from_rgb_f(0.9, 0.2, 0.2)
+				},
 			);
+			// The overheal shield fills the orb from the bottom over the life.
+			if stats.shield > 0. && !stats.dead
+			{
+				draw_orb(
+					state,
+					orb_radius,
+					life_pos.x,
+					life_pos.y,
+					utils::min(1., stats.shield / stats.values.max_life),
+					Color::from_rgba_f(0.45, 0.45, 0.3, 0.5),
+				);
+			}
 
 			let sprite = state.get_sprite(orb_sprite).unwrap();
-			sprite.draw_frame(Point2::new(orb_left, orb_y), "Default", 0, state);
+			sprite.draw_frame(life_pos, "Default", 0, state);
 
 			state.core.draw_text(
 				state.ui_font(),
 				Color::from_rgb_f(1., 1., 1.),
-				orb_right,
-				orb_top,
+				mana_pos.x,
+				mana_pos.y - orb_radius - lh,
 				FontAlign::Centre,
-				&format!("{}/{}", stats.mana as i32, stats.values.max_mana as i32,),
+				&format!(
+					"{}/{}",
+					utils::format_number(stats.mana, state.options.number_format),
+					utils::format_number(stats.values.max_mana, state.options.number_format),
+				),
 			);
 
 			draw_orb(
 				state,
 				orb_radius,
-				orb_right,
-				orb_y,
+				mana_pos.x,
+				mana_pos.y,
 				stats.mana / stats.values.max_mana,
 				Color::from_rgb_f(0.2, 0.2, 0.9),
 			);
-			sprite.draw_frame(Point2::new(orb_right, orb_y), "Default", 0, state);
+			sprite.draw_frame(mana_pos, "Default", 0, state);
 		}
 
 		if !self.inventory_shown
@@ -4736,6 +8868,16 @@ impl Map
 			);
 			text_y += lh;
 
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(1., 1., 1.),
+				center.x,
+				text_y,
+				FontAlign::Centre,
+				&format!("You Collected {} Souls", self.stats.souls),
+			);
+			text_y += lh;
+
 			state.core.draw_text(
 				state.ui_font(),
 				Color::from_rgb_f(1., 1., 1.),
@@ -4778,33 +8920,198 @@ impl Map
 			);
 		}
 
+		let info_pos = layout.info.resolve(bw, bh);
+		let info_align = layout.info.align();
 		state.core.draw_text(
 			state.ui_font(),
 			Color::from_rgb_f(1., 1., 1.),
-			pad + 6.,
-			pad,
-			FontAlign::Left,
+			info_pos.x,
+			info_pos.y,
+			info_align,
 			&format!("Height: {}m", self.level * 50),
 		);
 
+		let clear_time = self.clear_time.unwrap_or(state.time() - self.start_time);
+		let best_time = self
+			.best_time
+			.map(|t| format!(" / {}", utils::format_time(t)))
+			.unwrap_or("".to_string());
+		state.core.draw_text(
+			state.ui_font(),
+			Color::from_rgb_f(1., 1., 1.),
+			info_pos.x,
+			info_pos.y + lh,
+			info_align,
+			&format!(
+				"Time: {}{}{}",
+				utils::format_time(clear_time),
+				best_time,
+				if self.ghost_badge { " [Ghost]" } else { "" }
+			),
+		);
+
+		if !self.challenges.is_empty()
+		{
+			let challenges: Vec<_> = self.challenges.iter().map(|c| c.to_str()).collect();
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(0.9, 0.5, 0.3),
+				info_pos.x,
+				info_pos.y + 2. * lh,
+				info_align,
+				&format!("Trial: {}", challenges.join(", ")),
+			);
+		}
+
+		let mut row = if self.challenges.is_empty() { 2. } else { 3. };
+		if let Some(day) = self.daily
+		{
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(0.9, 0.5, 0.3),
+				info_pos.x,
+				info_pos.y + row * lh,
+				info_align,
+				&format!("Daily: {}", utils::format_day(day)),
+			);
+			row += 1.;
+		}
+		for map_mod in &self.map_mods
+		{
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(0.9, 0.5, 0.3),
+				info_pos.x,
+				info_pos.y + row * lh,
+				info_align,
+				map_mod.to_str(),
+			);
+			row += 1.;
+		}
+
+		if self.new_best_time
+		{
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(0.9, 0.9, 0.3),
+				state.buffer_width() / 2.,
+				state.buffer_height() / 2. - 64.,
+				FontAlign::Centre,
+				"New Best Time!",
+			);
+		}
+
+		if self.ghost_clear
+		{
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(0.5, 0.5, 1.),
+				state.buffer_width() / 2.,
+				state.buffer_height() / 2. - 64. + lh,
+				FontAlign::Centre,
+				&format!("Ghost Clear! +{} Souls", GHOST_CLEAR_SOULS),
+			);
+		}
+
 		let num_crystals_left = self.tiles.crystals.len() as i32 - self.num_crystals_done;
-		let text = if num_crystals_left > 0
+		let text = if self.objective_done
 		{
-			format!("Crystals: {}", num_crystals_left)
+			"Exit open!".to_string()
 		}
 		else
 		{
-			"Exit open!".to_string()
+			match self.objective
+			{
+				comps::Objective::BreakCrystals => format!("Crystals: {}", num_crystals_left),
+				comps::Objective::Survive =>
+				{
+					let left = comps::SURVIVE_TIME - (state.time() - self.objective_start_time);
+					format!("Survive: {}s", utils::max(0., left).ceil() as i32)
+				}
+				_ => self.objective.to_str().to_string(),
+			}
 		};
+		let crystals_pos = layout.crystals.resolve(bw, bh);
+		let crystals_align = layout.crystals.align();
 		state.core.draw_text(
 			state.ui_font(),
 			Color::from_rgb_f(1., 1., 1.),
-			state.buffer_width() - pad - 6.,
-			pad,
-			FontAlign::Right,
+			crystals_pos.x,
+			crystals_pos.y,
+			crystals_align,
 			&text,
 		);
 
+		if self.active_inventory.is_some()
+		{
+			state.core.draw_text(
+				state.ui_font(),
+				Color::from_rgb_f(0.9, 0.9, 0.3),
+				crystals_pos.x,
+				crystals_pos.y + lh,
+				crystals_align,
+				"Item swap pending",
+			);
+		}
+
+		if state.options.show_event_log
+		{
+			// Newest entries are on top, older ones fade out.
+			let mut y = crystals_pos.y + 2. * lh;
+			for (text, color, time) in self.event_log.iter().rev()
+			{
+				let age = state.time() - time;
+				if age > EVENT_LOG_DURATION
+				{
+					continue;
+				}
+				let f = utils::clamp((EVENT_LOG_DURATION - age) / EVENT_LOG_FADE, 0., 1.) as f32;
+				let (r, g, b, _) = color.to_rgba_f();
+				state.core.draw_text(
+					state.ui_font(),
+					Color::from_rgba_f(r * f, g * f, b * f, f),
+					crystals_pos.x,
+					y,
+					crystals_align,
+					text,
+				);
+				y += lh;
+			}
+		}
+
+		if let Some((hint, _, anchor)) = self.hint
+		{
+			if state.options.show_hints
+			{
+				// Hints without an anchor follow the player.
+				let anchor = anchor.or_else(|| {
+					self.world
+						.get::<&comps::Position>(self.player)
+						.ok()
+						.map(|position| {
+							let draw_pos = position.draw_pos(state.alpha);
+							Point2::new(draw_pos.x, draw_pos.y - draw_pos.z)
+						})
+				});
+				if let Some(anchor) = anchor
+				{
+					let pos = anchor + self.camera_shift(state);
+					let text = hint.text(&state.options.controls);
+					let tw = state.ui_font().get_text_width(&text) as f32;
+					let x = utils::clamp(pos.x, tw / 2. + lh, bw - tw / 2. - lh);
+					let y = utils::clamp(pos.y - 48., 2. * lh, bh - 4. * lh);
+					state.core.draw_text(
+						state.ui_font(),
+						Color::from_rgb_f(0.6, 1.0, 1.0),
+						x,
+						y,
+						FontAlign::Centre,
+						&text,
+					);
+				}
+			}
+		}
+
 		Ok(())
 	}
 }
@@ -4841,7 +9148,7 @@ fn draw_orb(state: &game_state::GameState, r: f32, dx: f32, dy: f32, f: f32, col
 
 pub fn draw_blade_blade(
 	pos: Point2<f32>, z_shift: f32, radius: f32, num_blades: i32, speed: f32, ratio: f32,
-	blade_scale: f32, state: &game_state::GameState,
+	blade_scale: f32, color: Color, state: &game_state::GameState,
 )
 {
 	let mut trail_vertices = vec![];
@@ -4853,8 +9160,6 @@ pub fn draw_blade_blade(
 		.collect();
 	let offsets = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.];
 	let speeds = [0.1, 0.3, 0.5, 0.7, 1.1, 1.3, 1.7, 1.9, 2.3, 3.1];
-	let color = Color::from_rgb_f(1., 0.2, 0.2);
-
 	for blade in 0..num_blades
 	{
 		let r = 32. * radius * radii[blade as usize];
@@ -4925,3 +9230,73 @@ pub fn draw_blade_blade(
 		PrimType::TriangleList,
 	);
 }
+
+#[test]
+fn missing_tile_layer_test()
+{
+	assert!(Tiles::new("data/test_no_tiles.tmx", "data/terrain.cfg", 0).is_err());
+	assert!(Tiles::new("data/test.tmx", "data/terrain.cfg", 0).is_ok());
+}
+
+#[test]
+fn build_summary_round_trip_test()
+{
+	let inventory = comps::Archetype::Pyromancer.starting_inventory();
+	let build = BuildSummary::new(
+		&inventory,
+		&comps::StatValues::default(),
+		comps::Archetype::Pyromancer,
+		3,
+	);
+	let text = format!("{}", slr_config::to_element(&build).unwrap());
+	let mut source = slr_config::Source::new(std::path::Path::new("build.cfg"), &text);
+	let element = slr_config::ConfigElement::from_source(&mut source).unwrap();
+	let loaded: BuildSummary = slr_config::from_element(&element, Some(&source)).unwrap();
+
+	assert_eq!(loaded.version, build.version);
+	assert_eq!(loaded.archetype, build.archetype);
+	assert_eq!(loaded.level, build.level);
+	assert_eq!(loaded.items.len(), build.items.len());
+	for (loaded_item, item) in loaded.items.iter().zip(&build.items)
+	{
+		match (loaded_item, item)
+		{
+			(Some(loaded_item), Some(item)) => assert!(loaded_item.same_item(item)),
+			(None, None) => (),
+			_ => panic!("Item mismatch"),
+		}
+	}
+	assert_eq!(loaded.stats, build.stats);
+}
+
+#[test]
+fn transform_frame_test()
+{
+	let identity = MapTransform {
+		transpose: false,
+		mirror_x: false,
+		mirror_y: false,
+	};
+	let mirror_x = MapTransform {
+		transpose: false,
+		mirror_x: true,
+		mirror_y: false,
+	};
+	let transpose = MapTransform {
+		transpose: true,
+		mirror_x: false,
+		mirror_y: false,
+	};
+	for id in 0..16
+	{
+		assert_eq!(identity.transform_frame(id), id);
+		assert_eq!(mirror_x.transform_frame(mirror_x.transform_frame(id)), id);
+	}
+	// Empty and full tiles are symmetric.
+	assert_eq!(transpose.transform_frame(0), 0);
+	assert_eq!(transpose.transform_frame(1), 1);
+	// Bottom edge becomes the right edge.
+	assert_eq!(transpose.transform_frame(2), 5);
+	// Top-left corner becomes the top-right corner.
+	assert_eq!(mirror_x.transform_frame(8), 9);
+}
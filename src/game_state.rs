@@ -1,5 +1,5 @@
-use crate::error::Result;
-use crate::{atlas, controls, palette, sfx, sprite, utils};
+use crate::error::{Error, ErrorKind, Result};
+use crate::{atlas, components, controls, palette, sfx, sprite, utils};
 use allegro::*;
 use allegro_font::*;
 use allegro_image::*;
@@ -7,11 +7,13 @@ use allegro_primitives::*;
 use allegro_ttf::*;
 use nalgebra::{Point2, Vector2};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::{fmt, path, sync};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Shared by the options slider and sanitize.
+pub const MAX_DROPPED_ITEMS_LIMIT: i32 = 200;
 
 fn default_min_level() -> i32
 {
@@ -33,7 +35,226 @@ pub struct GameSpec
 	pub start_map: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PickupFilter
+{
+	pub ignored_rarities: Vec<components::Rarity>,
+	pub ignored_prefixes: Vec<components::ItemPrefix>,
+	pub ignored_suffixes: Vec<components::ItemSuffix>,
+}
+
+impl PickupFilter
+{
+	pub fn ignores(&self, item: &components::Item) -> bool
+	{
+		self.ignored_rarities.contains(&item.rarity)
+			|| item
+				.prefixes
+				.iter()
+				.any(|(prefix, _, _)| self.ignored_prefixes.contains(prefix))
+			|| item
+				.suffixes
+				.iter()
+				.any(|(suffix, _, _)| self.ignored_suffixes.contains(suffix))
+	}
+
+	pub fn ignores_rarity(&self, rarity: components::Rarity) -> bool
+	{
+		self.ignored_rarities.contains(&rarity)
+	}
+
+	pub fn toggle_rarity(&mut self, rarity: components::Rarity)
+	{
+		if self.ignores_rarity(rarity)
+		{
+			self.ignored_rarities.retain(|r| *r != rarity);
+		}
+		else
+		{
+			self.ignored_rarities.push(rarity);
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum HudAnchor
+{
+	TopLeft,
+	TopCenter,
+	TopRight,
+	BottomLeft,
+	BottomCenter,
+	BottomRight,
+}
+
+// Offsets point inward from the anchor, so positive values move away from the screen edges.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct HudElement
+{
+	pub anchor: HudAnchor,
+	pub offset_x: f32,
+	pub offset_y: f32,
+}
+
+impl HudElement
+{
+	pub fn new(anchor: HudAnchor, offset_x: f32, offset_y: f32) -> Self
+	{
+		Self {
+			anchor: anchor,
+			offset_x: offset_x,
+			offset_y: offset_y,
+		}
+	}
+
+	pub fn resolve(&self, width: f32, height: f32) -> Point2<f32>
+	{
+		let x = match self.anchor
+		{
+			HudAnchor::TopLeft | HudAnchor::BottomLeft => self.offset_x,
+			HudAnchor::TopCenter | HudAnchor::BottomCenter => width / 2. + self.offset_x,
+			HudAnchor::TopRight | HudAnchor::BottomRight => width - self.offset_x,
+		};
+		let y = match self.anchor
+		{
+			HudAnchor::TopLeft | HudAnchor::TopCenter | HudAnchor::TopRight => self.offset_y,
+			HudAnchor::BottomLeft | HudAnchor::BottomCenter | HudAnchor::BottomRight =>
+			{
+				height - self.offset_y
+			}
+		};
+		Point2::new(x, y)
+	}
+
+	pub fn align(&self) -> FontAlign
+	{
+		match self.anchor
+		{
+			HudAnchor::TopLeft | HudAnchor::BottomLeft => FontAlign::Left,
+			HudAnchor::TopCenter | HudAnchor::BottomCenter => FontAlign::Centre,
+			HudAnchor::TopRight | HudAnchor::BottomRight => FontAlign::Right,
+		}
+	}
+}
+
+// What the player turns towards while attacking.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AutoFace
+{
+	// Face the direction of movement.
+	Classic,
+	NearestEnemy,
+	Cursor,
+}
+
+impl Default for AutoFace
+{
+	fn default() -> Self
+	{
+		AutoFace::Classic
+	}
+}
+
+// Colors of the better/worse markers when comparing items.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ComparisonColors
+{
+	GreenRed,
+	// Easier to tell apart with red-green color blindness.
+	BlueOrange,
+}
+
+impl ComparisonColors
+{
+	pub fn better(&self) -> Color
+	{
+		match self
+		{
+			ComparisonColors::GreenRed => Color::from_rgb_f(0.3, 0.9, 0.3),
+			ComparisonColors::BlueOrange => Color::from_rgb_f(0.3, 0.6, 1.),
+		}
+	}
+
+	pub fn worse(&self) -> Color
+	{
+		match self
+		{
+			ComparisonColors::GreenRed => Color::from_rgb_f(0.9, 0.3, 0.3),
+			ComparisonColors::BlueOrange => Color::from_rgb_f(1., 0.6, 0.1),
+		}
+	}
+}
+
+impl Default for ComparisonColors
+{
+	fn default() -> Self
+	{
+		ComparisonColors::GreenRed
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct HudLayout
+{
+	pub orb_radius: f32,
+	// Orb centers.
+	pub life_orb: HudElement,
+	pub mana_orb: HudElement,
+	// Height, time and trial lines.
+	pub info: HudElement,
+	// Crystal counter and the swap pending line.
+	pub crystals: HudElement,
+}
+
+impl HudLayout
+{
+	pub fn classic() -> Self
+	{
+		Self {
+			orb_radius: 31.,
+			life_orb: HudElement::new(HudAnchor::BottomLeft, 33., 33.),
+			mana_orb: HudElement::new(HudAnchor::BottomRight, 33., 33.),
+			info: HudElement::new(HudAnchor::TopLeft, 8., 2.),
+			crystals: HudElement::new(HudAnchor::TopRight, 8., 2.),
+		}
+	}
+
+	pub fn minimal() -> Self
+	{
+		Self {
+			orb_radius: 23.,
+			life_orb: HudElement::new(HudAnchor::BottomCenter, -27., 25.),
+			mana_orb: HudElement::new(HudAnchor::BottomCenter, 27., 25.),
+			info: HudElement::new(HudAnchor::TopLeft, 8., 2.),
+			crystals: HudElement::new(HudAnchor::TopRight, 8., 2.),
+		}
+	}
+
+	// The orbs move to the top of the screen to make room for the inventory.
+	pub fn with_inventory_shown(&self) -> Self
+	{
+		let orb_radius = 23.;
+		let orb_x = orb_radius + 4.;
+		let orb_y = 18. + orb_radius;
+		Self {
+			orb_radius: orb_radius,
+			life_orb: HudElement::new(HudAnchor::TopCenter, -orb_x, orb_y),
+			mana_orb: HudElement::new(HudAnchor::TopCenter, orb_x, orb_y),
+			..*self
+		}
+	}
+}
+
+impl Default for HudLayout
+{
+	fn default() -> Self
+	{
+		Self::classic()
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Options
 {
 	pub fullscreen: bool,
@@ -43,11 +264,71 @@ pub struct Options
 	pub vsync_method: i32,
 	pub sfx_volume: f32,
 	pub music_volume: f32,
+	// How quickly the camera catches up with the player, 1 (floaty) to 10 (snappy).
 	pub camera_speed: i32,
+	pub camera_deadzone_width: f32,
+	pub camera_deadzone_height: f32,
 	pub grab_mouse: bool,
 	pub ui_scale: f32,
 	pub frac_scale: bool,
 	pub ray_casting_steps: i32,
+	pub pickup_filter: PickupFilter,
+	pub defer_item_swaps: bool,
+	pub pixel_snap: bool,
+	pub asset_fallback: bool,
+	pub soft_fall: bool,
+	pub item_despawn: bool,
+	pub hud_layout: HudLayout,
+	// The last hand-edited layout, kept around when switching to a preset.
+	pub custom_hud_layout: Option<HudLayout>,
+	pub show_hints: bool,
+	pub fade_duration: f32,
+	pub preload_assets: bool,
+	pub number_format: utils::NumberFormat,
+	// Seconds after a crystal first spawns enemies within which clearing it grants bonus items, 0 disables.
+	pub quick_clear_window: f32,
+	pub quick_clear_items: i32,
+	pub game_speed: f32,
+	pub show_event_log: bool,
+	pub show_resistances: bool,
+	pub attack_buffer_ms: f32,
+	pub auto_recast: bool,
+	// Shrinks the blade blade orbit near walls and holes.
+	pub blade_wall_clip: bool,
+	// Whether enemy corpses block movement.
+	pub corpse_collision: bool,
+	// Play a wind-up sound before ranged enemies fire.
+	pub enemy_tell_sound: bool,
+	// Reward clearing a map without killing more elves than its crystals started with.
+	pub ghost_clear: bool,
+	// Show how many elves each crystal is still waiting on.
+	pub crystal_enemy_count: bool,
+	// Briefly slow down and zoom in when a rare enemy dies.
+	pub boss_slowmo: bool,
+	// Draw the predicted paths of enemy projectiles.
+	pub projectile_previews: bool,
+	pub auto_face: AutoFace,
+	pub comparison_colors: ComparisonColors,
+	// Whether the second chance revive recharges on every map, rather than once per run.
+	pub second_chance_per_map: bool,
+	// Minimum time between blade blade cast sounds.
+	pub blade_sound_interval_ms: f32,
+	// Seconds life regen pauses for after taking a hit, 0 disables.
+	pub regen_delay: f32,
+	// Oldest low rarity items get removed past this many drops, 0 disables.
+	pub max_dropped_items: i32,
+	// Randomly rotates and mirrors maps.
+	pub transform_maps: bool,
+	// Rerolls crystal kinds on every reset instead of deriving them from the map seed. Crystal kind
+	// picks the item kind that drops, so this also stops a save from having a fixed loot pool.
+	pub random_crystal_kinds: bool,
+	// Restores the last windowed size and position on launch.
+	pub remember_window: bool,
+	pub window_x: Option<i32>,
+	pub window_y: Option<i32>,
+	pub debug_draw: bool,
+	// With debug drawing on, lethal damage restores the player to full life instead.
+	pub instant_respawn: bool,
 
 	pub controls: controls::Controls,
 }
@@ -65,15 +346,205 @@ impl Default for Options
 			sfx_volume: 1.,
 			music_volume: 1.,
 			camera_speed: 4,
+			camera_deadzone_width: 48.,
+			camera_deadzone_height: 32.,
 			grab_mouse: false,
 			ui_scale: 1.,
 			frac_scale: true,
 			ray_casting_steps: 16,
+			pickup_filter: PickupFilter::default(),
+			defer_item_swaps: false,
+			pixel_snap: true,
+			asset_fallback: true,
+			soft_fall: false,
+			item_despawn: false,
+			hud_layout: HudLayout::default(),
+			custom_hud_layout: None,
+			show_hints: true,
+			fade_duration: 0.25,
+			preload_assets: true,
+			number_format: utils::NumberFormat::default(),
+			quick_clear_window: 45.,
+			quick_clear_items: 1,
+			game_speed: 1.,
+			show_event_log: true,
+			show_resistances: true,
+			attack_buffer_ms: 150.,
+			auto_recast: false,
+			blade_wall_clip: false,
+			corpse_collision: true,
+			enemy_tell_sound: true,
+			ghost_clear: false,
+			crystal_enemy_count: true,
+			boss_slowmo: false,
+			projectile_previews: false,
+			auto_face: AutoFace::default(),
+			comparison_colors: ComparisonColors::default(),
+			second_chance_per_map: true,
+			blade_sound_interval_ms: 150.,
+			regen_delay: 0.,
+			max_dropped_items: 40,
+			transform_maps: false,
+			random_crystal_kinds: false,
+			remember_window: true,
+			window_x: None,
+			window_y: None,
+			debug_draw: false,
+			instant_respawn: false,
 			controls: controls::Controls::new_game(),
 		}
 	}
 }
 
+impl Options
+{
+	pub fn sanitize(&mut self)
+	{
+		let default = Options::default();
+		self.width = utils::clamp(self.width, 320, 7680);
+		self.height = utils::clamp(self.height, 240, 4320);
+		self.vsync_method = utils::clamp(self.vsync_method, 0, 2);
+		self.sfx_volume = utils::clamp(self.sfx_volume, 0., 4.);
+		self.music_volume = utils::clamp(self.music_volume, 0., 4.);
+		self.camera_speed = utils::clamp(self.camera_speed, 1, 10);
+		self.ui_scale = utils::clamp(self.ui_scale, 1., 4.);
+		self.ray_casting_steps = utils::clamp(self.ray_casting_steps, 4, 32);
+		if !self.sfx_volume.is_finite()
+		{
+			self.sfx_volume = default.sfx_volume;
+		}
+		if !self.music_volume.is_finite()
+		{
+			self.music_volume = default.music_volume;
+		}
+		if !self.ui_scale.is_finite()
+		{
+			self.ui_scale = default.ui_scale;
+		}
+		if !self.camera_deadzone_width.is_finite()
+		{
+			self.camera_deadzone_width = default.camera_deadzone_width;
+		}
+		if !self.camera_deadzone_height.is_finite()
+		{
+			self.camera_deadzone_height = default.camera_deadzone_height;
+		}
+		if !self.fade_duration.is_finite()
+		{
+			self.fade_duration = default.fade_duration;
+		}
+		self.fade_duration = utils::clamp(self.fade_duration, 0., 2.);
+		if !self.quick_clear_window.is_finite()
+		{
+			self.quick_clear_window = default.quick_clear_window;
+		}
+		self.quick_clear_window = utils::clamp(self.quick_clear_window, 0., 600.);
+		self.quick_clear_items = utils::clamp(self.quick_clear_items, 0, 5);
+		if !self.game_speed.is_finite()
+		{
+			self.game_speed = default.game_speed;
+		}
+		self.game_speed = utils::clamp(self.game_speed, 0.5, 1.);
+		if !self.attack_buffer_ms.is_finite()
+		{
+			self.attack_buffer_ms = default.attack_buffer_ms;
+		}
+		self.attack_buffer_ms = utils::clamp(self.attack_buffer_ms, 0., 500.);
+		if !self.blade_sound_interval_ms.is_finite()
+		{
+			self.blade_sound_interval_ms = default.blade_sound_interval_ms;
+		}
+		self.blade_sound_interval_ms = utils::clamp(self.blade_sound_interval_ms, 0., 1000.);
+		if !self.regen_delay.is_finite()
+		{
+			self.regen_delay = default.regen_delay;
+		}
+		self.regen_delay = utils::clamp(self.regen_delay, 0., 10.);
+		self.max_dropped_items = utils::clamp(self.max_dropped_items, 0, MAX_DROPPED_ITEMS_LIMIT);
+		self.camera_deadzone_width = utils::clamp(self.camera_deadzone_width, 0., 256.);
+		self.camera_deadzone_height = utils::clamp(self.camera_deadzone_height, 0., 256.);
+		self.controls.sanitize();
+		self.controls.add_missing_actions(&default.controls);
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Hint
+{
+	Move,
+	BreakCrystal,
+	Inventory,
+	Exit,
+}
+
+impl Hint
+{
+	pub fn text(&self, controls: &controls::Controls) -> String
+	{
+		match self
+		{
+			Hint::Move => format!(
+				"Move with {}, {}, {}, {}",
+				controls.get_action_string(controls::Action::MoveUp),
+				controls.get_action_string(controls::Action::MoveLeft),
+				controls.get_action_string(controls::Action::MoveDown),
+				controls.get_action_string(controls::Action::MoveRight),
+			),
+			Hint::BreakCrystal => format!(
+				"Approach the crystal and defeat its guardians, cast blades with {}",
+				controls.get_action_string(controls::Action::BladeBlade),
+			),
+			Hint::Inventory => format!(
+				"Open the inventory with {} to equip items",
+				controls.get_action_string(controls::Action::Inventory),
+			),
+			Hint::Exit => format!(
+				"The exit is open, stand on it to descend. Jump with {}",
+				controls.get_action_string(controls::Action::Jump),
+			),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SeenHints
+{
+	hints: Vec<Hint>,
+}
+
+impl SeenHints
+{
+	pub fn has_seen(&self, hint: Hint) -> bool
+	{
+		self.hints.contains(&hint)
+	}
+
+	pub fn mark_seen(&mut self, hint: Hint)
+	{
+		if !self.has_seen(hint)
+		{
+			self.hints.push(hint);
+		}
+	}
+}
+
+pub fn load_seen_hints(core: &Core) -> Result<SeenHints>
+{
+	Ok(utils::load_user_data(core, "seen_hints.cfg")?.unwrap_or_default())
+}
+
+pub fn save_seen_hints(core: &Core, seen_hints: &SeenHints) -> Result<()>
+{
+	utils::save_user_data(core, "seen_hints.cfg", seen_hints)
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Asset
+{
+	Bitmap(&'static str),
+	Sprite(&'static str),
+}
+
 #[derive(Debug)]
 pub enum NextScreen
 {
@@ -97,13 +568,21 @@ pub struct GameState
 	pub atlas: atlas::Atlas,
 	pub ui_font: Option<Font>,
 	pub options: Options,
+	pub seen_hints: SeenHints,
 	bitmaps: HashMap<String, Bitmap>,
 	sprites: HashMap<String, sprite::Sprite>,
+	placeholder_bitmap: Option<Bitmap>,
+	placeholder_sprite: Option<sprite::Sprite>,
+	missing_assets: RefCell<HashSet<String>>,
 	pub controls: controls::ControlsHandler,
 	pub menu_controls: controls::ControlsHandler,
 	pub game_ui_controls: controls::ControlsHandler,
 	pub track_mouse: bool,
 	pub mouse_pos: Point2<i32>,
+	pub num_joysticks: i32,
+	toast: Option<(String, f64)>,
+	fade_out_end: Option<f64>,
+	fade_in_start: f64,
 
 	pub draw_scale: f32,
 	pub display_width: f32,
@@ -127,13 +606,18 @@ pub struct GameState
 
 	pub palettes: palette::PaletteList,
 	pub game_spec: GameSpec,
+	pub challenges: Vec<components::Challenge>,
+	pub archetype: components::Archetype,
+	pub daily_run: bool,
 
 	pub alpha: f32,
 }
 
 pub fn load_options(core: &Core) -> Result<Options>
 {
-	Ok(utils::load_user_data(core, "options.cfg")?.unwrap_or_default())
+	let mut options: Options = utils::load_user_data(core, "options.cfg")?.unwrap_or_default();
+	options.sanitize();
+	Ok(options)
 }
 
 pub fn save_options(core: &Core, options: &Options) -> Result<()>
@@ -141,6 +625,20 @@ pub fn save_options(core: &Core, options: &Options) -> Result<()>
 	utils::save_user_data(core, "options.cfg", options)
 }
 
+pub fn export_options(core: &Core, options: &Options) -> Result<()>
+{
+	utils::save_user_data(core, "options_export.cfg", options)
+}
+
+pub fn import_options(core: &Core) -> Result<Option<Options>>
+{
+	let options: Option<Options> = utils::load_user_data(core, "options_export.cfg")?;
+	Ok(options.map(|mut options| {
+		options.sanitize();
+		options
+	}))
+}
+
 impl GameState
 {
 	pub fn new() -> Result<Self>
@@ -150,6 +648,7 @@ impl GameState
 		core.set_org_name("SiegeLord");
 
 		let options = load_options(&core)?;
+		let seen_hints = load_seen_hints(&core)?;
 		let prim = PrimitivesAddon::init(&core)?;
 		let image = ImageAddon::init(&core)?;
 		let font = FontAddon::init(&core)?;
@@ -172,12 +671,16 @@ impl GameState
 		let controls = controls::ControlsHandler::new(options.controls.clone());
 		Ok(Self {
 			options: options,
+			seen_hints: seen_hints,
 			core: core,
 			prim: prim,
 			image: image,
 			tick: 0,
 			bitmaps: HashMap::new(),
 			sprites: HashMap::new(),
+			placeholder_bitmap: None,
+			placeholder_sprite: None,
+			missing_assets: RefCell::new(HashSet::new()),
 			font: font,
 			ttf: ttf,
 			sfx: sfx,
@@ -194,6 +697,10 @@ impl GameState
 			game_ui_controls: controls::ControlsHandler::new(controls::Controls::new_game_ui()),
 			track_mouse: true,
 			mouse_pos: Point2::new(0, 0),
+			num_joysticks: unsafe { allegro_sys::al_get_num_joysticks() },
+			toast: None,
+			fade_out_end: None,
+			fade_in_start: 0.,
 			palette_shader: Default::default(),
 			basic_shader: Default::default(),
 			jfa_seed_shader: Default::default(),
@@ -208,6 +715,9 @@ impl GameState
 			distance_buffer_2: None,
 			distance_buffer_fin: None,
 			game_spec: utils::load_config("data/game.cfg")?,
+			challenges: vec![],
+			archetype: components::Archetype::Wanderer,
+			daily_run: false,
 			alpha: 0.,
 		})
 	}
@@ -222,6 +732,18 @@ impl GameState
 		self.buffer2.as_ref().unwrap()
 	}
 
+	pub fn snap_point(&self, point: Point2<f32>) -> Point2<f32>
+	{
+		if self.options.pixel_snap
+		{
+			utils::round_point(point)
+		}
+		else
+		{
+			point
+		}
+	}
+
 	pub fn buffer_width(&self) -> f32
 	{
 		self.buffer1().get_width() as f32
@@ -232,6 +754,86 @@ impl GameState
 		self.buffer1().get_height() as f32
 	}
 
+	pub fn show_toast(&mut self, text: &str)
+	{
+		self.toast = Some((text.to_string(), self.core.get_time() + 2.));
+	}
+
+	pub fn draw_toast(&self)
+	{
+		if let Some((text, end_time)) = self.toast.as_ref()
+		{
+			if self.core.get_time() < *end_time
+			{
+				let lh = self.ui_font().get_line_height() as f32;
+				self.core.draw_text(
+					self.ui_font(),
+					Color::from_rgb_f(1., 1., 1.),
+					self.buffer_width() / 2.,
+					self.buffer_height() - 3. * lh,
+					FontAlign::Centre,
+					text,
+				);
+			}
+		}
+	}
+
+	pub fn start_fade_out(&mut self)
+	{
+		if self.fade_out_end.is_none()
+		{
+			self.fade_out_end = Some(self.core.get_time() + self.options.fade_duration as f64);
+		}
+	}
+
+	pub fn fade_out_done(&self) -> bool
+	{
+		self.fade_out_end
+			.map(|end_time| self.core.get_time() >= end_time)
+			.unwrap_or(false)
+	}
+
+	pub fn start_fade_in(&mut self)
+	{
+		self.fade_out_end = None;
+		self.fade_in_start = self.core.get_time();
+	}
+
+	pub fn draw_fade(&self)
+	{
+		let duration = self.options.fade_duration as f64;
+		if duration <= 0.
+		{
+			return;
+		}
+		let time = self.core.get_time();
+		let f = if let Some(end_time) = self.fade_out_end
+		{
+			1. - (end_time - time) / duration
+		}
+		else
+		{
+			1. - (time - self.fade_in_start) / duration
+		};
+		let f = utils::clamp(f, 0., 1.) as f32;
+		if f <= 0.
+		{
+			return;
+		}
+		self.core
+			.use_shader(Some(&*self.basic_shader.upgrade().unwrap()))
+			.unwrap();
+		self.core
+			.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
+		self.prim.draw_filled_rectangle(
+			0.,
+			0.,
+			self.buffer_width(),
+			self.buffer_height(),
+			Color::from_rgba_f(0., 0., 0., f),
+		);
+	}
+
 	pub fn ui_font(&self) -> &Font
 	{
 		self.ui_font.as_ref().unwrap()
@@ -321,43 +923,178 @@ impl GameState
 		(x, y)
 	}
 
+	pub fn init_placeholders(&mut self) -> Result<()>
+	{
+		let placeholder_palette = "data/player_pal.png";
+		if self
+			.palettes
+			.get_palette_index(placeholder_palette)
+			.is_err()
+		{
+			self.palettes.add_palette(&self.core, placeholder_palette)?;
+		}
+		let bitmap = Bitmap::new(&self.core, 16, 16)
+			.map_err(|_| "Couldn't create placeholder bitmap".to_string())?;
+		self.core.set_target_bitmap(Some(&bitmap));
+		self.core.clear_to_color(Color::from_rgb_f(1., 0., 1.));
+		self.placeholder_sprite = Some(sprite::Sprite::new_placeholder(
+			&self.core,
+			&mut self.atlas,
+			&bitmap,
+			placeholder_palette,
+		)?);
+		self.placeholder_bitmap = Some(bitmap);
+		Ok(())
+	}
+
+	fn warn_missing(&self, name: &str, e: &Error)
+	{
+		if self.missing_assets.borrow_mut().insert(name.to_string())
+		{
+			println!("Warning: using a placeholder for '{}': {}", name, e);
+		}
+	}
+
 	pub fn cache_bitmap<'l>(&'l mut self, name: &str) -> Result<&'l Bitmap>
 	{
-		Ok(match self.bitmaps.entry(name.to_string())
+		if !self.bitmaps.contains_key(name)
 		{
-			Entry::Occupied(o) => o.into_mut(),
-			Entry::Vacant(v) => v.insert(utils::load_bitmap(&self.core, name)?),
-		})
+			match utils::load_bitmap(&self.core, name)
+			{
+				Ok(bitmap) =>
+				{
+					self.bitmaps.insert(name.to_string(), bitmap);
+				}
+				Err(e) if self.options.asset_fallback && self.placeholder_bitmap.is_some() =>
+				{
+					self.warn_missing(name, &e);
+					return Ok(self.placeholder_bitmap.as_ref().unwrap());
+				}
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(&self.bitmaps[name])
 	}
 
 	pub fn cache_sprite<'l>(&'l mut self, name: &str) -> Result<&'l sprite::Sprite>
 	{
-		Ok(match self.sprites.entry(name.to_string())
+		if !self.sprites.contains_key(name)
 		{
-			Entry::Occupied(o) => o.into_mut(),
-			Entry::Vacant(v) => v.insert(sprite::Sprite::load(
-				name,
-				&self.core,
-				&mut self.atlas,
-				&mut self.palettes,
-			)?),
-		})
+			match sprite::Sprite::load(name, &self.core, &mut self.atlas, &mut self.palettes)
+			{
+				Ok(sprite) =>
+				{
+					self.sprites.insert(name.to_string(), sprite);
+				}
+				Err(e) if self.options.asset_fallback && self.placeholder_sprite.is_some() =>
+				{
+					self.warn_missing(name, &e);
+					return Ok(self.placeholder_sprite.as_ref().unwrap());
+				}
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(&self.sprites[name])
+	}
+
+	pub fn get_sprite_names(&self) -> Vec<String>
+	{
+		let mut names: Vec<_> = self.sprites.keys().cloned().collect();
+		names.sort();
+		names
+	}
+
+	// Assets that failed to load and got replaced by a placeholder.
+	pub fn get_missing_assets(&self) -> Vec<String>
+	{
+		let mut names: Vec<_> = self.missing_assets.borrow().iter().cloned().collect();
+		names.sort();
+		names
+	}
+
+	pub fn cache_asset(&mut self, asset: Asset) -> Result<()>
+	{
+		match asset
+		{
+			Asset::Bitmap(name) =>
+			{
+				self.cache_bitmap(name)?;
+			}
+			Asset::Sprite(name) =>
+			{
+				self.cache_sprite(name)?;
+			}
+		}
+		Ok(())
+	}
+
+	pub fn is_asset_cached(&self, asset: Asset) -> bool
+	{
+		match asset
+		{
+			Asset::Bitmap(name) => self.bitmaps.contains_key(name),
+			Asset::Sprite(name) => self.sprites.contains_key(name),
+		}
+	}
+
+	// Frames are re-inserted into the atlas without freeing the old ones, so every reload grows
+	// the atlas (possibly by whole pages). This is only meant for development, restart to compact.
+	pub fn reload_sprites(&mut self)
+	{
+		let names: Vec<String> = self.sprites.keys().cloned().collect();
+		for name in names
+		{
+			match sprite::Sprite::load(&name, &self.core, &mut self.atlas, &mut self.palettes)
+			{
+				Ok(sprite) =>
+				{
+					self.sprites.insert(name, sprite);
+				}
+				Err(e) => println!("Couldn't reload sprite '{}': {}", name, e),
+			}
+		}
 	}
 
 	pub fn get_bitmap<'l>(&'l self, name: &str) -> Result<&'l Bitmap>
 	{
-		Ok(self
-			.bitmaps
-			.get(name)
-			.ok_or_else(|| format!("{name} is not cached!"))?)
+		match self.bitmaps.get(name)
+		{
+			Some(bitmap) => Ok(bitmap),
+			None =>
+			{
+				let e = Error::with_kind(ErrorKind::Asset, format!("{name} is not cached!"), None);
+				match self.placeholder_bitmap.as_ref()
+				{
+					Some(bitmap) if self.options.asset_fallback =>
+					{
+						self.warn_missing(name, &e);
+						Ok(bitmap)
+					}
+					_ => Err(e),
+				}
+			}
+		}
 	}
 
 	pub fn get_sprite<'l>(&'l self, name: &str) -> Result<&'l sprite::Sprite>
 	{
-		Ok(self
-			.sprites
-			.get(name)
-			.ok_or_else(|| format!("{name} is not cached!"))?)
+		match self.sprites.get(name)
+		{
+			Some(sprite) => Ok(sprite),
+			None =>
+			{
+				let e = Error::with_kind(ErrorKind::Asset, format!("{name} is not cached!"), None);
+				match self.placeholder_sprite.as_ref()
+				{
+					Some(sprite) if self.options.asset_fallback =>
+					{
+						self.warn_missing(name, &e);
+						Ok(sprite)
+					}
+					_ => Err(e),
+				}
+			}
+		}
 	}
 
 	pub fn time(&self) -> f64
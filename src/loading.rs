@@ -0,0 +1,121 @@
+use crate::error::Result;
+use crate::{game, game_state, utils};
+
+use allegro::*;
+use allegro_font::*;
+use allegro_primitives::*;
+use nalgebra::Point2;
+
+// Loads the game assets a few at a time so the screen keeps updating. Allegro bitmaps and the atlas
+// have to be touched from the thread that owns the display, so this runs on the logic ticks rather
+// than a background thread.
+pub struct Loading
+{
+	resume: bool,
+	next_asset: usize,
+}
+
+impl Loading
+{
+	pub fn new(resume: bool, state: &mut game_state::GameState) -> Result<Self>
+	{
+		state.controls.clear_action_states();
+		Ok(Self {
+			resume: resume,
+			next_asset: 0,
+		})
+	}
+
+	pub fn is_done(&self) -> bool
+	{
+		self.next_asset >= game::GAME_ASSETS.len()
+	}
+
+	pub fn input(
+		&mut self, event: &Event, state: &mut game_state::GameState,
+	) -> Result<Option<game_state::NextScreen>>
+	{
+		match *event
+		{
+			Event::MouseAxes { x, y, .. } =>
+			{
+				if state.track_mouse
+				{
+					let (x, y) = state.transform_mouse(x as f32, y as f32);
+					state.mouse_pos = Point2::new(x as i32, y as i32);
+				}
+			}
+			_ => (),
+		}
+		Ok(None)
+	}
+
+	pub fn logic(
+		&mut self, state: &mut game_state::GameState,
+	) -> Result<Option<game_state::NextScreen>>
+	{
+		// Spend at most half a tick loading, but always make progress.
+		let start = state.core.get_time();
+		while !self.is_done()
+		{
+			state.cache_asset(game::GAME_ASSETS[self.next_asset])?;
+			self.next_asset += 1;
+			if state.core.get_time() - start > utils::DT as f64 / 2.
+			{
+				break;
+			}
+		}
+		if self.is_done()
+		{
+			Ok(Some(game_state::NextScreen::Game(self.resume)))
+		}
+		else
+		{
+			Ok(None)
+		}
+	}
+
+	pub fn draw(&mut self, state: &game_state::GameState) -> Result<()>
+	{
+		state.core.clear_to_color(Color::from_rgb_f(0., 0., 0.));
+		state
+			.core
+			.use_shader(Some(&*state.basic_shader.upgrade().unwrap()))
+			.unwrap();
+		state
+			.core
+			.set_blender(BlendOperation::Add, BlendMode::One, BlendMode::InverseAlpha);
+
+		let f = self.next_asset as f32 / utils::max(1, game::GAME_ASSETS.len()) as f32;
+		let cx = state.buffer_width() / 2.;
+		let cy = state.buffer_height() / 2.;
+		let w = state.buffer_width() / 3.;
+		let h = 6.;
+		let lh = state.ui_font().get_line_height() as f32;
+
+		state.core.draw_text(
+			state.ui_font(),
+			Color::from_rgb_f(1., 1., 1.),
+			cx,
+			cy - h - 1.5 * lh,
+			FontAlign::Centre,
+			"Loading...",
+		);
+		state.prim.draw_filled_rectangle(
+			cx - w / 2.,
+			cy - h / 2.,
+			cx - w / 2. + w * f,
+			cy + h / 2.,
+			Color::from_rgb_f(0.6, 1.0, 1.0),
+		);
+		state.prim.draw_rectangle(
+			cx - w / 2. - 1.,
+			cy - h / 2. - 1.,
+			cx + w / 2. + 1.,
+			cy + h / 2. + 1.,
+			Color::from_rgb_f(1., 1., 1.),
+			1.,
+		);
+		Ok(())
+	}
+}
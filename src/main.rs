@@ -10,6 +10,7 @@ mod controls;
 mod error;
 mod game;
 mod game_state;
+mod loading;
 mod menu;
 mod palette;
 mod sfx;
@@ -30,6 +31,49 @@ enum Screen
 {
 	Game(game::Game),
 	Menu(menu::Menu),
+	Loading(loading::Loading),
+}
+
+fn game_speed(screen: &Screen, state: &game_state::GameState) -> f32
+{
+	match screen
+	{
+		Screen::Game(game) => state.options.game_speed * game.time_scale(state),
+		_ => 1.,
+	}
+}
+
+// Whether a window placed here would have its title bar on one of the monitors.
+fn window_position_visible(core: &Core, x: i32, y: i32, width: i32) -> bool
+{
+	let margin = 32;
+	for adapter in 0..core.get_num_video_adapters()
+	{
+		if let Ok(info) = core.get_monitor_info(adapter)
+		{
+			if x + width > info.x1 + margin
+				&& x < info.x2 - margin
+				&& y >= info.y1
+				&& y < info.y2 - margin
+			{
+				return true;
+			}
+		}
+	}
+	false
+}
+
+fn remember_window(display: &Display, state: &mut game_state::GameState)
+{
+	if !state.options.remember_window || state.options.fullscreen
+	{
+		return;
+	}
+	let (x, y) = display.get_window_position();
+	state.options.window_x = Some(x);
+	state.options.window_y = Some(y);
+	state.options.width = display.get_width();
+	state.options.height = display.get_height();
 }
 
 fn real_main() -> Result<()>
@@ -52,6 +96,17 @@ fn real_main() -> Result<()>
 			DisplayOptionImportance::Suggest,
 		);
 	}
+	if state.options.remember_window && !state.options.fullscreen
+	{
+		if let (Some(x), Some(y)) = (state.options.window_x, state.options.window_y)
+		{
+			// The monitor it was on might be gone.
+			if window_position_visible(&state.core, x, y, state.options.width)
+			{
+				state.core.set_new_window_position(x, y);
+			}
+		}
+	}
 	let mut display = Display::new(&state.core, state.options.width, state.options.height)
 		.map_err(|_| "Couldn't create display".to_string())?;
 
@@ -63,6 +118,7 @@ fn real_main() -> Result<()>
 	state.jfa_jump_shader = utils::load_shader(&mut display, "data/jump")?;
 	state.jfa_dist_shader = utils::load_shader(&mut display, "data/dist")?;
 	state.ray_casting_shader = utils::load_shader(&mut display, "data/ray_casting")?;
+	state.init_placeholders()?;
 
 	let timer = Timer::new(&state.core, utils::DT as f64)
 		.map_err(|_| "Couldn't create timer".to_string())?;
@@ -96,12 +152,14 @@ fn real_main() -> Result<()>
 	//let mut cur_screen = Screen::Game(game::Game::new(&mut state)?);
 
 	let mut logics_without_draw = 0;
+	let mut pending_screen = None;
 	let mut old_fullscreen = state.options.fullscreen;
 	let mut old_ui_scale = state.options.ui_scale;
 	let mut old_frac_scale = state.options.frac_scale;
 
 	let prev_frame_start = state.core.get_time();
 	let mut logic_end = prev_frame_start;
+	let mut speed_accumulator = 0.;
 	let mut frame_count = 0;
 	if state.options.grab_mouse
 	{
@@ -126,18 +184,23 @@ fn real_main() -> Result<()>
 				{
 					Screen::Game(game) => game.resize(&state),
 					Screen::Menu(menu) => menu.resize(&state),
+					Screen::Loading(_) => (),
 				}
 			}
 
 			let frame_start = state.core.get_time();
 			state.core.set_target_bitmap(Some(state.buffer1()));
-			state.alpha = (frame_start - logic_end) as f32 / utils::DT;
+			state.alpha =
+				(frame_start - logic_end) as f32 / (utils::DT / game_speed(&cur_screen, &state));
 
 			match &mut cur_screen
 			{
 				Screen::Game(game) => game.draw(&state)?,
 				Screen::Menu(menu) => menu.draw(&state)?,
+				Screen::Loading(loading) => loading.draw(&state)?,
 			}
+			state.draw_fade();
+			state.draw_toast();
 
 			if state.options.vsync_method == 2
 			{
@@ -210,22 +273,43 @@ fn real_main() -> Result<()>
 		state.controls.decode_event(&event);
 		state.menu_controls.decode_event(&event);
 		state.game_ui_controls.decode_event(&event);
-		let mut next_screen = match &mut cur_screen
+		// Input is dropped while fading out to the next screen.
+		let mut next_screen = if pending_screen.is_some()
+		{
+			None
+		}
+		else
 		{
-			Screen::Game(game) => game.input(&event, &mut state)?,
-			Screen::Menu(menu) => menu.input(&event, &mut state)?,
+			match &mut cur_screen
+			{
+				Screen::Game(game) => game.input(&event, &mut state)?,
+				Screen::Menu(menu) => menu.input(&event, &mut state)?,
+				Screen::Loading(loading) => loading.input(&event, &mut state)?,
+			}
 		};
 		state.menu_controls.clear_action_states();
 		state.game_ui_controls.clear_action_states();
 
 		match event
 		{
-			Event::DisplayClose { .. } => quit = true,
+			Event::DisplayClose { .. } =>
+			{
+				if let Screen::Game(game) = &cur_screen
+				{
+					// Don't let a failed save keep the window open.
+					if let Err(e) = game.save_on_close(&state)
+					{
+						println!("Couldn't save on close: {}", e);
+					}
+				}
+				quit = true;
+			}
 			Event::DisplayResize { .. } =>
 			{
 				display
 					.acknowledge_resize()
 					.map_err(|_| "Couldn't acknowledge resize".to_string())?;
+				remember_window(&display, &mut state);
 			}
 			Event::DisplaySwitchIn { .. } =>
 			{
@@ -260,6 +344,20 @@ fn real_main() -> Result<()>
 					.core
 					.reconfigure_joysticks()
 					.map_err(|_| "Couldn't reconfigure joysticks".to_string())?;
+				// Inputs from a removed device would otherwise stay held.
+				state.controls.clear_joystick_states();
+				state.menu_controls.clear_joystick_states();
+				state.game_ui_controls.clear_joystick_states();
+				let num_joysticks = unsafe { al_get_num_joysticks() };
+				if num_joysticks > state.num_joysticks
+				{
+					state.show_toast("Controller connected");
+				}
+				else if num_joysticks < state.num_joysticks
+				{
+					state.show_toast("Controller disconnected");
+				}
+				state.num_joysticks = num_joysticks;
 			}
 			Event::TimerTick { .. } =>
 			{
@@ -268,11 +366,20 @@ fn real_main() -> Result<()>
 					continue;
 				}
 
-				if next_screen.is_none()
+				// Slowing the game down skips logic ticks, so DT and the tick-based timers are unchanged.
+				speed_accumulator += game_speed(&cur_screen, &state);
+				let do_logic = speed_accumulator >= 1.;
+				if do_logic
+				{
+					speed_accumulator -= 1.;
+				}
+
+				if do_logic && next_screen.is_none() && pending_screen.is_none()
 				{
 					next_screen = match &mut cur_screen
 					{
 						Screen::Game(game) => game.logic(&mut state)?,
+						Screen::Loading(loading) => loading.logic(&mut state)?,
 						_ => None,
 					}
 				}
@@ -286,33 +393,71 @@ fn real_main() -> Result<()>
 				logics_without_draw += 1;
 				state.sfx.update_sounds(&state.core)?;
 
-				if !state.paused
+				if do_logic
 				{
-					state.tick += 1;
+					if !state.paused
+					{
+						state.tick += 1;
+					}
+					logic_end = state.core.get_time();
 				}
-				logic_end = state.core.get_time();
 			}
 			_ => (),
 		}
 
 		if let Some(next_screen) = next_screen
 		{
+			if let game_state::NextScreen::Quit = next_screen
+			{
+				quit = true;
+			}
+			else
+			{
+				state.start_fade_out();
+				pending_screen = Some(next_screen);
+			}
+		}
+
+		if pending_screen.is_some() && state.fade_out_done()
+		{
+			let next_screen = pending_screen.take().unwrap();
 			match next_screen
 			{
 				game_state::NextScreen::Game(resume) =>
 				{
-					cur_screen = Screen::Game(game::Game::new(resume, &mut state)?);
+					let loaded = match &cur_screen
+					{
+						Screen::Loading(loading) => loading.is_done(),
+						_ => game::GAME_ASSETS
+							.iter()
+							.all(|asset| state.is_asset_cached(*asset)),
+					};
+					if state.options.preload_assets && !loaded
+					{
+						cur_screen = Screen::Loading(loading::Loading::new(resume, &mut state)?);
+					}
+					else
+					{
+						cur_screen = Screen::Game(game::Game::new(resume, &mut state)?);
+					}
 				}
 				game_state::NextScreen::Menu =>
 				{
 					cur_screen = Screen::Menu(menu::Menu::new(&mut state)?);
 				}
-				game_state::NextScreen::Quit =>
-				{
-					quit = true;
-				}
 				_ => panic!("Unknown next screen {:?}", next_screen),
 			}
+			state.start_fade_in();
+		}
+	}
+	// Every way of quitting ends up here. The position is read now, so a window that was only moved
+	// is remembered too.
+	if state.options.remember_window
+	{
+		remember_window(&display, &mut state);
+		if let Err(e) = game_state::save_options(&state.core, &state.options)
+		{
+			println!("Couldn't save options on quit: {}", e);
 		}
 	}
 	state.sfx.fade_out(&state.core);
@@ -68,7 +68,16 @@ impl Menu
 			{
 				match action
 				{
-					ui::Action::Start => return Ok(Some(game_state::NextScreen::Game(false))),
+					ui::Action::Start =>
+					{
+						state.daily_run = false;
+						return Ok(Some(game_state::NextScreen::Game(false)));
+					}
+					ui::Action::StartDaily =>
+					{
+						state.daily_run = true;
+						return Ok(Some(game_state::NextScreen::Game(false)));
+					}
 					ui::Action::Resume => return Ok(Some(game_state::NextScreen::Game(true))),
 					ui::Action::Quit => return Ok(Some(game_state::NextScreen::Quit)),
 					_ => (),
@@ -119,6 +128,7 @@ impl Menu
 			0.06,
 			ratio,
 			3.,
+			Color::from_rgb_f(1., 0.2, 0.2),
 			state,
 		);
 		let sprite = state.get_sprite("data/logo.cfg").unwrap();
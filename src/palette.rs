@@ -1,4 +1,4 @@
-use crate::error::Result;
+use crate::error::{Error, ErrorKind, Result};
 use crate::utils;
 use allegro::*;
 use allegro_sys::*;
@@ -23,6 +23,35 @@ impl PaletteList
 	}
 
 	pub fn add_palette(&mut self, core: &Core, filename: &str) -> Result<()>
+	{
+		if let Some(&row) = self.palette_registry.get(filename)
+		{
+			return self.copy_palette(core, filename, row);
+		}
+		self.copy_palette(core, filename, self.num_palettes)?;
+		self.palette_registry
+			.insert(filename.to_string(), self.num_palettes);
+		self.num_palettes += 1;
+
+		Ok(())
+	}
+
+	// Re-reads every registered palette into its existing row, so indices stay valid.
+	pub fn reload_all(&mut self, core: &Core) -> Result<()>
+	{
+		let entries: Vec<(String, i32)> = self
+			.palette_registry
+			.iter()
+			.map(|(k, v)| (k.clone(), *v))
+			.collect();
+		for (filename, row) in entries
+		{
+			self.copy_palette(core, &filename, row)?;
+		}
+		Ok(())
+	}
+
+	fn copy_palette(&mut self, core: &Core, filename: &str, row: i32) -> Result<()>
 	{
 		let old_flags = core.get_new_bitmap_flags();
 		core.set_new_bitmap_flags(MEMORY_BITMAP);
@@ -50,7 +79,7 @@ impl PaletteList
 			for x in 0..palette_bitmap.get_width()
 			{
 				let color = palette_bitmap.get_pixel(x, y);
-				core.put_pixel(target_x, self.num_palettes, color);
+				core.put_pixel(target_x, row, color);
 				target_x += 1;
 			}
 		}
@@ -60,10 +89,6 @@ impl PaletteList
 			al_unlock_bitmap(self.palette_bitmap.get_allegro_bitmap());
 		}
 
-		self.palette_registry
-			.insert(filename.to_string(), self.num_palettes);
-		self.num_palettes += 1;
-
 		Ok(())
 	}
 
@@ -72,6 +97,12 @@ impl PaletteList
 		self.palette_registry
 			.get(filename)
 			.map(|&v| v)
-			.ok_or(format!("Couldn't find palette {}", filename).into())
+			.ok_or_else(|| {
+				Error::with_kind(
+					ErrorKind::Asset,
+					format!("Couldn't find palette {}", filename),
+					None,
+				)
+			})
 	}
 }
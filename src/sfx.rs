@@ -1,6 +1,7 @@
-use crate::error::Result;
+use crate::error::{Error, ErrorKind, Result};
 use crate::utils;
 use nalgebra::{Point2, Vector2};
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
@@ -12,6 +13,17 @@ use rand::prelude::*;
 
 const MAX_INSTANCES: usize = 10;
 const FADEOUT_TIME: f64 = 0.1;
+// Positional sounds are budgeted per tick, loudest first.
+const MAX_PLAYS_PER_TICK: usize = 8;
+const MAX_SAME_PLAYS_PER_TICK: usize = 2;
+
+struct PendingSound
+{
+	name: String,
+	volume: f32,
+	pan: f32,
+	pitch: f32,
+}
 
 pub struct Sfx
 {
@@ -28,6 +40,7 @@ pub struct Sfx
 	exclusive_instance: Option<SampleInstance>,
 	sfx_volume: f32,
 	music_volume: f32,
+	pending_sounds: Vec<PendingSound>,
 
 	samples: HashMap<String, Sample>,
 }
@@ -38,7 +51,13 @@ impl Sfx
 	{
 		let audio = AudioAddon::init(&core)?;
 		let acodec = AcodecAddon::init(&audio)?;
-		let sink = Sink::new(&audio).map_err(|_| "Couldn't create audio sink".to_string())?;
+		let sink = Sink::new(&audio).map_err(|_| {
+			Error::with_kind(
+				ErrorKind::Audio,
+				"Couldn't create audio sink".to_string(),
+				None,
+			)
+		})?;
 
 		let mut sfx = Sfx {
 			sfx_volume: 0.,
@@ -55,6 +74,7 @@ impl Sfx
 			time_to_next_music: 0.,
 			music_fade_factor: 1.0,
 			next_music: None,
+			pending_sounds: vec![],
 		};
 		sfx.set_sfx_volume(sfx_volume);
 		sfx.set_music_volume(music_volume);
@@ -82,6 +102,7 @@ impl Sfx
 		{
 			instances.retain(|s| s.get_playing().unwrap());
 		}
+		self.flush_pending_sounds()?;
 		if self.next_music.is_some()
 		{
 			if core.get_time() > self.time_to_next_music
@@ -126,7 +147,9 @@ impl Sfx
 						thread_rng().gen_range(0.9..1.1),
 						Playmode::Once,
 					)
-					.map_err(|_| "Couldn't play sound".to_string())?;
+					.map_err(|_| {
+						Error::with_kind(ErrorKind::Audio, "Couldn't play sound".to_string(), None)
+					})?;
 				self.exclusive_instance = Some(instance);
 			}
 		}
@@ -154,6 +177,55 @@ impl Sfx
 		}
 	}
 
+	// Plays the sounds queued up this tick. Quiet duplicates and anything past the budget are
+	// dropped, so swarms don't exhaust the sample instances.
+	fn flush_pending_sounds(&mut self) -> Result<()>
+	{
+		let mut pending_sounds = std::mem::take(&mut self.pending_sounds);
+		pending_sounds.sort_by(|a, b| b.volume.partial_cmp(&a.volume).unwrap_or(Ordering::Equal));
+		let mut counts = HashMap::new();
+		let mut num_played = 0;
+		for sound in pending_sounds
+		{
+			if num_played >= MAX_PLAYS_PER_TICK
+			{
+				break;
+			}
+			let count = counts.entry(sound.name.clone()).or_insert(0);
+			if *count >= MAX_SAME_PLAYS_PER_TICK
+			{
+				continue;
+			}
+			let num_active = self
+				.sample_instances
+				.get(&sound.name)
+				.map(|instances| instances.len())
+				.unwrap_or(0);
+			if num_active >= MAX_INSTANCES
+			{
+				continue;
+			}
+			*count += 1;
+			num_played += 1;
+
+			let sample = self.samples.get(&sound.name).unwrap();
+			let instance = self
+				.sink
+				.play_sample(
+					sample,
+					sound.volume,
+					Some(sound.pan),
+					sound.pitch,
+					Playmode::Once,
+				)
+				.map_err(|_| {
+					Error::with_kind(ErrorKind::Audio, "Couldn't play sound".to_string(), None)
+				})?;
+			self.add_sample_instance(&sound.name, instance);
+		}
+		Ok(())
+	}
+
 	pub fn play_sound_with_pitch(&mut self, name: &str, pitch: f32) -> Result<()>
 	{
 		self.cache_sample(name)?;
@@ -167,7 +239,9 @@ impl Sfx
 				thread_rng().gen_range(0.9..1.1) * pitch,
 				Playmode::Once,
 			)
-			.map_err(|_| "Couldn't play sound".to_string())?;
+			.map_err(|_| {
+				Error::with_kind(ErrorKind::Audio, "Couldn't play sound".to_string(), None)
+			})?;
 		self.add_sample_instance(name, instance);
 		Ok(())
 	}
@@ -185,7 +259,9 @@ impl Sfx
 				thread_rng().gen_range(0.9..1.1),
 				Playmode::Once,
 			)
-			.map_err(|_| "Couldn't play sound".to_string())?;
+			.map_err(|_| {
+				Error::with_kind(ErrorKind::Audio, "Couldn't play sound".to_string(), None)
+			})?;
 		self.add_sample_instance(name, instance);
 		Ok(())
 	}
@@ -197,7 +273,9 @@ impl Sfx
 		let instance = self
 			.sink
 			.play_sample(sample, self.sfx_volume * volume, None, 1., Playmode::Loop)
-			.map_err(|_| "Couldn't play sound".to_string())?;
+			.map_err(|_| {
+				Error::with_kind(ErrorKind::Audio, "Couldn't play sound".to_string(), None)
+			})?;
 		Ok(instance)
 	}
 
@@ -205,9 +283,15 @@ impl Sfx
 		&mut self, name: &str, sound_pos: Point2<f32>, camera_pos: Point2<f32>, volume: f32,
 	) -> Result<()>
 	{
-		self.cache_sample(name)?;
+		self.play_positional_sound_with_pitch(name, sound_pos, camera_pos, volume, 1.)
+	}
 
-		let sample = self.samples.get(name).unwrap();
+	pub fn play_positional_sound_with_pitch(
+		&mut self, name: &str, sound_pos: Point2<f32>, camera_pos: Point2<f32>, volume: f32,
+		pitch: f32,
+	) -> Result<()>
+	{
+		self.cache_sample(name)?;
 
 		let dist_sq = (sound_pos - camera_pos).norm_squared();
 		let base_dist = 100.;
@@ -221,17 +305,15 @@ impl Sfx
 		let diff = sound_pos - camera_pos;
 		let pan = diff.x / (diff.x.powf(2.) + 32.0_f32.powf(2.)).sqrt();
 
-		let instance = self
-			.sink
-			.play_sample(
-				sample,
-				volume,
-				Some(pan),
-				thread_rng().gen_range(0.9..1.1),
-				Playmode::Once,
-			)
-			.map_err(|_| "Couldn't play sound".to_string())?;
-		self.add_sample_instance(name, instance);
+		if volume > 0.
+		{
+			self.pending_sounds.push(PendingSound {
+				name: name.to_string(),
+				volume: volume,
+				pan: pan,
+				pitch: thread_rng().gen_range(0.9..1.1) * pitch,
+			});
+		}
 		Ok(())
 	}
 
@@ -249,8 +331,13 @@ impl Sfx
 
 	fn start_music(&mut self) -> Result<()>
 	{
-		let mut new_stream = AudioStream::load(&self.audio, &self.music.0)
-			.map_err(|_| format!("Couldn't load {}", self.music.0))?;
+		let mut new_stream = AudioStream::load(&self.audio, &self.music.0).map_err(|_| {
+			Error::with_kind(
+				ErrorKind::Audio,
+				format!("Couldn't load {}", self.music.0),
+				None,
+			)
+		})?;
 		new_stream.attach(&mut self.sink).unwrap();
 		new_stream.set_playmode(Playmode::Loop).unwrap();
 		new_stream
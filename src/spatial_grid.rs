@@ -83,6 +83,9 @@ impl<T> SpatialGrid<T>
 {
 	pub fn new(width: usize, height: usize, cell_width: f32, cell_height: f32) -> Self
 	{
+		// Always keep at least one cell, so points always have somewhere to go.
+		let width = utils::max(1, width);
+		let height = utils::max(1, height);
 		Self {
 			entries: vec![],
 			cells: vec![vec![]; width * height],
@@ -296,3 +299,17 @@ fn test_rect_segment()
 
 	assert!(rect.intersects_with_segment(start, end));
 }
+
+#[test]
+fn test_empty_grid()
+{
+	let mut grid = SpatialGrid::new(0, 0, 32., 32.);
+	grid.push(entry(Point2::new(-10., -10.), Point2::new(100., 100.), ()));
+	grid.push(entry(Point2::new(0., 0.), Point2::new(10., 10.), ()));
+	assert_eq!(grid.all_pairs(|_, _| true).len(), 1);
+	assert_eq!(
+		grid.query_rect(Point2::new(5., 5.), Point2::new(6., 6.), |_| true)
+			.len(),
+		2
+	);
+}
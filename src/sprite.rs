@@ -1,4 +1,4 @@
-use crate::error::Result;
+use crate::error::{Error, ErrorKind, Result};
 use crate::{atlas, game_state, palette, utils};
 use allegro::*;
 use na::{Point2, Vector2};
@@ -14,6 +14,51 @@ struct AnimationDesc
 	frame_ms: Vec<f64>,
 	#[serde(default)]
 	active_frame: i32,
+	#[serde(default)]
+	events: HashMap<String, Vec<i32>>,
+}
+
+// Frame lists are 1-based indices into the sprite sheet, active frames 0-based indices into the
+// frame list.
+fn validate_animations(filename: &str, desc: &SpriteDesc, num_frames: usize) -> Result<()>
+{
+	let mut names: Vec<_> = desc.animations.keys().collect();
+	names.sort();
+	for name in names
+	{
+		let animation_desc = &desc.animations[name];
+		for &frame in &animation_desc.frames
+		{
+			if frame < 1 || frame as usize > num_frames
+			{
+				return Err(Error::with_kind(
+					ErrorKind::Asset,
+					format!(
+						"'{}' animation '{}' references frame {}, but there are only {} frames",
+						filename, name, frame, num_frames
+					),
+					None,
+				));
+			}
+		}
+		if !animation_desc.frames.is_empty()
+			&& (animation_desc.active_frame < 0
+				|| animation_desc.active_frame as usize >= animation_desc.frames.len())
+		{
+			return Err(Error::with_kind(
+				ErrorKind::Asset,
+				format!(
+					"'{}' animation '{}' has active frame {}, but only {} frames",
+					filename,
+					name,
+					animation_desc.active_frame,
+					animation_desc.frames.len()
+				),
+				None,
+			));
+		}
+	}
+	Ok(())
 }
 
 fn default_false() -> bool
@@ -49,10 +94,49 @@ pub struct Sprite
 {
 	desc: SpriteDesc,
 	animations: HashMap<String, Animation>,
+	is_placeholder: bool,
 }
 
 impl Sprite
 {
+	pub fn new_placeholder(
+		core: &Core, atlas: &mut atlas::Atlas, bitmap: &Bitmap, palette: &str,
+	) -> Result<Self>
+	{
+		let frame = atlas.insert(core, bitmap)?;
+		let mut animation_descs = HashMap::new();
+		animation_descs.insert(
+			"Default".to_string(),
+			AnimationDesc {
+				frames: vec![1],
+				frame_ms: vec![100.],
+				active_frame: 0,
+				events: HashMap::new(),
+			},
+		);
+		let mut animations = HashMap::new();
+		animations.insert(
+			"Default".to_string(),
+			Animation {
+				frames: vec![frame],
+				duration_ms: 100.,
+			},
+		);
+		Ok(Sprite {
+			desc: SpriteDesc {
+				bitmap: "".to_string(),
+				width: bitmap.get_width(),
+				height: bitmap.get_height(),
+				center_offt_x: 0.,
+				center_offt_y: 0.,
+				animations: animation_descs,
+				palettes: vec![palette.to_string()],
+			},
+			animations: animations,
+			is_placeholder: true,
+		})
+	}
+
 	pub fn load(
 		filename: &str, core: &Core, atlas: &mut atlas::Atlas, palettes: &mut palette::PaletteList,
 	) -> Result<Self>
@@ -114,10 +198,13 @@ impl Sprite
 					frames: (0..frames.len()).map(|i| i as i32 + 1).collect(),
 					frame_ms: vec![],
 					active_frame: 0,
+					events: HashMap::new(),
 				},
 			);
 		}
 
+		validate_animations(filename, &desc, frames.len())?;
+
 		let mut animations = HashMap::new();
 		for (name, animation_desc) in &mut desc.animations
 		{
@@ -145,9 +232,50 @@ impl Sprite
 		Ok(Sprite {
 			desc: desc,
 			animations: animations,
+			is_placeholder: false,
 		})
 	}
 
+	fn get_animation(&self, animation_name: &str) -> &Animation
+	{
+		if self.is_placeholder
+		{
+			&self.animations["Default"]
+		}
+		else
+		{
+			&self.animations[animation_name]
+		}
+	}
+
+	pub fn has_animation(&self, animation_name: &str) -> bool
+	{
+		self.animations.contains_key(animation_name)
+	}
+
+	pub fn get_animation_names(&self) -> Vec<String>
+	{
+		let mut names: Vec<_> = self.animations.keys().cloned().collect();
+		names.sort();
+		names
+	}
+
+	pub fn get_num_frames(&self, animation_name: &str) -> usize
+	{
+		self.animations
+			.get(animation_name)
+			.map(|animation| animation.frames.len())
+			.unwrap_or(0)
+	}
+
+	pub fn get_duration_ms(&self, animation_name: &str) -> f64
+	{
+		self.animations
+			.get(animation_name)
+			.map(|animation| animation.duration_ms)
+			.unwrap_or(0.)
+	}
+
 	pub fn get_palettes(&self) -> &[String]
 	{
 		&self.desc.palettes
@@ -172,8 +300,8 @@ impl Sprite
 	{
 		let w = self.desc.width as f32;
 		let h = self.desc.height as f32;
-		let animation = &self.animations[animation_name];
-		let atlas_bmp = &animation.frames[frame_idx as usize];
+		let animation = self.get_animation(animation_name);
+		let atlas_bmp = &animation.frames[frame_idx as usize % animation.frames.len()];
 
 		state.core.draw_bitmap_region(
 			&state.atlas.pages[atlas_bmp.page].bitmap,
@@ -200,8 +328,8 @@ impl Sprite
 	{
 		let w = self.desc.width as f32;
 		let h = self.desc.height as f32;
-		let animation = &self.animations[animation_name];
-		let atlas_bmp = &animation.frames[frame_idx as usize];
+		let animation = self.get_animation(animation_name);
+		let atlas_bmp = &animation.frames[frame_idx as usize % animation.frames.len()];
 
 		(
 			*atlas_bmp,
@@ -216,19 +344,25 @@ impl Sprite
 	{
 		state.num_activations = 0;
 		state.num_loops = 0;
+		state.events.clear();
 		if state.animation_name != state.new_animation_name
 		{
 			state.animation_name = state.new_animation_name.clone();
 			state.frame_idx = 0;
 		}
+		let animation_name = if self.is_placeholder
+		{
+			"Default"
+		}
+		else
+		{
+			state.animation_name.as_str()
+		};
 		let animation_desc = &self
 			.desc
 			.animations
-			.get(&state.animation_name)
-			.expect(&format!(
-				"Could not find animation '{}'",
-				state.animation_name
-			));
+			.get(animation_name)
+			.expect(&format!("Could not find animation '{}'", animation_name));
 		state.frame_progress += amount * 1000.;
 		while state.frame_progress > animation_desc.frame_ms[state.frame_idx as usize]
 		{
@@ -238,6 +372,13 @@ impl Sprite
 			{
 				state.num_activations += 1;
 			}
+			for (event, frames) in &animation_desc.events
+			{
+				if frames.contains(&state.frame_idx)
+				{
+					state.events.push(event.clone());
+				}
+			}
 			if state.frame_idx == animation_desc.frames.len() as i32 - 1
 			{
 				state.num_loops += 1;
@@ -255,6 +396,8 @@ pub struct AnimationState
 	pub frame_idx: i32,
 	pub num_activations: i32,
 	num_loops: i32,
+	#[serde(skip)]
+	events: Vec<String>,
 }
 
 impl AnimationState
@@ -268,6 +411,7 @@ impl AnimationState
 			frame_idx: 0,
 			num_activations: 0,
 			num_loops: 0,
+			events: vec![],
 		}
 	}
 
@@ -285,4 +429,30 @@ impl AnimationState
 	{
 		self.num_loops
 	}
+
+	pub fn drain_events(&mut self) -> Vec<String>
+	{
+		std::mem::take(&mut self.events)
+	}
+}
+
+#[test]
+fn bad_frame_test()
+{
+	let desc: SpriteDesc = utils::load_config("data/test_bad_frame.cfg").unwrap();
+	assert!(validate_animations("data/test_bad_frame.cfg", &desc, 4).is_ok());
+
+	let err = validate_animations("data/test_bad_frame.cfg", &desc, 2)
+		.unwrap_err()
+		.to_string();
+	assert!(err.contains("data/test_bad_frame.cfg"));
+	assert!(err.contains("'Default'"));
+	assert!(err.contains("frame 3"));
+
+	let mut desc = desc;
+	desc.animations.get_mut("Default").unwrap().active_frame = 3;
+	let err = validate_animations("data/test_bad_frame.cfg", &desc, 4)
+		.unwrap_err()
+		.to_string();
+	assert!(err.contains("active frame 3"));
 }
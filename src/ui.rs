@@ -22,18 +22,57 @@ pub enum Action
 	SelectMe,
 	MainMenu,
 	Start,
+	StartDaily,
 	Resume,
 	Quit,
 	Back,
 	Forward(fn(&mut game_state::GameState) -> Result<SubScreen>),
 	ToggleFullscreen,
+	ToggleRememberWindow,
 	ToggleFracScale,
 	ChangeInput(controls::Action, usize),
 	MouseSensitivity(f32),
+	StickDeadzone(f32),
+	StickResponse(f32),
+	StickSmoothing(f32),
 	UiScale(f32),
 	MusicVolume(f32),
 	SfxVolume(f32),
+	BladeSoundInterval(f32),
 	RayCastingSteps(i32),
+	GameSpeed(f32),
+	CameraSpeed(i32),
+	AttackBuffer(f32),
+	RegenDelay(f32),
+	MaxDroppedItems(i32),
+	ToggleAutoRecast,
+	ToggleBladeWallClip,
+	ToggleCorpseCollision,
+	ToggleEnemyTellSound,
+	ToggleGhostClear,
+	ToggleCrystalEnemyCount,
+	ToggleBossSlowmo,
+	ToggleProjectilePreviews,
+	SelectAutoFace(usize),
+	SelectComparisonColors(usize),
+	SelectSecondChanceRecharge(usize),
+	ToggleIgnoreRarity(components::Rarity),
+	ToggleDeferItemSwaps,
+	TogglePixelSnap,
+	ToggleSoftFall,
+	ToggleItemDespawn,
+	ToggleShowHints,
+	ToggleShowEventLog,
+	ToggleShowResistances,
+	ToggleTransformMaps,
+	ToggleRandomCrystalKinds,
+	ResetHints,
+	ExportOptions,
+	ImportOptions,
+	ToggleChallenge(components::Challenge),
+	SelectArchetype(usize),
+	SelectHudLayout(usize),
+	SelectNumberFormat(usize),
 }
 
 #[derive(Clone)]
@@ -579,6 +618,9 @@ struct WidgetList
 	widgets: Vec<Vec<Widget>>,
 	cur_selection: (usize, usize),
 	pos: Point2<f32>,
+	// Lists taller than the screen scroll, keeping the selection in view.
+	scroll: f32,
+	max_scroll: f32,
 }
 
 impl WidgetList
@@ -610,6 +652,8 @@ impl WidgetList
 			pos: Point2::new(0., 0.),
 			widgets: new_widgets,
 			cur_selection: cur_selection.expect("No selectable widgets?"),
+			scroll: 0.,
+			max_scroll: 0.,
 		}
 	}
 
@@ -626,6 +670,14 @@ impl WidgetList
 
 	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
 	{
+		if let Event::MouseAxes { dz, .. } = *event
+		{
+			if dz != 0 && self.max_scroll > 0.
+			{
+				let s = state.options.ui_scale;
+				self.scroll_by(-dz as f32 * s * 2. * VERT_SPACE);
+			}
+		}
 		let mut action = None;
 		let old_selection = self.cur_selection;
 		'got_action: for (i, row) in self.widgets.iter_mut().enumerate()
@@ -729,9 +781,50 @@ impl WidgetList
 		}
 		self.widgets[old_selection.0][old_selection.1].set_selected(false);
 		self.widgets[self.cur_selection.0][self.cur_selection.1].set_selected(true);
+		if self.cur_selection != old_selection
+		{
+			self.scroll_to_selection(state);
+		}
 		action
 	}
 
+	fn scroll_by(&mut self, amount: f32)
+	{
+		let new_scroll = utils::clamp(self.scroll + amount, 0., self.max_scroll);
+		let dy = self.scroll - new_scroll;
+		self.scroll = new_scroll;
+		for row in self.widgets.iter_mut()
+		{
+			for w in row.iter_mut()
+			{
+				let mut loc = w.loc();
+				loc.y += dy;
+				w.set_loc(loc);
+			}
+		}
+	}
+
+	fn scroll_to_selection(&mut self, state: &game_state::GameState)
+	{
+		if self.max_scroll <= 0.
+		{
+			return;
+		}
+		let s = state.options.ui_scale;
+		let margin = s * 2. * VERT_SPACE;
+		let w = &self.widgets[self.cur_selection.0][self.cur_selection.1];
+		let top = w.loc().y - s * w.height() / 2.;
+		let bottom = w.loc().y + s * w.height() / 2.;
+		if top < margin
+		{
+			self.scroll_by(top - margin);
+		}
+		else if bottom > state.buffer_height() - margin
+		{
+			self.scroll_by(bottom - (state.buffer_height() - margin));
+		}
+	}
+
 	fn resize(&mut self, state: &game_state::GameState)
 	{
 		let s = state.options.ui_scale;
@@ -742,6 +835,7 @@ impl WidgetList
 
 		let mut y = 0.;
 		let mut cur_selection = None;
+		let mut last_height = 0.;
 		let num_rows = self.widgets.len();
 		for (i, row) in self.widgets.iter_mut().enumerate()
 		{
@@ -788,18 +882,44 @@ impl WidgetList
 			{
 				y += (h_space + max_height) / 2.;
 			}
+			last_height = max_height;
 		}
 
-		// Shift the y's
+		// Shift the y's, lists that don't fit start at the top and scroll.
+		let margin = s * 2. * VERT_SPACE;
+		let first_height = self
+			.widgets
+			.first()
+			.map(|row| {
+				row.iter()
+					.map(|w| s * w.height())
+					.fold(0., |a: f32, b| utils::max(a, b))
+			})
+			.unwrap_or(0.);
+		let total_height = y + (first_height + last_height) / 2.;
+		let view_height = state.buffer_height() - 2. * margin;
+		let shift = if total_height > view_height
+		{
+			self.max_scroll = total_height - view_height;
+			self.scroll = utils::clamp(self.scroll, 0., self.max_scroll);
+			margin + first_height / 2. - self.scroll
+		}
+		else
+		{
+			self.max_scroll = 0.;
+			self.scroll = 0.;
+			cy - y / 2.
+		};
 		for row in self.widgets.iter_mut()
 		{
 			for w in row.iter_mut()
 			{
 				let mut loc = w.loc();
-				loc.y += cy - y / 2.;
+				loc.y += shift;
 				w.set_loc(loc);
 			}
 		}
+		self.scroll_to_selection(state);
 	}
 }
 
@@ -902,6 +1022,7 @@ impl MainMenu
 pub struct Story
 {
 	widgets: WidgetList,
+	description_row: usize,
 }
 
 impl Story
@@ -934,12 +1055,74 @@ impl Story
 				FontAlign::Left,
 			))]);
 		}
+		let archetype_idx = components::ARCHETYPES
+			.iter()
+			.position(|a| *a == state.archetype)
+			.unwrap_or(0);
+		widgets.push(vec![
+			Widget::Label(Label::new(w, h, "Archetype")),
+			Widget::Toggle(Toggle::new(
+				w,
+				h,
+				archetype_idx,
+				components::ARCHETYPES
+					.iter()
+					.map(|a| a.to_str().into())
+					.collect(),
+				|i| Action::SelectArchetype(i),
+			)),
+		]);
+		let description_row = widgets.len();
+		widgets.push(vec![Widget::Label(Label::new(
+			state.buffer_width() - 2. * HORIZ_SPACE,
+			h,
+			state.archetype.description(),
+		))]);
+		for challenge in components::CHALLENGES
+		{
+			let action_fn: fn(usize) -> Action = match challenge
+			{
+				components::Challenge::NoHealing =>
+				{
+					|_| Action::ToggleChallenge(components::Challenge::NoHealing)
+				}
+				components::Challenge::FastEnemies =>
+				{
+					|_| Action::ToggleChallenge(components::Challenge::FastEnemies)
+				}
+				components::Challenge::HalfLife =>
+				{
+					|_| Action::ToggleChallenge(components::Challenge::HalfLife)
+				}
+				components::Challenge::Enrage =>
+				{
+					|_| Action::ToggleChallenge(components::Challenge::Enrage)
+				}
+			};
+			let label = match game::challenge_badge(challenge, state)
+			{
+				Some(level) => format!("{} ({}m)", challenge.to_str(), level * 50),
+				None => challenge.to_str().to_string(),
+			};
+			widgets.push(vec![
+				Widget::Label(Label::new(w, h, &label)),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.challenges.contains(&challenge) as usize,
+					vec!["No".into(), "Yes".into()],
+					action_fn,
+				)),
+			]);
+		}
 		widgets.push(vec![
 			Widget::Button(Button::new(w, h, "Burn it all", Action::Start)),
+			Widget::Button(Button::new(w, h, "Daily Run", Action::StartDaily)),
 			Widget::Button(Button::new(w, h, "Reconsider", Action::Back)),
 		]);
 		let mut res = Self {
 			widgets: WidgetList::new(&widgets.iter().map(|r| &r[..]).collect::<Vec<_>>()),
+			description_row: description_row,
 		};
 		res.resize(state);
 		res
@@ -952,7 +1135,29 @@ impl Story
 
 	pub fn input(&mut self, state: &mut game_state::GameState, event: &Event) -> Option<Action>
 	{
-		self.widgets.input(state, event)
+		let action = self.widgets.input(state, event);
+		if let Some(Action::SelectArchetype(i)) = action
+		{
+			state.archetype = components::ARCHETYPES[i];
+			if let Widget::Label(label) = &mut self.widgets.widgets[self.description_row][0]
+			{
+				label.text = state.archetype.description().into();
+			}
+			return None;
+		}
+		if let Some(Action::ToggleChallenge(challenge)) = action
+		{
+			if state.challenges.contains(&challenge)
+			{
+				state.challenges.retain(|c| *c != challenge);
+			}
+			else
+			{
+				state.challenges.push(challenge);
+			}
+			return None;
+		}
+		action
 	}
 
 	pub fn resize(&mut self, state: &game_state::GameState)
@@ -980,20 +1185,54 @@ impl ControlsMenu
 		let h = BUTTON_HEIGHT;
 
 		let mut widgets = vec![];
-		// widgets.push(vec![
-		// 	Widget::Label(Label::new(0., 0., w * 1.5, h, "MOUSE SENSITIVITY")),
-		// 	Widget::Slider(Slider::new(
-		// 		0.,
-		// 		0.,
-		// 		w,
-		// 		h,
-		// 		state.controls.get_mouse_sensitivity(),
-		// 		0.,
-		// 		2.,
-		// 		false,
-		// 		|i| Action::MouseSensitivity(i),
-		// 	)),
-		// ]);
+		widgets.push(vec![
+			Widget::Label(Label::new(w, h, "Mouse Sensitivity")),
+			Widget::Slider(Slider::new(
+				w,
+				h,
+				state.controls.get_mouse_sensitivity(),
+				0.,
+				2.,
+				0.05,
+				|i| Action::MouseSensitivity(i),
+			)),
+		]);
+		widgets.push(vec![
+			Widget::Label(Label::new(w, h, "Stick Deadzone")),
+			Widget::Slider(Slider::new(
+				w,
+				h,
+				state.controls.get_stick_deadzone(),
+				0.,
+				0.9,
+				0.05,
+				|i| Action::StickDeadzone(i),
+			)),
+		]);
+		widgets.push(vec![
+			Widget::Label(Label::new(w, h, "Stick Response")),
+			Widget::Slider(Slider::new(
+				w,
+				h,
+				state.controls.get_stick_response(),
+				0.5,
+				3.,
+				0.1,
+				|i| Action::StickResponse(i),
+			)),
+		]);
+		widgets.push(vec![
+			Widget::Label(Label::new(w, h, "Stick Smoothing")),
+			Widget::Slider(Slider::new(
+				w,
+				h,
+				state.controls.get_stick_smoothing(),
+				0.,
+				0.5,
+				0.05,
+				|i| Action::StickSmoothing(i),
+			)),
+		]);
 
 		for (&action, &inputs) in state.controls.get_actions_to_inputs()
 		{
@@ -1097,6 +1336,21 @@ impl ControlsMenu
 					state.controls.set_mouse_sensitivity(ms);
 					options_changed = true;
 				}
+				Some(Action::StickDeadzone(dz)) =>
+				{
+					state.controls.set_stick_deadzone(dz);
+					options_changed = true;
+				}
+				Some(Action::StickResponse(r)) =>
+				{
+					state.controls.set_stick_response(r);
+					options_changed = true;
+				}
+				Some(Action::StickSmoothing(s)) =>
+				{
+					state.controls.set_stick_smoothing(s);
+					options_changed = true;
+				}
 				Some(Action::Back) =>
 				{
 					game_state::save_options(&state.core, &state.options).unwrap();
@@ -1152,6 +1406,25 @@ impl OptionsMenu
 		let w = BUTTON_WIDTH;
 		let h = BUTTON_HEIGHT;
 
+		// Custom is only offered once there's a hand-edited layout to go back to.
+		let mut hud_layouts: Vec<String> = vec!["Classic".into(), "Minimal".into()];
+		let hud_layout = if state.options.hud_layout == game_state::HudLayout::classic()
+		{
+			0
+		}
+		else if state.options.hud_layout == game_state::HudLayout::minimal()
+		{
+			1
+		}
+		else
+		{
+			2
+		};
+		if hud_layout == 2 || state.options.custom_hud_layout.is_some()
+		{
+			hud_layouts.push("Custom".into());
+		}
+
 		let widgets = [
 			vec![
 				Widget::Label(Label::new(w, h, "Fullscreen")),
@@ -1163,6 +1436,16 @@ impl OptionsMenu
 					|_| Action::ToggleFullscreen,
 				)),
 			],
+			vec![
+				Widget::Label(Label::new(w, h, "Remember Window")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.remember_window as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleRememberWindow,
+				)),
+			],
 			vec![
 				Widget::Label(Label::new(w, h, "Fractional Scale")),
 				Widget::Toggle(Toggle::new(
@@ -1197,6 +1480,18 @@ impl OptionsMenu
 					|i| Action::SfxVolume(i),
 				)),
 			],
+			vec![
+				Widget::Label(Label::new(w, h, "Blade Sound Interval (ms)")),
+				Widget::Slider(Slider::new(
+					w,
+					h,
+					state.options.blade_sound_interval_ms,
+					0.,
+					1000.,
+					50.,
+					|i| Action::BladeSoundInterval(i),
+				)),
+			],
 			//vec![
 			//	Widget::Label(Label::new(w, h, "UI Scale")),
 			//	Widget::Slider(Slider::new(
@@ -1209,18 +1504,173 @@ impl OptionsMenu
 			//		|i| Action::UiScale(i),
 			//	)),
 			//],
-			//vec![
-			//	Widget::Label(Label::new(w, h, "Scroll")),
-			//	Widget::Slider(Slider::new(
-			//		w,
-			//		h,
-			//		state.options.camera_speed as f32,
-			//		1.,
-			//		10.,
-			//		1.,
-			//		|i| Action::CameraSpeed(i as i32),
-			//	)),
-			//],
+			vec![
+				Widget::Label(Label::new(w, h, "Camera Speed")),
+				Widget::Slider(Slider::new(
+					w,
+					h,
+					state.options.camera_speed as f32,
+					1.,
+					10.,
+					1.,
+					|i| Action::CameraSpeed(i as i32),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Game Speed")),
+				Widget::Slider(Slider::new(
+					w,
+					h,
+					state.options.game_speed,
+					0.5,
+					1.,
+					0.05,
+					|i| Action::GameSpeed(i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Attack Buffer (ms)")),
+				Widget::Slider(Slider::new(
+					w,
+					h,
+					state.options.attack_buffer_ms,
+					0.,
+					500.,
+					10.,
+					|i| Action::AttackBuffer(i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Regen Delay")),
+				Widget::Slider(Slider::new(
+					w,
+					h,
+					state.options.regen_delay,
+					0.,
+					10.,
+					0.5,
+					|i| Action::RegenDelay(i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Auto Recast")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.auto_recast as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleAutoRecast,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Blades Avoid Walls")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.blade_wall_clip as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleBladeWallClip,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Corpse Collision")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.corpse_collision as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleCorpseCollision,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Enemy Attack Cues")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.enemy_tell_sound as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleEnemyTellSound,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Ghost Clear Bonus")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.ghost_clear as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleGhostClear,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Crystal Enemy Count")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.crystal_enemy_count as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleCrystalEnemyCount,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Boss Kill Slow Motion")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.boss_slowmo as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleBossSlowmo,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Projectile Previews")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.projectile_previews as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleProjectilePreviews,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Attack Facing")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					match state.options.auto_face
+					{
+						game_state::AutoFace::Classic => 0,
+						game_state::AutoFace::NearestEnemy => 1,
+						game_state::AutoFace::Cursor => 2,
+					},
+					vec!["Classic".into(), "Nearest Enemy".into(), "Cursor".into()],
+					|i| Action::SelectAutoFace(i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Second Chance Recharge")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.second_chance_per_map as usize,
+					vec!["Per Run".into(), "Per Map".into()],
+					|i| Action::SelectSecondChanceRecharge(i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Comparison Colors")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					match state.options.comparison_colors
+					{
+						game_state::ComparisonColors::GreenRed => 0,
+						game_state::ComparisonColors::BlueOrange => 1,
+					},
+					vec!["Green/Red".into(), "Blue/Orange".into()],
+					|i| Action::SelectComparisonColors(i),
+				)),
+			],
 			vec![
 				Widget::Label(Label::new(w, h, "Ray Casting Steps")),
 				Widget::Slider(Slider::new(
@@ -1233,6 +1683,178 @@ impl OptionsMenu
 					|i| Action::RayCastingSteps(i as i32),
 				)),
 			],
+			vec![
+				Widget::Label(Label::new(w, h, "Ignore Magic Items")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state
+						.options
+						.pickup_filter
+						.ignores_rarity(components::Rarity::Magic) as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleIgnoreRarity(components::Rarity::Magic),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Ignore Rare Items")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state
+						.options
+						.pickup_filter
+						.ignores_rarity(components::Rarity::Rare) as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleIgnoreRarity(components::Rarity::Rare),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Ignore Unique Items")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state
+						.options
+						.pickup_filter
+						.ignores_rarity(components::Rarity::Unique) as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleIgnoreRarity(components::Rarity::Unique),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Max Dropped Items")),
+				Widget::Slider(Slider::new(
+					w,
+					h,
+					state.options.max_dropped_items as f32,
+					0.,
+					game_state::MAX_DROPPED_ITEMS_LIMIT as f32,
+					5.,
+					|i| Action::MaxDroppedItems(i as i32),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Defer Swaps In Combat")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.defer_item_swaps as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleDeferItemSwaps,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Pixel Snap")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.pixel_snap as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::TogglePixelSnap,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Soft Fall")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.soft_fall as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleSoftFall,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Item Despawn")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.item_despawn as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleItemDespawn,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "HUD Layout")),
+				Widget::Toggle(Toggle::new(w, h, hud_layout, hud_layouts, |i| {
+					Action::SelectHudLayout(i)
+				})),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Numbers")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					match state.options.number_format
+					{
+						utils::NumberFormat::Plain => 0,
+						utils::NumberFormat::Grouped => 1,
+						utils::NumberFormat::Abbreviated => 2,
+					},
+					vec!["Plain".into(), "Grouped".into(), "Abbreviated".into()],
+					|i| Action::SelectNumberFormat(i),
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Tutorial Hints")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.show_hints as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleShowHints,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Event Log")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.show_event_log as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleShowEventLog,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Enemy Resistances")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.show_resistances as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleShowResistances,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Map Variations")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.transform_maps as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleTransformMaps,
+				)),
+			],
+			vec![
+				Widget::Label(Label::new(w, h, "Random Crystals")),
+				Widget::Toggle(Toggle::new(
+					w,
+					h,
+					state.options.random_crystal_kinds as usize,
+					vec!["No".into(), "Yes".into()],
+					|_| Action::ToggleRandomCrystalKinds,
+				)),
+			],
+			vec![Widget::Button(Button::new(
+				w,
+				h,
+				"Reset Hints",
+				Action::ResetHints,
+			))],
+			vec![
+				Widget::Button(Button::new(w, h, "Export", Action::ExportOptions)),
+				Widget::Button(Button::new(w, h, "Import", Action::ImportOptions)),
+			],
 			vec![Widget::Button(Button::new(w, h, "Back", Action::Back))],
 		];
 
@@ -1259,6 +1881,10 @@ impl OptionsMenu
 				{
 					state.options.fullscreen = !state.options.fullscreen;
 				}
+				Action::ToggleRememberWindow =>
+				{
+					state.options.remember_window = !state.options.remember_window;
+				}
 				Action::ToggleFracScale =>
 				{
 					state.options.frac_scale = !state.options.frac_scale;
@@ -1272,6 +1898,176 @@ impl OptionsMenu
 				{
 					state.options.ray_casting_steps = i;
 				}
+				Action::GameSpeed(v) =>
+				{
+					state.options.game_speed = v;
+				}
+				Action::CameraSpeed(v) =>
+				{
+					state.options.camera_speed = v;
+				}
+				Action::BladeSoundInterval(v) =>
+				{
+					state.options.blade_sound_interval_ms = v;
+				}
+				Action::AttackBuffer(v) =>
+				{
+					state.options.attack_buffer_ms = v;
+				}
+				Action::RegenDelay(v) =>
+				{
+					state.options.regen_delay = v;
+				}
+				Action::MaxDroppedItems(i) =>
+				{
+					state.options.max_dropped_items = i;
+				}
+				Action::ToggleAutoRecast =>
+				{
+					state.options.auto_recast = !state.options.auto_recast;
+				}
+				Action::ToggleBladeWallClip =>
+				{
+					state.options.blade_wall_clip = !state.options.blade_wall_clip;
+				}
+				Action::ToggleCorpseCollision =>
+				{
+					state.options.corpse_collision = !state.options.corpse_collision;
+				}
+				Action::ToggleEnemyTellSound =>
+				{
+					state.options.enemy_tell_sound = !state.options.enemy_tell_sound;
+				}
+				Action::ToggleGhostClear =>
+				{
+					state.options.ghost_clear = !state.options.ghost_clear;
+				}
+				Action::ToggleCrystalEnemyCount =>
+				{
+					state.options.crystal_enemy_count = !state.options.crystal_enemy_count;
+				}
+				Action::ToggleBossSlowmo =>
+				{
+					state.options.boss_slowmo = !state.options.boss_slowmo;
+				}
+				Action::ToggleProjectilePreviews =>
+				{
+					state.options.projectile_previews = !state.options.projectile_previews;
+				}
+				Action::SelectSecondChanceRecharge(i) =>
+				{
+					state.options.second_chance_per_map = i == 1;
+				}
+				Action::SelectComparisonColors(i) =>
+				{
+					state.options.comparison_colors = match i
+					{
+						1 => game_state::ComparisonColors::BlueOrange,
+						_ => game_state::ComparisonColors::GreenRed,
+					};
+				}
+				Action::SelectAutoFace(i) =>
+				{
+					state.options.auto_face = match i
+					{
+						1 => game_state::AutoFace::NearestEnemy,
+						2 => game_state::AutoFace::Cursor,
+						_ => game_state::AutoFace::Classic,
+					};
+				}
+				Action::ToggleIgnoreRarity(rarity) =>
+				{
+					state.options.pickup_filter.toggle_rarity(rarity);
+				}
+				Action::ToggleDeferItemSwaps =>
+				{
+					state.options.defer_item_swaps = !state.options.defer_item_swaps;
+				}
+				Action::TogglePixelSnap =>
+				{
+					state.options.pixel_snap = !state.options.pixel_snap;
+				}
+				Action::ToggleSoftFall =>
+				{
+					state.options.soft_fall = !state.options.soft_fall;
+				}
+				Action::ToggleItemDespawn =>
+				{
+					state.options.item_despawn = !state.options.item_despawn;
+				}
+				Action::SelectNumberFormat(i) =>
+				{
+					state.options.number_format = match i
+					{
+						1 => utils::NumberFormat::Grouped,
+						2 => utils::NumberFormat::Abbreviated,
+						_ => utils::NumberFormat::Plain,
+					};
+				}
+				Action::ToggleShowHints =>
+				{
+					state.options.show_hints = !state.options.show_hints;
+				}
+				Action::ToggleShowEventLog =>
+				{
+					state.options.show_event_log = !state.options.show_event_log;
+				}
+				Action::ToggleShowResistances =>
+				{
+					state.options.show_resistances = !state.options.show_resistances;
+				}
+				Action::ToggleTransformMaps =>
+				{
+					state.options.transform_maps = !state.options.transform_maps;
+				}
+				Action::ToggleRandomCrystalKinds =>
+				{
+					state.options.random_crystal_kinds = !state.options.random_crystal_kinds;
+				}
+				Action::ResetHints =>
+				{
+					state.seen_hints = game_state::SeenHints::default();
+					if let Err(e) = game_state::save_seen_hints(&state.core, &state.seen_hints)
+					{
+						println!("Couldn't reset hints: {}", e);
+					}
+				}
+				Action::SelectHudLayout(i) =>
+				{
+					let layout = state.options.hud_layout;
+					if layout != game_state::HudLayout::classic()
+						&& layout != game_state::HudLayout::minimal()
+					{
+						state.options.custom_hud_layout = Some(layout);
+					}
+					state.options.hud_layout = match i
+					{
+						0 => game_state::HudLayout::classic(),
+						1 => game_state::HudLayout::minimal(),
+						_ => state.options.custom_hud_layout.unwrap_or(layout),
+					};
+				}
+				Action::ExportOptions =>
+				{
+					if let Err(e) = game_state::export_options(&state.core, &state.options)
+					{
+						println!("Couldn't export options: {}", e);
+					}
+				}
+				Action::ImportOptions => match game_state::import_options(&state.core)
+				{
+					Ok(Some(options)) =>
+					{
+						state.sfx.set_music_volume(options.music_volume);
+						state.sfx.set_sfx_volume(options.sfx_volume);
+						state.controls = controls::ControlsHandler::new(options.controls.clone());
+						state.options = options;
+						game_state::save_options(&state.core, &state.options).unwrap();
+						*self = OptionsMenu::new(state);
+					}
+					Ok(None) => println!("No options to import"),
+					Err(e) => println!("Couldn't import options: {}", e),
+				},
 				Action::SfxVolume(v) =>
 				{
 					state.options.sfx_volume = v;
@@ -1,4 +1,4 @@
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 use allegro::*;
 use allegro_audio::*;
 use allegro_color::*;
@@ -8,6 +8,7 @@ use nalgebra;
 use rand::prelude::*;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use slr_config::{from_element, to_element, ConfigElement, Source};
 use std::{fs, path};
 
@@ -163,26 +164,51 @@ pub fn sigmoid(x: f32) -> f32
 
 pub fn read_to_string(path: &str) -> Result<String>
 {
-	fs::read_to_string(path)
-		.map_err(|e| Error::new(format!("Couldn't read '{}'", path), Some(Box::new(e))))
+	fs::read_to_string(path).map_err(|e| {
+		Error::with_kind(
+			ErrorKind::Io,
+			format!("Couldn't read '{}'", path),
+			Some(Box::new(e)),
+		)
+	})
 }
 
 pub fn load_config<T: DeserializeOwned + Clone>(file: &str) -> Result<T>
 {
 	let contents = read_to_string(file)?;
 	let mut source = Source::new(path::Path::new(file), &contents);
-	let element = ConfigElement::from_source(&mut source)
-		.map_err(|e| Error::new(format!("Config parsing error"), Some(Box::new(e))))?;
-	from_element::<T>(&element, Some(&source))
-		.map_err(|e| Error::new(format!("Config parsing error"), Some(Box::new(e))))
+	let element = ConfigElement::from_source(&mut source).map_err(|e| {
+		Error::with_kind(
+			ErrorKind::Config,
+			format!("Config parsing error"),
+			Some(Box::new(e)),
+		)
+	})?;
+	from_element::<T>(&element, Some(&source)).map_err(|e| {
+		Error::with_kind(
+			ErrorKind::Config,
+			format!("Config parsing error"),
+			Some(Box::new(e)),
+		)
+	})
 }
 
 pub fn save_config<T: Serialize>(file: &str, val: T) -> Result<()>
 {
-	let element = to_element(&val)
-		.map_err(|e| Error::new(format!("Config writing error"), Some(Box::new(e))))?;
-	std::fs::write(file, format!("{}", element))
-		.map_err(|e| Error::new(format!("Couldn't write '{}'", file), Some(Box::new(e))))?;
+	let element = to_element(&val).map_err(|e| {
+		Error::with_kind(
+			ErrorKind::Config,
+			format!("Config writing error"),
+			Some(Box::new(e)),
+		)
+	})?;
+	std::fs::write(file, format!("{}", element)).map_err(|e| {
+		Error::with_kind(
+			ErrorKind::Io,
+			format!("Couldn't write '{}'", file),
+			Some(Box::new(e)),
+		)
+	})?;
 	Ok(())
 }
 
@@ -193,7 +219,13 @@ pub fn user_data_path(core: &Core) -> Result<path::PathBuf>
 	{
 		path_buf.push(
 			core.get_standard_path(StandardPath::UserSettings)
-				.map_err(|_| "Couldn't get standard path".to_string())?,
+				.map_err(|_| {
+					Error::with_kind(
+						ErrorKind::Io,
+						"Couldn't get standard path".to_string(),
+						None,
+					)
+				})?,
 		);
 	}
 	Ok(path_buf)
@@ -217,31 +249,41 @@ pub fn load_user_data<T: DeserializeOwned + Clone>(core: &Core, filename: &str)
 pub fn save_user_data<T: Serialize>(core: &Core, filename: &str, options: &T) -> Result<()>
 {
 	let mut path_buf = user_data_path(core)?;
-	std::fs::create_dir_all(&path_buf).map_err(|_| "Couldn't create directory".to_string())?;
+	std::fs::create_dir_all(&path_buf).map_err(|e| {
+		Error::with_kind(
+			ErrorKind::Io,
+			"Couldn't create directory".to_string(),
+			Some(Box::new(e)),
+		)
+	})?;
 	path_buf.push(filename);
 	save_config(path_buf.to_str().unwrap(), &options)
 }
 
 pub fn load_bitmap(core: &Core, file: &str) -> Result<Bitmap>
 {
-	Ok(Bitmap::load(&core, file).map_err(|_| format!("Couldn't load {}", file))?)
+	Ok(Bitmap::load(&core, file)
+		.map_err(|_| Error::with_kind(ErrorKind::Asset, format!("Couldn't load {}", file), None))?)
 }
 
 pub fn load_bitmap_indexed(core: &Core, file: &str) -> Result<Bitmap>
 {
-	Ok(Bitmap::load_indexed(&core, file).map_err(|_| format!("Couldn't load {}", file))?)
+	Ok(Bitmap::load_indexed(&core, file)
+		.map_err(|_| Error::with_kind(ErrorKind::Asset, format!("Couldn't load {}", file), None))?)
 }
 
 pub fn load_sample(audio: &AudioAddon, path: &str) -> Result<Sample>
 {
-	Ok(Sample::load(audio, path).map_err(|_| format!("Couldn't load '{}'", path))?)
+	Ok(Sample::load(audio, path).map_err(|_| {
+		Error::with_kind(ErrorKind::Audio, format!("Couldn't load '{}'", path), None)
+	})?)
 }
 
 pub fn load_ttf_font(ttf: &TtfAddon, file: &str, size: i32) -> Result<Font>
 {
 	Ok(ttf
 		.load_ttf_font(file, size, Flag::zero())
-		.map_err(|_| format!("Couldn't load {}", file))?)
+		.map_err(|_| Error::with_kind(ErrorKind::Asset, format!("Couldn't load {}", file), None))?)
 }
 
 pub fn load_shader(disp: &mut Display, path: &str) -> Result<std::sync::Weak<Shader>>
@@ -269,6 +311,11 @@ pub fn load_shader(disp: &mut Display, path: &str) -> Result<std::sync::Weak<Sha
 	Ok(shader)
 }
 
+pub fn reflect(v: Vector2<f32>, normal: Vector2<f32>) -> Vector2<f32>
+{
+	v - 2. * v.dot(&normal) * normal
+}
+
 pub fn nearest_line_point(v1: Point2<f32>, v2: Point2<f32>, test_point: Point2<f32>)
 	-> Point2<f32>
 {
@@ -488,6 +535,114 @@ pub fn nice_float(f: f32, max_frac_digits: usize) -> String
 	// ret
 }
 
+pub fn format_time(t: f64) -> String
+{
+	let tenths = (max(t, 0.) * 10.).round() as i64;
+	format!("{}:{:02}.{}", tenths / 600, (tenths / 10) % 60, tenths % 10)
+}
+
+// Days since the Unix epoch, in UTC.
+pub fn current_day() -> i64
+{
+	std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map(|d| (d.as_secs() / 86400) as i64)
+		.unwrap_or(0)
+}
+
+// Formats days since the Unix epoch as YYYY-MM-DD.
+pub fn format_day(day: i64) -> String
+{
+	// See http://howardhinnant.github.io/date_algorithms.html
+	let z = day + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = doy - (153 * mp + 2) / 5 + 1;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 };
+	let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+	format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NumberFormat
+{
+	Plain,
+	Grouped,
+	Abbreviated,
+}
+
+impl Default for NumberFormat
+{
+	fn default() -> Self
+	{
+		NumberFormat::Plain
+	}
+}
+
+pub fn format_number(f: f32, format: NumberFormat) -> String
+{
+	let n = f as i64;
+	match format
+	{
+		NumberFormat::Plain => format!("{}", n),
+		NumberFormat::Grouped =>
+		{
+			let digits = n.abs().to_string();
+			let mut res = String::new();
+			for (i, c) in digits.chars().enumerate()
+			{
+				if i > 0 && (digits.len() - i) % 3 == 0
+				{
+					res.push(',');
+				}
+				res.push(c);
+			}
+			if n < 0
+			{
+				format!("-{}", res)
+			}
+			else
+			{
+				res
+			}
+		}
+		NumberFormat::Abbreviated =>
+		{
+			let abs = n.abs() as f32;
+			if abs < 10_000.
+			{
+				format!("{}", n)
+			}
+			else if abs < 1_000_000.
+			{
+				format!("{}K", nice_float(n as f32 / 1e3, 1))
+			}
+			else if abs < 1_000_000_000.
+			{
+				format!("{}M", nice_float(n as f32 / 1e6, 1))
+			}
+			else
+			{
+				format!("{}B", nice_float(n as f32 / 1e9, 1))
+			}
+		}
+	}
+}
+
+// Nearest rank percentile, p is in 0..=100. The values must be sorted.
+pub fn percentile(sorted: &[f64], p: f64) -> f64
+{
+	if sorted.is_empty()
+	{
+		return 0.;
+	}
+	let rank = (p / 100. * sorted.len() as f64).ceil() as usize;
+	sorted[clamp(rank, 1, sorted.len()) - 1]
+}
+
 #[test]
 fn nice_float_test()
 {
@@ -506,6 +661,61 @@ fn nice_float_test()
 	assert_eq!("100.1", nice_float(100.1, 2));
 }
 
+#[test]
+fn format_time_test()
+{
+	assert_eq!("0:00.0", format_time(0.));
+	assert_eq!("0:05.3", format_time(5.25));
+	assert_eq!("1:00.0", format_time(59.99));
+	assert_eq!("12:34.5", format_time(754.5));
+	assert_eq!("0:00.0", format_time(-3.));
+}
+
+#[test]
+fn format_day_test()
+{
+	assert_eq!("1970-01-01", format_day(0));
+	assert_eq!("2000-02-29", format_day(11016));
+	assert_eq!("2024-01-01", format_day(19723));
+	assert_eq!("1969-12-31", format_day(-1));
+}
+
+#[test]
+fn format_number_test()
+{
+	assert_eq!("999", format_number(999.9, NumberFormat::Plain));
+	assert_eq!("1234567", format_number(1234567., NumberFormat::Plain));
+	assert_eq!("999", format_number(999., NumberFormat::Grouped));
+	assert_eq!("1,234", format_number(1234., NumberFormat::Grouped));
+	assert_eq!(
+		"-1,234,567",
+		format_number(-1234567., NumberFormat::Grouped)
+	);
+	assert_eq!("9999", format_number(9999., NumberFormat::Abbreviated));
+	assert_eq!("12.5K", format_number(12500., NumberFormat::Abbreviated));
+	assert_eq!("3M", format_number(3000000., NumberFormat::Abbreviated));
+}
+
+#[test]
+fn percentile_test()
+{
+	let values = [1., 2., 3., 4., 5., 6., 7., 8., 9., 10.];
+	assert_eq!(1., percentile(&values, 0.));
+	assert_eq!(5., percentile(&values, 50.));
+	assert_eq!(10., percentile(&values, 95.));
+	assert_eq!(10., percentile(&values, 100.));
+	assert_eq!(0., percentile(&[], 50.));
+}
+
+#[test]
+fn test_reflect()
+{
+	let v = reflect(Vector2::new(1., -1.), Vector2::new(0., 1.));
+	assert_eq!(Vector2::new(1., 1.), v);
+	let v = reflect(Vector2::new(2., 0.), Vector2::new(-1., 0.));
+	assert_eq!(Vector2::new(-2., 0.), v);
+}
+
 #[test]
 fn test_nearest_line_point()
 {